@@ -1,10 +1,12 @@
 mod install;
 mod preview;
+mod restore;
 mod run;
 mod ssh;
 mod up;
 pub use install::*;
 pub use preview::*;
+pub use restore::*;
 pub use run::*;
 pub use ssh::*;
 pub use up::*;
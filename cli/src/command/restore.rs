@@ -0,0 +1,41 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use colored::Colorize;
+
+use crate::{Cli, command::connect_ssh};
+
+/// Put back a file cook backed up before overwriting it
+#[derive(Parser)]
+pub struct Restore {
+    /// Host the file is on
+    host: String,
+    /// Path of the file on the host, e.g. /etc/caddy/Caddyfile
+    path: PathBuf,
+    /// Restore the newest backup taken at or before this UTC time, e.g.
+    /// 2026-10-19T12:00:00Z or just 2026-10-19. Defaults to the newest backup.
+    #[clap(long)]
+    at: Option<String>,
+}
+
+impl Restore {
+    pub async fn run(&self, _cli: &Cli) {
+        let session = connect_ssh(&self.host).await;
+        match cook::backup::restore(&session, &self.path, self.at.as_deref()).await {
+            Ok(backup) => {
+                let success = "[success]".green();
+                eprintln!(
+                    "{success} {}: restored {} from {}",
+                    self.host,
+                    self.path.display(),
+                    backup.display()
+                );
+            }
+            Err(e) => {
+                let error = "[error]".red();
+                eprintln!("{error} {}: {e}", self.host);
+                std::process::exit(1);
+            }
+        }
+    }
+}
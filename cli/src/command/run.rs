@@ -1,6 +1,6 @@
 use clap::Parser;
 use colored::Colorize;
-use cook::State;
+use cook::{Notification, NotifyQueue, State};
 use openssh::Session;
 use serde::Serialize;
 use std::fmt::Display;
@@ -43,6 +43,9 @@ impl Run {
         }
         let command = self.command.join(" ");
         let mut context = Context::new(&cli.root);
+        context.set_backup(cli.backup);
+        context.set_paranoid(cli.paranoid);
+        context.enable_hash_cache();
         cook::add_kdl_deserializers_to_context(&mut context);
         let state = parse_kdl(&command, context);
 
@@ -133,6 +136,10 @@ enum UnitOutcome {
 /// Units run in dependency order. A `requires` dependency that fails causes its
 /// dependents to be skipped rather than aborting the whole run.
 ///
+/// A unit that applies any modification queues its `notify` services. They
+/// are restarted or reloaded, once each, when a `flush` unit runs and again
+/// at the end for whatever was queued since.
+///
 /// Returns `true` if no unit failed.
 pub async fn run_over_ssh(cli: &Cli, session: Session, state: &State, host: &str) -> bool {
     let session = Arc::new(session);
@@ -141,6 +148,7 @@ pub async fn run_over_ssh(cli: &Cli, session: Session, state: &State, host: &str
         .build_schedule()
         .unwrap_or_else(|e| panic!("invalid sequencing in config: {e}"));
 
+    let mut notifications = NotifyQueue::default();
     let mut outcomes: Vec<Option<UnitOutcome>> = vec![None; units.len()];
     for &u in &schedule.topo_order {
         let mut skip = false;
@@ -153,13 +161,23 @@ pub async fn run_over_ssh(cli: &Cli, session: Session, state: &State, host: &str
             }
         }
 
-        let outcome = if skip {
-            UnitOutcome::Skipped
+        let result = if skip {
+            None
+        } else if units[u].is_flush() {
+            Some(deliver(cli, &session, notifications.take()).await)
         } else {
-            match run_unit_rules(cli, state, session.clone(), units[u].rules.clone()).await {
-                Ok(outputs) => UnitOutcome::Done(Arc::new(outputs)),
-                Err(e) => UnitOutcome::Failed(Arc::from(format!("unit '{}': {e}", units[u].qualified()))),
+            let result = run_unit_rules(cli, state, session.clone(), units[u].rules.clone()).await;
+            if result.as_ref().is_ok_and(|outputs| !outputs.is_empty()) {
+                for notification in &units[u].notify {
+                    notifications.push(notification.clone());
+                }
             }
+            Some(result)
+        };
+        let outcome = match result {
+            None => UnitOutcome::Skipped,
+            Some(Ok(outputs)) => UnitOutcome::Done(Arc::new(outputs)),
+            Some(Err(e)) => UnitOutcome::Failed(Arc::from(format!("unit '{}': {e}", units[u].qualified()))),
         };
         outcomes[u] = Some(outcome);
     }
@@ -194,6 +212,20 @@ pub async fn run_over_ssh(cli: &Cli, session: Session, state: &State, host: &str
         }
     }
 
+    match deliver(cli, &session, notifications.take()).await {
+        Ok(outputs) => {
+            for output in outputs {
+                count += 1;
+                print!("{output}");
+            }
+        }
+        Err(e) => {
+            ok = false;
+            let error = "[error]".red();
+            eprintln!("{error} {host}: {e}");
+        }
+    }
+
     if ok && count == 0 {
         let success = "[success]".green();
         eprintln!("{success} {host}: No modifications to run");
@@ -208,6 +240,26 @@ pub async fn run_over_ssh(cli: &Cli, session: Session, state: &State, host: &str
     ok
 }
 
+/// How a delivered notification is reported, alongside the modifications.
+#[derive(Serialize)]
+struct Notified<'a> {
+    notify: &'a Notification,
+}
+
+/// Restart or reload each notified service, in the order they were queued.
+/// Returns the serialized output of each, or the first error encountered.
+async fn deliver(cli: &Cli, session: &Session, notifications: Vec<Notification>) -> Result<Vec<String>, cook::Error> {
+    let mut outputs = Vec::new();
+    for notification in &notifications {
+        notification
+            .deliver(session)
+            .await
+            .map_err(|e| format!("notify service:{}: {e}", notification.service))?;
+        outputs.push(serialize_structured(cli.format, &Notified { notify: notification }));
+    }
+    Ok(outputs)
+}
+
 /// Run all rules in a unit in order. Each rule checks itself, then applies its
 /// modifications in order. Returns the serialized outputs of every modification
 /// applied, or the first error encountered.
@@ -232,6 +284,9 @@ async fn run_unit_rules(
                 .downcast_ssh()
                 .ok_or_else(|| cook::Error::from("modification cannot be applied over ssh"))?;
             m.apply_ssh(session.clone()).await?;
+            if modification.is_bookkeeping() {
+                continue;
+            }
             let ser: &dyn erased_serde::Serialize = modification.as_ref();
             outputs.push(serialize_structured(cli.format, ser));
         }
@@ -44,6 +44,15 @@ struct Cli {
     /// Specify a specific host to operate on
     #[clap(long, short = 'H', env = "COOK_HOST", global = true)]
     host: Vec<String>,
+    /// Back up files and units before overwriting them, unless a node says
+    /// backup=#false. Backups go under /var/lib/cook/backups on the host.
+    #[clap(long, env = "COOK_BACKUP", global = true, default_value = "false")]
+    backup: bool,
+    /// Hash every file under a cp destination on each check, instead of
+    /// trusting the manifest of the last apply for files whose size and
+    /// mtime are unchanged.
+    #[clap(long, env = "COOK_PARANOID", global = true, default_value = "false")]
+    paranoid: bool,
     #[command(subcommand)]
     command: Command,
 }
@@ -59,21 +68,30 @@ enum Command {
     Run(command::Run),
     Preview(command::Preview),
     Up(command::Up),
+    Restore(command::Restore),
 }
 
 fn main() {
     let mut cli = Cli::parse();
 
+    // Before the Cookfile is read, so what parsing warns about is shown, and
+    // on stderr, so it stays out of `--format json` output.
+    let level = if cli.verbose {
+        LevelFilter::DEBUG
+    } else {
+        LevelFilter::WARN
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .init();
+
     let path = Path::new(&cli.root);
-    let state = build_state(path);
+    let state = build_state(path, cli.backup, cli.paranoid);
     if cli.host.is_empty() {
         cli.host = state.hosts();
     }
 
-    if cli.verbose {
-        tracing_subscriber::fmt().with_max_level(LevelFilter::DEBUG).init();
-    }
-
     match &cli.command {
         Command::Install(install) => {
             tokio::runtime::Runtime::new()
@@ -96,6 +114,11 @@ fn main() {
                 .unwrap()
                 .block_on(async { up.run(&cli, state).await });
         }
+        Command::Restore(restore) => {
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(async { restore.run(&cli).await });
+        }
     }
 }
 
@@ -110,7 +133,7 @@ fn main() {
 //     }
 // }
 
-fn build_state(root: &Path) -> State {
+fn build_state(root: &Path, backup: bool, paranoid: bool) -> State {
     let mut state = State::new();
     for entry in std::fs::read_dir(root).expect("Failed to read directory") {
         let entry = entry.expect("Failed to read directory entry");
@@ -131,12 +154,13 @@ fn build_state(root: &Path) -> State {
         if file_name == "Cookfile" || extension == "kdl" {
             let content = std::fs::read_to_string(&path).expect("Failed to read file");
             let mut cx = Context::new(root);
+            cx.set_backup(backup);
+            cx.set_paranoid(paranoid);
+            cx.enable_hash_cache();
             add_kdl_deserializers_to_context(&mut cx);
             let s = kdl::parse_kdl(&content, cx);
             state.merge(s);
-        } else if file_name == "main.py" {
-        } else if file_name == "main.ts" {
-        } else if file_name == "main.js" {
+        } else if matches!(file_name, "main.py" | "main.ts" | "main.js") {
         } else if file_name == "Cargo.toml" {
             let Ok(content) = std::fs::read_to_string(&path) else {
                 continue;
@@ -7,9 +7,17 @@ use std::{
 use kdl::KdlNode;
 
 use crate::State;
+use crate::file::hash_cache::{HashCache, sha256_file};
 
 pub struct Context {
     root: PathBuf,
+    /// Whether files and units are backed up before being overwritten when
+    /// their node does not say; see [`Context::set_backup`].
+    backup: bool,
+    /// Whether `cp` checks hash the whole remote tree; see [`Context::set_paranoid`].
+    paranoid: bool,
+    /// Hashes of `cp` sources from earlier runs; see [`Context::enable_hash_cache`].
+    hash_cache: Option<HashCache>,
     pub(crate) kdl_rule_deserializers: BTreeMap<&'static str, fn(&mut State, &KdlNode, &Context)>,
 }
 
@@ -29,12 +37,70 @@ impl Context {
         let root = fs::canonicalize(root).expect("Failed to canonicalize path");
         Context {
             root,
+            backup: false,
+            paranoid: false,
+            hash_cache: None,
             // file,
             kdl_rule_deserializers: BTreeMap::new(),
         }
     }
 
+    /// Back up every file and unit cook overwrites, unless its node says
+    /// `backup=#false`. Off by default: backups accumulate on the host.
+    pub fn set_backup(&mut self, backup: bool) {
+        self.backup = backup;
+    }
+
+    pub(crate) fn backup(&self) -> bool {
+        self.backup
+    }
+
+    /// Check a `cp` by hashing every file under its destination, rather than
+    /// trusting the manifest left by the last apply for files whose size and
+    /// mtime haven't moved.
+    pub fn set_paranoid(&mut self, paranoid: bool) {
+        self.paranoid = paranoid;
+    }
+
+    pub(crate) fn paranoid(&self) -> bool {
+        self.paranoid
+    }
+
+    /// Reuse the hashes of unchanged `cp` sources across runs, from
+    /// `<root>/.cook/cache`. Off by default, so parsing a config leaves
+    /// nothing behind unless the caller asks for it.
+    pub fn enable_hash_cache(&mut self) {
+        self.hash_cache = Some(HashCache::open(&self.root.join(".cook/cache")));
+    }
+
+    /// The sha256 of a local file, from the hash cache when it is enabled.
+    pub(crate) fn sha256_file(&self, path: &Path) -> String {
+        let sha256 = match &self.hash_cache {
+            Some(cache) => cache.sha256(path),
+            None => sha256_file(path),
+        };
+        sha256.unwrap_or_else(|e| panic!("Failed to read {}: {e}", path.display()))
+    }
+
+    /// Write hashes taken since the last save back to the cache. A cache that
+    /// can't be written only makes the next run slower, so it is not an error.
+    fn save_hash_cache(&self) {
+        if let Some(cache) = &self.hash_cache
+            && let Err(e) = cache.save()
+        {
+            tracing::warn!(root = %self.root.display(), "could not save the hash cache: {e}");
+        }
+    }
+
     pub fn local_path(&self, path: impl AsRef<Path>) -> PathBuf {
         self.root.join(path.as_ref())
     }
 }
+
+/// The hash cache is written once, when the config that filled it has been
+/// read, rather than after every `cp`.
+impl Drop for Context {
+    fn drop(&mut self) {
+        self.save_hash_cache();
+    }
+}
@@ -13,6 +13,15 @@ pub fn file(destination: impl Into<String>) -> File {
     File::new(destination.into())
 }
 
+pub fn link(destination: impl Into<String>, target: impl Into<String>) -> File {
+    File {
+        destination: destination.into(),
+        src: None,
+        content: None,
+        link: Some(target.into()),
+    }
+}
+
 #[derive(Debug)]
 pub struct File {
     pub destination: String,
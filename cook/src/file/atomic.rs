@@ -0,0 +1,403 @@
+//! Putting new file content in place on a host without a moment where the file
+//! is half written.
+//!
+//! Content goes to a temporary file beside the destination — the same
+//! directory, so the same filesystem — is given its mode and owner there, and
+//! is then renamed over the destination. `rename(2)` is atomic: a service
+//! reading its config mid-deploy sees the old file or the new one, and a
+//! dropped connection leaves at most a stray temporary file, never a truncated
+//! config.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{Error, sh_single_quote};
+
+/// The mode and ownership a file is given before it replaces the old one.
+///
+/// `None` keeps what the replaced file had — writing in place used to, and a
+/// rule that does not manage the mode should not reset it — or, for a new
+/// file, whatever creating it produced.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Attributes {
+    pub mode: Option<u32>,
+    pub owner: Option<u32>,
+    pub group: Option<u32>,
+}
+
+/// What the old file gets before it is replaced: a check the new content has
+/// to pass, and a copy kept of the old one.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Safeguards<'a> {
+    /// A command with `%s` for the temporary copy, e.g. `visudo -cf %s`.
+    pub validate: Option<&'a str>,
+    /// Where to copy the old file, if there is one.
+    pub backup: Option<&'a Path>,
+}
+
+/// A temporary path beside `path`, hidden and distinct per call.
+pub(crate) fn temp_path(path: &Path) -> Result<String, Error> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| format!("{} has no valid file name", path.display()))?;
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp = path.with_file_name(format!(".{name}.cook-{}-{n}", std::process::id()));
+    Ok(tmp.to_str().ok_or("file path is not valid utf-8")?.to_string())
+}
+
+/// A command printing `path`'s permission bits in octal, then its numeric
+/// owner and group: `stat -c` on GNU and BusyBox, `stat -f` on BSD and macOS.
+pub(crate) fn stat_script(path: &str) -> String {
+    let p = sh_single_quote(path);
+    format!("stat -c '%a %u %g' {p} 2>/dev/null || stat -f '%Mp%Lp %u %g' {p}")
+}
+
+/// Exit status of the finish script when the validate command rejected the
+/// new content, as opposed to a step of the install itself failing.
+const REJECTED: i32 = 3;
+
+/// The script that moves `tmp` into place at `path`, once the validate
+/// command (if any) has accepted it and the old file (if any) is backed up.
+///
+/// Steps are chained with `&&` rather than relying on `set -e` (which a `||`
+/// around them would switch off), and any failure removes `tmp` so a refused
+/// install leaves nothing behind but the old file. Validation runs after the
+/// mode and owner are set, since `visudo` and `sshd -t` check those too.
+pub(crate) fn finish_script(tmp: &str, path: &str, attributes: &Attributes, safeguards: &Safeguards) -> String {
+    let t = sh_single_quote(tmp);
+    let p = sh_single_quote(path);
+    let mut steps = Vec::new();
+    if attributes.mode.is_none() || attributes.owner.is_none() || attributes.group.is_none() {
+        // `--reference` is GNU's alone; the old file's mode and owner are read
+        // and applied as numbers instead, which BSD and BusyBox also take.
+        let mut inherit = vec![format!("a=$({})", stat_script(path)), "set -- $a".to_string()];
+        if attributes.mode.is_none() {
+            inherit.push(format!("chmod \"$1\" {t}"));
+        }
+        if attributes.owner.is_none() || attributes.group.is_none() {
+            inherit.push(format!("chown \"$2:$3\" {t}"));
+        }
+        steps.push(format!("{{ [ ! -e {p} ] || {{ {}; }}; }}", inherit.join(" && ")));
+    }
+    if let Some(mode) = attributes.mode {
+        steps.push(format!("chmod {mode:o} {t}"));
+    }
+    match (attributes.owner, attributes.group) {
+        (Some(owner), Some(group)) => steps.push(format!("chown {owner}:{group} {t}")),
+        (Some(owner), None) => steps.push(format!("chown {owner} {t}")),
+        (None, Some(group)) => steps.push(format!("chgrp {group} {t}")),
+        (None, None) => {}
+    }
+    let mut script = String::new();
+    if !steps.is_empty() {
+        script.push_str(&format!("{{ {}; }} || {{ rm -f {t}; exit 1; }}; ", steps.join(" && ")));
+    }
+    if let Some(validate) = safeguards.validate {
+        // Validators like `nginx -t` report on stdout as often as stderr;
+        // both go to stderr so the error carries whatever was said.
+        script.push_str(&format!(
+            "{{ {}; }} 1>&2 || {{ rm -f {t}; exit {REJECTED}; }}; ",
+            validate.replace("%s", &t)
+        ));
+    }
+    // Last before the rename, so a rejected file never leaves a backup of a
+    // file that was not replaced.
+    if let Some(backup) = safeguards.backup {
+        script.push_str(&format!(
+            "{} || {{ rm -f {t}; exit 1; }}; ",
+            crate::file::backup::backup_script(path, backup)
+        ));
+    }
+    script.push_str(&format!("mv -f {t} {p} || {{ rm -f {t}; exit 1; }}"));
+    script
+}
+
+/// Move an already written `tmp` into place at `path`.
+#[cfg(feature = "ssh")]
+pub(crate) async fn finish(
+    session: &openssh::Session,
+    tmp: &str,
+    path: &Path,
+    attributes: &Attributes,
+    safeguards: &Safeguards<'_>,
+) -> Result<(), Error> {
+    let path_str = path.to_str().ok_or("file path is not valid utf-8")?;
+    let script = finish_script(tmp, path_str, attributes, safeguards);
+    let output = session.command("sh").arg("-c").arg(&script).output().await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if output.status.code() == Some(REJECTED) {
+            return Err(format!(
+                "{path_str} was not replaced: `{}` rejected the new content: {}",
+                safeguards.validate.unwrap_or_default(),
+                stderr.trim()
+            )
+            .into());
+        }
+        return Err(format!("installing {path_str} failed: {}", stderr.trim()).into());
+    }
+    Ok(())
+}
+
+/// Write `content` to `path` on the host, atomically, with `safeguards`.
+#[cfg(feature = "ssh")]
+pub(crate) async fn upload(
+    session: &std::sync::Arc<openssh::Session>,
+    path: &Path,
+    content: &[u8],
+    attributes: &Attributes,
+    safeguards: &Safeguards<'_>,
+) -> Result<(), Error> {
+    let tmp = temp_path(path)?;
+    write_temp(session, &tmp, path, content).await?;
+    finish(session, &tmp, path, attributes, safeguards).await
+}
+
+/// Stream the local file `local` to `path` on the host, atomically, with
+/// `safeguards`.
+///
+/// `sha256` is the hash taken when the Cookfile was read. A file edited since
+/// then is refused rather than shipped: the check that decided to upload it
+/// compared the host against the old hash, not what is on disk now.
+#[cfg(feature = "ssh")]
+pub(crate) async fn upload_local(
+    session: &std::sync::Arc<openssh::Session>,
+    path: &Path,
+    local: &Path,
+    sha256: &str,
+    attributes: &Attributes,
+    safeguards: &Safeguards<'_>,
+) -> Result<(), Error> {
+    let file = std::fs::File::open(local).map_err(|e| format!("reading {}: {e}", local.display()))?;
+    let tmp = temp_path(path)?;
+    let written = write_temp(session, &tmp, path, file).await?;
+    if written != sha256 {
+        let _ = session.command("rm").arg("-f").arg(&tmp).status().await;
+        return Err(format!(
+            "{} changed while cook was running; run again to ship it",
+            local.display()
+        )
+        .into());
+    }
+    finish(session, &tmp, path, attributes, safeguards).await
+}
+
+/// Copy `source` to `tmp` over sftp a chunk at a time, and return the sha256
+/// of what was written. On failure the partial `tmp` is removed.
+#[cfg(feature = "ssh")]
+async fn write_temp(
+    session: &std::sync::Arc<openssh::Session>,
+    tmp: &str,
+    path: &Path,
+    mut source: impl std::io::Read + Send,
+) -> Result<String, Error> {
+    use openssh_sftp_client::{Sftp, SftpOptions};
+    use sha2::{Digest, Sha256};
+    const CHUNK: usize = 64 * 1024;
+
+    let sftp = Sftp::from_clonable_session(session.clone(), SftpOptions::new()).await?;
+    let mut hasher = Sha256::new();
+    let written: Result<(), Error> = async {
+        let mut f = sftp.create(tmp).await?;
+        let mut buf = vec![0; CHUNK];
+        loop {
+            let n = source.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            f.write_all(&buf[..n]).await?;
+        }
+        f.close().await?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = written {
+        // Best effort: the connection that failed the write may be gone too.
+        let _ = session.command("rm").arg("-f").arg(tmp).status().await;
+        return Err(format!("uploading {} failed: {e}", path.display()).into());
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::process::Command;
+
+    /// A scratch directory holding `config` with `old` as its content.
+    fn scratch(name: &str) -> (std::path::PathBuf, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("cook-atomic-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config");
+        std::fs::write(&path, "old").unwrap();
+        (dir, path)
+    }
+
+    /// Write `new` to a temporary file beside `path` and run the finish
+    /// script on it locally, the way the host would.
+    fn install(path: &Path, new: &str, attributes: Attributes) -> (bool, String) {
+        let (status, tmp) = install_validated(path, new, attributes, None);
+        (status.success(), tmp)
+    }
+
+    fn install_validated(
+        path: &Path,
+        new: &str,
+        attributes: Attributes,
+        validate: Option<&str>,
+    ) -> (std::process::ExitStatus, String) {
+        let tmp = temp_path(path).unwrap();
+        std::fs::write(&tmp, new).unwrap();
+        let safeguards = Safeguards {
+            validate,
+            ..Safeguards::default()
+        };
+        let script = finish_script(&tmp, path.to_str().unwrap(), &attributes, &safeguards);
+        let output = Command::new("sh").arg("-c").arg(&script).output().unwrap();
+        (output.status, tmp)
+    }
+
+    fn mode(path: &Path) -> u32 {
+        std::fs::metadata(path).unwrap().permissions().mode() & 0o7777
+    }
+
+    #[test]
+    fn the_new_content_replaces_the_old_with_its_mode_set() {
+        let (dir, path) = scratch("replace");
+        let (ok, tmp) = install(
+            &path,
+            "new",
+            Attributes {
+                mode: Some(0o640),
+                ..Attributes::default()
+            },
+        );
+        assert!(ok);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(mode(&path), 0o640);
+        assert!(!Path::new(&tmp).exists(), "the temporary file is renamed away");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn an_unmanaged_mode_is_kept_from_the_replaced_file() {
+        let (dir, path) = scratch("inherit");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let (ok, _) = install(&path, "new", Attributes::default());
+        assert!(ok);
+        assert_eq!(mode(&path), 0o755);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_new_file_needs_nothing_to_inherit_from() {
+        let (dir, path) = scratch("fresh");
+        let fresh = dir.join("fresh");
+        let (ok, _) = install(&fresh, "new", Attributes::default());
+        assert!(ok);
+        assert_eq!(std::fs::read_to_string(&fresh).unwrap(), "new");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_failed_step_leaves_the_old_file_and_no_temporary() {
+        let (dir, path) = scratch("fail");
+        // Not a mode `chmod` accepts: the step after the write fails.
+        let (ok, tmp) = install(
+            &path,
+            "new",
+            Attributes {
+                mode: Some(0o7777777),
+                ..Attributes::default()
+            },
+        );
+        assert!(!ok);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old");
+        assert!(!Path::new(&tmp).exists(), "a refused install cleans up after itself");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_validator_sees_the_new_content_before_it_is_installed() {
+        let (dir, path) = scratch("validated");
+        let (status, _) = install_validated(&path, "new", Attributes::default(), Some("grep -q new %s"));
+        assert!(status.success());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_rejected_file_leaves_the_old_one_in_place() {
+        let (dir, path) = scratch("rejected");
+        let (status, tmp) = install_validated(&path, "broken", Attributes::default(), Some("grep -q new %s"));
+        assert_eq!(status.code(), Some(REJECTED));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old");
+        assert!(!Path::new(&tmp).exists(), "the rejected copy is removed");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn the_old_file_is_backed_up_before_it_is_replaced() {
+        let (dir, path) = scratch("backup");
+        let backup = dir.join("backups/config.20261019T123456Z");
+        let tmp = temp_path(&path).unwrap();
+        std::fs::write(&tmp, "new").unwrap();
+        let safeguards = Safeguards {
+            backup: Some(&backup),
+            ..Safeguards::default()
+        };
+        let script = finish_script(&tmp, path.to_str().unwrap(), &Attributes::default(), &safeguards);
+        assert!(Command::new("sh").arg("-c").arg(&script).status().unwrap().success());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "old");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn the_temporary_file_is_a_hidden_sibling() {
+        let tmp = temp_path(Path::new("/etc/caddy/Caddyfile")).unwrap();
+        assert!(tmp.starts_with("/etc/caddy/.Caddyfile.cook-"), "got: {tmp}");
+        assert_ne!(tmp, temp_path(Path::new("/etc/caddy/Caddyfile")).unwrap());
+    }
+
+    /// Where `stat -c` is refused, as on macOS, the BSD form is used.
+    #[test]
+    fn the_mode_is_kept_where_stat_takes_bsd_flags() {
+        let (dir, path) = scratch("bsd-stat");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o750)).unwrap();
+        let bin = dir.join("bin");
+        std::fs::create_dir(&bin).unwrap();
+        let shim = bin.join("stat");
+        std::fs::write(
+            &shim,
+            "#!/bin/sh\n[ \"$1\" = -f ] || exit 1\nexec /usr/bin/env -i PATH=/usr/bin:/bin stat -c '%04a %u %g' \"$3\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&shim, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let tmp = temp_path(&path).unwrap();
+        std::fs::write(&tmp, "new").unwrap();
+        let script = finish_script(
+            &tmp,
+            path.to_str().unwrap(),
+            &Attributes::default(),
+            &Safeguards::default(),
+        );
+        assert!(!script.contains("--reference"), "got: {script}");
+        let path_var = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(&script)
+            .env("PATH", path_var)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(mode(&path), 0o750);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
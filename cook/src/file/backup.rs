@@ -0,0 +1,259 @@
+//! Copies of files cook is about to overwrite, and putting them back.
+//!
+//! A backup of `/etc/caddy/Caddyfile` taken at 12:00:00 UTC on 2026-10-19 is
+//! `/var/lib/cook/backups/etc/caddy/Caddyfile.20261019T120000Z`. Mirroring the
+//! path keeps backups of same-named files apart, and the compact UTC stamp
+//! sorts as text in the order the backups were taken.
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[cfg(feature = "ssh")]
+use crate::Error;
+use crate::sh_single_quote;
+
+pub const BACKUP_DIR: &str = "/var/lib/cook/backups";
+
+/// `t` as a backup stamp, e.g. `20261019T120000Z`.
+pub fn stamp(t: SystemTime) -> String {
+    let secs = t
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .expect("the clock is set before 1970")
+        .as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs = secs % 86_400;
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Year, month and day of a count of days since 1970-01-01, in the proleptic
+/// Gregorian calendar (Howard Hinnant's `civil_from_days`).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Where the backup of `path` taken at `stamp` goes.
+pub fn backup_path(path: &Path, stamp: &str) -> PathBuf {
+    let relative = path.strip_prefix("/").unwrap_or(path);
+    let mut backup = Path::new(BACKUP_DIR).join(relative).into_os_string();
+    backup.push(format!(".{stamp}"));
+    PathBuf::from(backup)
+}
+
+/// Copy `path` to `backup`, if there is anything at `path` to copy.
+///
+/// `cp -p` keeps the mode and owner, so a restore puts back the file as it
+/// was and not just its bytes.
+pub(crate) fn backup_script(path: &str, backup: &Path) -> String {
+    let backup = backup.to_str().expect("backup paths are built from utf-8 paths");
+    let dir = Path::new(backup)
+        .parent()
+        .and_then(|d| d.to_str())
+        .unwrap_or(BACKUP_DIR);
+    format!(
+        "{{ [ ! -e {p} ] || {{ mkdir -p {d} && cp -p {p} {b}; }}; }}",
+        p = sh_single_quote(path),
+        d = sh_single_quote(dir),
+        b = sh_single_quote(backup),
+    )
+}
+
+/// Whether `s` has the shape of a [`stamp`].
+fn is_stamp(s: &str) -> bool {
+    let b = s.as_bytes();
+    b.len() == 16 && b[8] == b'T' && b[15] == b'Z' && b[..8].iter().chain(&b[9..15]).all(u8::is_ascii_digit)
+}
+
+/// The stamps of the backups of a file called `file_name`, oldest first, out
+/// of a listing of its backup directory.
+pub fn stamps_from_listing(listing: &str, file_name: &str) -> Vec<String> {
+    let prefix = format!("{file_name}.");
+    let mut stamps: Vec<String> = listing
+        .lines()
+        .filter_map(|name| name.strip_prefix(&prefix))
+        .filter(|stamp| is_stamp(stamp))
+        .map(str::to_string)
+        .collect();
+    stamps.sort();
+    stamps
+}
+
+/// A `--at` time in stamp form: `2026-10-19T12:00:00Z`, `2026-10-19 12:00`
+/// and `20261019T1200` all become a prefix of a stamp.
+fn normalize_time(at: &str) -> String {
+    at.trim()
+        .chars()
+        .filter(|c| !matches!(c, '-' | ':'))
+        .map(|c| if c == ' ' { 'T' } else { c.to_ascii_uppercase() })
+        .collect()
+}
+
+/// The backup to restore: the newest one, or with `at`, the newest one taken
+/// at or before it. A partial time covers the whole of what it names —
+/// `--at 2026-10-19` means the last backup of that day, not of the day before.
+pub fn select_backup<'a>(stamps: &'a [String], at: Option<&str>) -> Option<&'a str> {
+    let at = at.map(normalize_time);
+    stamps
+        .iter()
+        .filter(|stamp| match &at {
+            None => true,
+            Some(at) => stamp.as_str() <= at.as_str() || stamp.starts_with(at.as_str()),
+        })
+        .max()
+        .map(String::as_str)
+}
+
+/// Put the backup of `path` selected by `at` back in place, and return the
+/// backup it came from.
+///
+/// What is there now is itself backed up first, so a restore to the wrong
+/// time can be undone with another restore.
+#[cfg(feature = "ssh")]
+pub async fn restore(session: &openssh::Session, path: &Path, at: Option<&str>) -> Result<PathBuf, Error> {
+    let path_str = path.to_str().ok_or("file path is not valid utf-8")?;
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| format!("{path_str} has no file name"))?;
+    let dir = backup_path(path, "")
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from(BACKUP_DIR));
+    let output = session
+        .command("sh")
+        .arg("-c")
+        .arg(format!(
+            "ls -1A {} 2>/dev/null",
+            sh_single_quote(dir.to_str().unwrap_or(BACKUP_DIR))
+        ))
+        .output()
+        .await?;
+    let stamps = stamps_from_listing(&String::from_utf8_lossy(&output.stdout), file_name);
+    let Some(chosen) = select_backup(&stamps, at) else {
+        return Err(match at {
+            None => format!("no backups of {path_str} under {BACKUP_DIR}").into(),
+            Some(at) => format!("no backup of {path_str} taken at or before {at}").into(),
+        });
+    };
+    let source = backup_path(path, chosen);
+    let now = stamp(SystemTime::now());
+    // The current file's backup must not be the one being restored.
+    let current = if now.as_str() == chosen {
+        None
+    } else {
+        Some(backup_script(path_str, &backup_path(path, &now)))
+    };
+    let tmp = crate::file::atomic::temp_path(path)?;
+    let t = sh_single_quote(&tmp);
+    let script = format!(
+        "{}cp -p {s} {t} && mv -f {t} {p} || {{ rm -f {t}; exit 1; }}",
+        current.map(|c| format!("{c} && ")).unwrap_or_default(),
+        s = sh_single_quote(source.to_str().ok_or("backup path is not valid utf-8")?),
+        p = sh_single_quote(path_str),
+    );
+    let output = session.command("sh").arg("-c").arg(&script).output().await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "restoring {path_str} from {} failed: {}",
+            source.display(),
+            stderr.trim()
+        )
+        .into());
+    }
+    Ok(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn stamps_are_compact_utc() {
+        assert_eq!(stamp(SystemTime::UNIX_EPOCH), "19700101T000000Z");
+        // 2026-10-19 12:34:56 UTC.
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1_792_413_296);
+        assert_eq!(stamp(t), "20261019T123456Z");
+        // A leap day.
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(951_782_400);
+        assert_eq!(stamp(t), "20000229T000000Z");
+    }
+
+    #[test]
+    fn a_backup_mirrors_the_files_path() {
+        assert_eq!(
+            backup_path(Path::new("/etc/caddy/Caddyfile"), "20261019T123456Z"),
+            PathBuf::from("/var/lib/cook/backups/etc/caddy/Caddyfile.20261019T123456Z")
+        );
+    }
+
+    #[test]
+    fn only_this_files_backups_are_listed() {
+        let listing = "Caddyfile.20261019T123456Z\nCaddyfile.20261001T000000Z\n\
+                       Caddyfile.bak\nCaddyfile.d.20261019T123456Z\nother.20261019T123456Z\n";
+        assert_eq!(
+            stamps_from_listing(listing, "Caddyfile"),
+            vec!["20261001T000000Z", "20261019T123456Z"]
+        );
+    }
+
+    #[test]
+    fn the_newest_backup_at_or_before_the_time_is_chosen() {
+        let stamps: Vec<String> = [
+            "20261001T000000Z",
+            "20261019T080000Z",
+            "20261019T170000Z",
+            "20261020T090000Z",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(select_backup(&stamps, None), Some("20261020T090000Z"));
+        assert_eq!(
+            select_backup(&stamps, Some("2026-10-19T12:00:00Z")),
+            Some("20261019T080000Z")
+        );
+        assert_eq!(select_backup(&stamps, Some("2026-10-19")), Some("20261019T170000Z"));
+        assert_eq!(
+            select_backup(&stamps, Some("2026-10-15 09:00")),
+            Some("20261001T000000Z")
+        );
+        assert_eq!(select_backup(&stamps, Some("2026-09-30")), None);
+    }
+
+    #[test]
+    fn the_old_file_is_copied_only_if_it_exists() {
+        let dir = std::env::temp_dir().join(format!("cook-backup-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config");
+        let backup = dir.join("backups/config.20261019T123456Z");
+        let run = || {
+            std::process::Command::new("sh")
+                .arg("-c")
+                .arg(backup_script(path.to_str().unwrap(), &backup))
+                .status()
+                .unwrap()
+                .success()
+        };
+        assert!(run());
+        assert!(!backup.exists());
+        std::fs::write(&path, "old").unwrap();
+        assert!(run());
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), "old");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,451 @@
+//! Many new files shipped as one compressed tar stream.
+//!
+//! A `cp` of a fresh tree is hundreds of small files, and an sftp upload per
+//! file spends most of its time on round-trips. When a tree has several files
+//! the host doesn't have yet, they go as a single `tar | gzip` piped into
+//! `tar -x` on the host, with their modes set by the same extraction.
+//!
+//! The stream is extracted into a staging directory and only copied into
+//! place once all of it arrived and every local file still matched its hash,
+//! so a batch that fails part way leaves none of its files behind.
+//!
+//! Only files that don't exist yet are batched. `tar -x` writes in place, so
+//! a file being replaced still goes through the temporary-file-and-rename
+//! upload, with its validation and backup.
+
+use serde::Serialize;
+
+#[cfg(feature = "ssh")]
+use crate::file::spec::FileContent;
+use crate::file::spec::MissingFile;
+#[cfg(feature = "ssh")]
+use crate::{Error, sh_single_quote};
+
+/// Files that are absent from the host, applied together.
+#[derive(Debug, Serialize)]
+pub struct MissingFiles {
+    pub(crate) files: Vec<MissingFile>,
+}
+
+#[cfg(feature = "ssh")]
+impl MissingFiles {
+    /// Whether a `file` the host doesn't have can go in a batch: nothing has
+    /// to accept its content first, and its bytes are on this machine. Owners
+    /// are left to the per-file upload, which sets them on the temporary file.
+    pub(crate) fn accepts(file: &MissingFile) -> bool {
+        file.validate.is_none()
+            && file.owner.is_none()
+            && file.group.is_none()
+            && !matches!(file.content, FileContent::Url(_))
+    }
+
+    pub(crate) async fn apply_ssh(&self, session: &std::sync::Arc<openssh::Session>) -> Result<(), Error> {
+        let has_tar = session
+            .command("sh")
+            .arg("-c")
+            .arg("command -v tar && command -v gzip")
+            .output()
+            .await?
+            .status
+            .success();
+        if !has_tar {
+            for file in &self.files {
+                file.apply_ssh(session).await?;
+            }
+            return Ok(());
+        }
+        self.extract(session).await
+    }
+
+    /// Stream the archive into `tar -x` in a staging directory, then copy
+    /// it into place at `/`. The archive is built on its own thread, since it
+    /// reads local files, and handed over in chunks.
+    async fn extract(&self, session: &openssh::Session) -> Result<(), Error> {
+        let staging = crate::file::atomic::temp_path(std::path::Path::new(STAGING))?;
+        let mut installed = self.stream(session, &staging).await;
+        if installed.is_ok() {
+            installed = self.promote(session, &staging).await;
+        }
+        let cleanup = format!("rm -rf {}", sh_single_quote(&staging));
+        let removed = session.command("sh").arg("-c").arg(&cleanup).output().await;
+        installed?;
+        removed?;
+        Ok(())
+    }
+
+    /// Copy the extracted batch from `staging` into place, a few hundred
+    /// files per command.
+    async fn promote(&self, session: &openssh::Session, staging: &str) -> Result<(), Error> {
+        for script in promote_scripts(staging, "/", &self.files) {
+            let output = session.command("sh").arg("-c").arg(&script).output().await?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(format!("installing {} files failed: {}", self.files.len(), stderr.trim()).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Stream the archive into `tar -x` at `staging`, which it creates.
+    async fn stream(&self, session: &openssh::Session, staging: &str) -> Result<(), Error> {
+        use tokio::io::AsyncWriteExt;
+
+        let script = format!(
+            "mkdir -m 700 {s} && tar -xzpf - --no-same-owner -C {s}",
+            s = sh_single_quote(staging)
+        );
+        let mut child = session
+            .command("sh")
+            .arg("-c")
+            .arg(&script)
+            .stdin(openssh::Stdio::piped())
+            .stdout(openssh::Stdio::null())
+            .stderr(openssh::Stdio::piped())
+            .spawn()
+            .await?;
+        let mut stdin = child.stdin().take().ok_or("tar on the host has no stdin")?;
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(4);
+        let files = self.files.clone();
+        let builder = std::thread::spawn(move || {
+            let out = std::io::BufWriter::with_capacity(CHUNK, ChannelWriter(tx));
+            write_archive(&files, out)
+        });
+        let mut sent: Result<(), Error> = Ok(());
+        while let Some(chunk) = rx.recv().await {
+            if let Err(e) = stdin.write_all(&chunk).await {
+                sent = Err(e.into());
+                break;
+            }
+        }
+        // Closing the channel stops the builder if tar went away early.
+        drop(rx);
+        let _ = stdin.shutdown().await;
+        drop(stdin);
+        let built = builder.join().map_err(|_| "building the tar stream panicked")?;
+        let output = child.wait_with_output().await?;
+        built?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("extracting {} files failed: {}", self.files.len(), stderr.trim()).into());
+        }
+        sent
+    }
+}
+
+#[cfg(feature = "ssh")]
+const CHUNK: usize = 64 * 1024;
+
+/// Where a batch is extracted before it is copied into place, made unique
+/// per batch by [`crate::file::atomic::temp_path`].
+#[cfg(feature = "ssh")]
+const STAGING: &str = "/var/tmp/cook-batch";
+
+/// How many bytes of file names go in one command. The host runs each
+/// command as a single `sh -c` argument, which Linux caps at 128 KiB.
+#[cfg(feature = "ssh")]
+const NAMES_PER_COMMAND: usize = 64 * 1024;
+
+/// The scripts that copy a fully extracted batch from `staging` to `root`,
+/// each naming as many files as fit in [`NAMES_PER_COMMAND`] bytes.
+///
+/// Only the files are named, not `.`: the staging directory's own 0700 mode
+/// must not land on `root`. Directories the files need are created by the
+/// second `tar -x` as the first one created them.
+#[cfg(feature = "ssh")]
+fn promote_scripts(staging: &str, root: &str, files: &[MissingFile]) -> Vec<String> {
+    let mut chunks: Vec<Vec<String>> = Vec::new();
+    let mut size = 0;
+    for file in files {
+        let name = file.path.strip_prefix("/").unwrap_or(&file.path);
+        let name = sh_single_quote(&name.to_string_lossy());
+        match chunks.last_mut() {
+            Some(chunk) if size + name.len() < NAMES_PER_COMMAND => {
+                size += name.len() + 1;
+                chunk.push(name);
+            }
+            _ => {
+                size = name.len() + 1;
+                chunks.push(vec![name]);
+            }
+        }
+    }
+    chunks
+        .iter()
+        .map(|names| {
+            format!(
+                "tar -cf - -C {} {} | tar -xpf - --no-same-owner -C {}",
+                sh_single_quote(staging),
+                names.join(" "),
+                sh_single_quote(root),
+            )
+        })
+        .collect()
+}
+
+/// Hands what is written to it to the task feeding the host's `tar`.
+#[cfg(feature = "ssh")]
+struct ChannelWriter(tokio::sync::mpsc::Sender<Vec<u8>>);
+
+#[cfg(feature = "ssh")]
+impl std::io::Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(buf.to_vec())
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "the host stopped reading"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Write `files` to `out` as a gzipped tar archive, with paths relative to
+/// `/`, owned by root and with each file's mode (0644 when it has none).
+///
+/// A local file is hashed as it is read. If it no longer matches the hash
+/// taken when the Cookfile was read, this fails, and the caller throws away
+/// what the host extracted rather than install content nobody compared
+/// against the host.
+#[cfg(feature = "ssh")]
+pub(crate) fn write_archive(files: &[MissingFile], out: impl std::io::Write) -> Result<(), Error> {
+    use flate2::{Compression, write::GzEncoder};
+    use sha2::{Digest, Sha256};
+    use std::io::Read;
+
+    let mtime = std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut archive = tar::Builder::new(GzEncoder::new(out, Compression::default()));
+    for file in files {
+        let name = file.path.strip_prefix("/").unwrap_or(&file.path);
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_mode(file.mode.unwrap_or(0o644));
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(mtime);
+        match &file.content {
+            FileContent::Content(content, _) => {
+                header.set_size(content.len() as u64);
+                archive.append_data(&mut header, name, content.as_slice())?;
+            }
+            FileContent::Path(local, sha256) => {
+                let f = std::fs::File::open(local)?;
+                let size = f.metadata()?.len();
+                header.set_size(size);
+                let mut reader = Hashing {
+                    inner: f.take(size),
+                    hasher: Sha256::new(),
+                    read: 0,
+                };
+                archive.append_data(&mut header, name, &mut reader)?;
+                if reader.read != size || format!("{:x}", reader.hasher.finalize()) != *sha256 {
+                    return Err(format!(
+                        "{} changed while cook was running; run again to ship it",
+                        local.display()
+                    )
+                    .into());
+                }
+            }
+            FileContent::Url(_) => unreachable!("url files are never batched"),
+        }
+    }
+    archive.into_inner()?.finish()?.flush()?;
+    Ok(())
+}
+
+/// A reader that hashes and counts what passes through it.
+#[cfg(feature = "ssh")]
+struct Hashing<R> {
+    inner: R,
+    hasher: sha2::Sha256,
+    read: u64,
+}
+
+#[cfg(feature = "ssh")]
+impl<R: std::io::Read> std::io::Read for Hashing<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use sha2::Digest;
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(all(test, feature = "ssh"))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+
+    fn sha256(bytes: &[u8]) -> String {
+        use sha2::{Digest, Sha256};
+        format!("{:x}", Sha256::digest(bytes))
+    }
+
+    fn missing(path: &Path, content: FileContent, mode: Option<u32>) -> MissingFile {
+        MissingFile {
+            path: path.to_path_buf(),
+            content,
+            owner: None,
+            group: None,
+            mode,
+            validate: None,
+            backup: None,
+        }
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cook-batch-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Extract `archive` under `dir` the way the host does, but rooted there.
+    fn extract(archive: &[u8], dir: &Path) -> bool {
+        use std::io::Write;
+        let mut child = std::process::Command::new("tar")
+            .args(["-xzpf", "-", "--no-same-owner", "-C"])
+            .arg(dir)
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(archive).unwrap();
+        child.wait().unwrap().success()
+    }
+
+    #[test]
+    fn files_and_modes_arrive_in_one_stream() {
+        let dir = scratch("stream");
+        let local = dir.join("local.bin");
+        let big: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&local, &big).unwrap();
+        let files = vec![
+            missing(
+                Path::new("/srv/site/index.html"),
+                FileContent::Content(b"<h1>hi</h1>".to_vec(), sha256(b"<h1>hi</h1>")),
+                None,
+            ),
+            missing(
+                Path::new("/srv/site/bin/run"),
+                FileContent::Path(local.clone(), sha256(&big)),
+                Some(0o755),
+            ),
+        ];
+        let mut archive = Vec::new();
+        write_archive(&files, &mut archive).unwrap();
+
+        let out = dir.join("root");
+        std::fs::create_dir_all(&out).unwrap();
+        assert!(extract(&archive, &out));
+        let index = out.join("srv/site/index.html");
+        assert_eq!(std::fs::read(&index).unwrap(), b"<h1>hi</h1>");
+        assert_eq!(std::fs::metadata(&index).unwrap().permissions().mode() & 0o7777, 0o644);
+        let run = out.join("srv/site/bin/run");
+        assert_eq!(std::fs::read(&run).unwrap(), big);
+        assert_eq!(std::fs::metadata(&run).unwrap().permissions().mode() & 0o7777, 0o755);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_staged_batch_is_copied_into_place() {
+        let dir = scratch("promote");
+        let files = vec![
+            missing(
+                Path::new("/srv/site/index.html"),
+                FileContent::Content(b"<h1>hi</h1>".to_vec(), sha256(b"<h1>hi</h1>")),
+                None,
+            ),
+            missing(
+                Path::new("/srv/site/bin/run"),
+                FileContent::Content(b"#!/bin/sh\n".to_vec(), sha256(b"#!/bin/sh\n")),
+                Some(0o755),
+            ),
+        ];
+        let mut archive = Vec::new();
+        write_archive(&files, &mut archive).unwrap();
+        let staging = dir.join("staging");
+        std::fs::create_dir(&staging).unwrap();
+        std::fs::set_permissions(&staging, std::fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(extract(&archive, &staging));
+
+        let root = dir.join("root");
+        std::fs::create_dir(&root).unwrap();
+        for script in promote_scripts(staging.to_str().unwrap(), root.to_str().unwrap(), &files) {
+            let status = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(&script)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+        assert_eq!(std::fs::read(root.join("srv/site/index.html")).unwrap(), b"<h1>hi</h1>");
+        let run = root.join("srv/site/bin/run");
+        assert_eq!(std::fs::metadata(&run).unwrap().permissions().mode() & 0o7777, 0o755);
+        assert_ne!(
+            std::fs::metadata(&root).unwrap().permissions().mode() & 0o777,
+            0o700,
+            "the staging directory's mode stays with it"
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_large_batch_is_copied_in_commands_the_host_accepts() {
+        let content = FileContent::Content(b"x".to_vec(), sha256(b"x"));
+        let deep = "a-fairly-long-directory-name/".repeat(20);
+        let files: Vec<MissingFile> = (0..5000)
+            .map(|i| {
+                missing(
+                    &Path::new("/srv/site").join(&deep).join(format!("{i}.html")),
+                    content.clone(),
+                    None,
+                )
+            })
+            .collect();
+        let scripts = promote_scripts("/var/tmp/cook-batch.x", "/", &files);
+        assert!(scripts.len() > 1);
+        for script in &scripts {
+            assert!(script.len() < 128 * 1024, "{} bytes", script.len());
+        }
+        let named: usize = scripts.iter().map(|s| s.matches(".html").count()).sum();
+        assert_eq!(named, files.len());
+    }
+
+    #[test]
+    fn a_file_that_changed_since_it_was_hashed_stops_the_stream() {
+        let dir = scratch("changed");
+        let local = dir.join("local.txt");
+        std::fs::write(&local, "new").unwrap();
+        let files = vec![missing(
+            Path::new("/srv/a.txt"),
+            FileContent::Path(local, sha256(b"old")),
+            None,
+        )];
+        let err = write_archive(&files, Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("changed while cook was running"), "{err}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn only_local_files_without_safeguards_are_batched() {
+        let content = FileContent::Content(b"x".to_vec(), sha256(b"x"));
+        let plain = missing(Path::new("/a"), content, Some(0o600));
+        assert!(MissingFiles::accepts(&plain));
+        let validated = MissingFile {
+            validate: Some("true %s".into()),
+            ..plain.clone()
+        };
+        assert!(!MissingFiles::accepts(&validated));
+        let owned = MissingFile {
+            owner: Some(1000),
+            ..plain
+        };
+        assert!(!MissingFiles::accepts(&owned));
+    }
+}
@@ -0,0 +1,435 @@
+//! `directory <path>` — a directory, its mode and owner, and optionally those of
+//! everything already under it.
+//!
+//! `file /srv/app/` (a path with a trailing slash) means the same thing, so a
+//! directory does not have to be spelled differently from the files around it.
+
+use std::path::PathBuf;
+
+use kdl::KdlNode;
+use serde::{Deserialize, Serialize};
+
+use crate::file::spec::{FileChange, parse_mode};
+use crate::{Context, Error, FromKdl, Modification, Rule, RuleOverSsh, State};
+
+#[cfg(feature = "ssh")]
+use crate::sh_single_quote;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectorySpec {
+    pub path: PathBuf,
+    /// Permission bits to enforce on the directory, and with `recurse` on the
+    /// directories under it. Files keep their own: a directory's 750 would
+    /// make every data file under it executable. `None` leaves them to
+    /// `mkdir` and the umask.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// User name or uid. `chown` and `find -user` take either.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Also bring the directory's existing contents to `owner`/`group`, like
+    /// `chown -R`, and the directories among them to `mode`. Off by default:
+    /// a directory rule usually only exists so that something else has a
+    /// place to write.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub recurse: bool,
+}
+
+impl FromKdl for DirectorySpec {
+    fn kdl_keywords() -> &'static [&'static str] {
+        &["directory"]
+    }
+
+    /// Also reached from `file` when its path ends in `/`.
+    fn add_rules_to_state(state: &mut State, node: &KdlNode, _context: &Context) {
+        let keyword = node.name().value();
+        let mut path = None;
+        let mut spec = DirectorySpec {
+            path: PathBuf::new(),
+            mode: None,
+            owner: None,
+            group: None,
+            recurse: false,
+        };
+        for entry in node.entries() {
+            match entry.name().map(|i| i.value()) {
+                None if path.is_none() => path = Some(entry.expect_str()),
+                None => panic!("{keyword}: unexpected argument {}", entry.value()),
+                Some("mode") => spec.mode = Some(parse_mode(entry, keyword)),
+                Some("owner") => spec.owner = Some(entry.expect_str().to_string()),
+                Some("group") => spec.group = Some(entry.expect_str().to_string()),
+                Some("recurse") => {
+                    spec.recurse = entry
+                        .value()
+                        .as_bool()
+                        .unwrap_or_else(|| panic!("{keyword}: recurse must be true or false"))
+                }
+                Some(z) => panic!("Unexpected option for {keyword}: {z}"),
+            }
+        }
+        let path = path.unwrap_or_else(|| panic!("{keyword} requires a path"));
+        // The trailing slash only says "this is a directory"; the unit is
+        // named by the path itself, so `after /srv/app` finds it either way.
+        let trimmed = path.trim_end_matches('/');
+        spec.path = PathBuf::from(if trimmed.is_empty() { "/" } else { trimmed });
+        state.add_rule(spec);
+    }
+}
+
+impl Rule for DirectorySpec {
+    #[cfg(feature = "ssh")]
+    fn downcast_ssh(&self) -> Option<&dyn RuleOverSsh> {
+        Some(self)
+    }
+
+    fn check(&self) -> Result<Vec<Box<dyn Modification>>, Error> {
+        todo!()
+    }
+
+    fn kind(&self) -> &'static str {
+        "file"
+    }
+
+    fn identifier(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+#[cfg(feature = "ssh")]
+impl DirectorySpec {
+    /// The shell script that reports the directory's state in one round-trip.
+    ///
+    /// Prints `missing`, `not-a-directory`, or the directory's own
+    /// `mode user group uid gid` — followed, when `recurse` is set, by the
+    /// first entry under it whose mode or ownership is wrong. `find -quit`
+    /// stops at that first entry, so a large, already-correct tree costs one
+    /// walk and no output.
+    fn check_script(&self, path: &str) -> String {
+        let p = sh_single_quote(path);
+        let mut script = format!("if [ -d {p} ]; then stat -c '%a %U %G %u %g' {p}");
+        let mut drift = Vec::new();
+        if let Some(owner) = &self.owner {
+            drift.push(format!("! -user {}", sh_single_quote(owner)));
+        }
+        if let Some(group) = &self.group {
+            drift.push(format!("! -group {}", sh_single_quote(group)));
+        }
+        if let Some(mode) = self.mode {
+            drift.push(format!("-type d ! -perm {mode:o}"));
+        }
+        if self.recurse && !drift.is_empty() {
+            script.push_str(&format!(
+                "; find {p} -mindepth 1 \\( {} \\) -print -quit 2>/dev/null",
+                drift.join(" -o ")
+            ));
+        }
+        script.push_str(&format!(
+            "; elif [ -e {p} ]; then echo not-a-directory; else echo missing; fi"
+        ));
+        script
+    }
+}
+
+/// What [`DirectorySpec::check_script`] found on the host.
+#[cfg(feature = "ssh")]
+#[derive(Debug, PartialEq, Eq)]
+enum RemoteDirectory {
+    Missing,
+    NotADirectory,
+    Present {
+        mode: u32,
+        user_name: String,
+        group_name: String,
+        uid: String,
+        gid: String,
+        /// Something under the directory has the wrong mode or owner. Only
+        /// ever set when the rule recurses.
+        contents_drifted: bool,
+    },
+}
+
+#[cfg(feature = "ssh")]
+impl RemoteDirectory {
+    fn parse(stdout: &str) -> Result<RemoteDirectory, Error> {
+        let mut lines = stdout.lines();
+        let first = lines.next().unwrap_or_default().trim();
+        match first {
+            "missing" => return Ok(RemoteDirectory::Missing),
+            "not-a-directory" => return Ok(RemoteDirectory::NotADirectory),
+            _ => {}
+        }
+        let fields: Vec<&str> = first.split_whitespace().collect();
+        let [mode, user_name, group_name, uid, gid] = fields[..] else {
+            return Err(format!("unexpected directory check output {stdout:?}").into());
+        };
+        let mode = u32::from_str_radix(mode, 8).map_err(|_| format!("unexpected directory mode {mode:?}"))?;
+        Ok(RemoteDirectory::Present {
+            mode,
+            user_name: user_name.to_string(),
+            group_name: group_name.to_string(),
+            uid: uid.to_string(),
+            gid: gid.to_string(),
+            contents_drifted: lines.any(|l| !l.trim().is_empty()),
+        })
+    }
+}
+
+#[cfg(feature = "ssh")]
+#[async_trait::async_trait]
+impl RuleOverSsh for DirectorySpec {
+    async fn check_ssh(&self, session: &openssh::Session) -> Result<Vec<Box<dyn Modification>>, Error> {
+        let path = self.path.to_str().ok_or("directory path is not valid utf-8")?;
+        let output = session
+            .command("sh")
+            .arg("-c")
+            .arg(self.check_script(path))
+            .output()
+            .await?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let changes = match RemoteDirectory::parse(&stdout)? {
+            RemoteDirectory::Missing => vec![FileChange::MissingDirectory(MissingDirectory {
+                path: self.path.clone(),
+                mode: self.mode,
+                owner: self.owner.clone(),
+                group: self.group.clone(),
+            })],
+            RemoteDirectory::NotADirectory => {
+                return Err(format!("{path} exists on the host but is not a directory").into());
+            }
+            RemoteDirectory::Present {
+                mode,
+                user_name,
+                group_name,
+                uid,
+                gid,
+                contents_drifted,
+            } => {
+                let owner_ok = self.owner.as_ref().is_none_or(|o| *o == user_name || *o == uid);
+                let group_ok = self.group.as_ref().is_none_or(|g| *g == group_name || *g == gid);
+                let mode_ok = self.mode.is_none_or(|m| m == mode);
+                if owner_ok && group_ok && mode_ok && !contents_drifted {
+                    Vec::new()
+                } else {
+                    vec![FileChange::WrongDirectory(WrongDirectory {
+                        path: self.path.clone(),
+                        mode: self.mode,
+                        owner: self.owner.clone(),
+                        group: self.group.clone(),
+                        recurse: self.recurse,
+                        current: format!("{mode:o} {user_name}:{group_name}"),
+                    })]
+                }
+            }
+        };
+        Ok(changes
+            .into_iter()
+            .map(|c| Box::new(c) as Box<dyn Modification>)
+            .collect())
+    }
+}
+
+/// The directory does not exist. Creating it also creates its parents, which
+/// are left at `mkdir`'s defaults — only the directory itself is this rule's.
+#[derive(Debug, Serialize)]
+pub struct MissingDirectory {
+    pub path: PathBuf,
+    pub mode: Option<u32>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+}
+
+/// The directory exists, but its mode or ownership — or, with `recurse`, that
+/// of something under it — is not what the rule says.
+#[derive(Debug, Serialize)]
+pub struct WrongDirectory {
+    pub path: PathBuf,
+    pub mode: Option<u32>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    pub recurse: bool,
+    /// `mode user:group` of the directory on the host, for readable output.
+    pub current: String,
+}
+
+/// The `chown`/`chmod` commands that bring `path` to the given attributes,
+/// joined into one script. Empty when there is nothing to enforce. With
+/// `recurse`, ownership goes to everything under `path` and the mode only to
+/// the directories.
+#[cfg(feature = "ssh")]
+fn fix_attributes_script(
+    path: &str,
+    mode: Option<u32>,
+    owner: Option<&str>,
+    group: Option<&str>,
+    recurse: bool,
+) -> String {
+    let p = sh_single_quote(path);
+    let r = if recurse { " -R" } else { "" };
+    let mut commands = Vec::new();
+    // `chown user:` would switch the group to the user's login group, so a
+    // rule that names only one of the two leaves the other alone.
+    let spec = match (owner, group) {
+        (Some(owner), Some(group)) => Some(format!("{owner}:{group}")),
+        (Some(owner), None) => Some(owner.to_string()),
+        (None, Some(group)) => Some(format!(":{group}")),
+        (None, None) => None,
+    };
+    if let Some(spec) = spec {
+        commands.push(format!("chown{r} {} {p}", sh_single_quote(&spec)));
+    }
+    if let Some(mode) = mode {
+        if recurse {
+            commands.push(format!("find {p} -type d -exec chmod {mode:o} {{}} +"));
+        } else {
+            commands.push(format!("chmod {mode:o} {p}"));
+        }
+    }
+    commands.join(" && ")
+}
+
+#[cfg(feature = "ssh")]
+async fn run_script(session: &openssh::Session, script: &str, what: &str) -> Result<(), Error> {
+    let output = session.command("sh").arg("-c").arg(script).output().await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{what} failed: {}", stderr.trim()).into());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "ssh")]
+impl MissingDirectory {
+    pub(crate) async fn apply_ssh(&self, session: &openssh::Session) -> Result<(), Error> {
+        let path = self.path.to_str().ok_or("directory path is not valid utf-8")?;
+        let mut script = format!("mkdir -p {}", sh_single_quote(path));
+        let fix = fix_attributes_script(path, self.mode, self.owner.as_deref(), self.group.as_deref(), false);
+        if !fix.is_empty() {
+            script.push_str(" && ");
+            script.push_str(&fix);
+        }
+        run_script(session, &script, &format!("creating directory {path}")).await
+    }
+}
+
+#[cfg(feature = "ssh")]
+impl WrongDirectory {
+    pub(crate) async fn apply_ssh(&self, session: &openssh::Session) -> Result<(), Error> {
+        let path = self.path.to_str().ok_or("directory path is not valid utf-8")?;
+        let script = fix_attributes_script(
+            path,
+            self.mode,
+            self.owner.as_deref(),
+            self.group.as_deref(),
+            self.recurse,
+        );
+        run_script(session, &script, &format!("fixing directory {path}")).await
+    }
+}
+
+#[cfg(all(test, feature = "ssh"))]
+mod tests {
+    use super::*;
+
+    fn spec(mode: Option<u32>, owner: Option<&str>, recurse: bool) -> DirectorySpec {
+        DirectorySpec {
+            path: PathBuf::from("/srv/app"),
+            mode,
+            owner: owner.map(str::to_string),
+            group: None,
+            recurse,
+        }
+    }
+
+    #[test]
+    fn check_output_is_parsed() {
+        assert_eq!(RemoteDirectory::parse("missing\n").unwrap(), RemoteDirectory::Missing);
+        assert_eq!(
+            RemoteDirectory::parse("not-a-directory\n").unwrap(),
+            RemoteDirectory::NotADirectory
+        );
+        assert_eq!(
+            RemoteDirectory::parse("755 app app-grp 1001 2002\n").unwrap(),
+            RemoteDirectory::Present {
+                mode: 0o755,
+                user_name: "app".to_string(),
+                group_name: "app-grp".to_string(),
+                uid: "1001".to_string(),
+                gid: "2002".to_string(),
+                contents_drifted: false,
+            }
+        );
+    }
+
+    #[test]
+    fn a_path_printed_after_the_stat_line_is_drift_underneath() {
+        let RemoteDirectory::Present { contents_drifted, .. } =
+            RemoteDirectory::parse("755 app app 1001 1001\n/srv/app/root-owned.log\n").unwrap()
+        else {
+            panic!("expected a present directory");
+        };
+        assert!(contents_drifted);
+    }
+
+    #[test]
+    fn garbage_is_an_error_rather_than_a_guess() {
+        assert!(RemoteDirectory::parse("").is_err());
+        assert!(RemoteDirectory::parse("drwxr-xr-x app\n").is_err());
+    }
+
+    #[test]
+    fn only_a_recursive_rule_walks_the_contents() {
+        let flat = spec(Some(0o750), Some("app"), false).check_script("/srv/app");
+        assert!(!flat.contains("find"), "got: {flat}");
+
+        let deep = spec(Some(0o750), Some("app"), true).check_script("/srv/app");
+        assert!(
+            deep.contains("find '/srv/app' -mindepth 1 \\( ! -user 'app' -o -type d ! -perm 750 \\) -print -quit"),
+            "got: {deep}"
+        );
+    }
+
+    #[test]
+    fn recursing_with_nothing_to_enforce_walks_nothing() {
+        let script = spec(None, None, true).check_script("/srv/app");
+        assert!(!script.contains("find"), "got: {script}");
+    }
+
+    #[test]
+    fn a_recursive_mode_is_only_for_directories() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("cook-directory-recurse-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        std::fs::write(dir.join("data/report.csv"), "").unwrap();
+        let mode = |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+        std::fs::set_permissions(dir.join("data/report.csv"), std::fs::Permissions::from_mode(0o640)).unwrap();
+
+        let path = dir.to_str().unwrap();
+        let script = fix_attributes_script(path, Some(0o750), None, None, true);
+        assert!(
+            std::process::Command::new("sh")
+                .arg("-c")
+                .arg(&script)
+                .status()
+                .unwrap()
+                .success()
+        );
+        assert_eq!(mode(&dir), 0o750);
+        assert_eq!(mode(&dir.join("data")), 0o750);
+        assert_eq!(mode(&dir.join("data/report.csv")), 0o640);
+
+        // And a file that keeps its own mode is not drift.
+        let check = spec(Some(0o750), None, true).check_script(path);
+        let output = std::process::Command::new("sh").arg("-c").arg(&check).output().unwrap();
+        let RemoteDirectory::Present { contents_drifted, .. } =
+            RemoteDirectory::parse(&String::from_utf8_lossy(&output.stdout)).unwrap()
+        else {
+            panic!("expected a present directory");
+        };
+        assert!(!contents_drifted);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,453 @@
+//! `line` and `block` — a part of a file cook owns, in a file it doesn't.
+//!
+//! ```kdl
+//! line /etc/ssh/sshd_config "PasswordAuthentication no" match="^#?PasswordAuthentication\\b"
+//! line /etc/hosts "10.0.0.9 old-db" state=absent
+//! block /etc/hosts cluster content="10.0.0.5 db\n10.0.0.6 cache"
+//! ```
+//!
+//! Every `line` and `block` for one path, in one config, is a single rule:
+//! the file is read once, edited in config order, and written back once,
+//! atomically, if anything changed. A node that joins an earlier node's rule
+//! joins its unit too, so its own `after` and `before` go unused.
+
+use std::path::{Path, PathBuf};
+
+use kdl::{KdlNode, KdlValue};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::file::spec::{FileChange, parse_validate};
+use crate::{Context, Error, FromKdl, Modification, Rule, RuleOverSsh, State};
+
+#[cfg(feature = "ssh")]
+use crate::file::backup;
+#[cfg(feature = "ssh")]
+use crate::file::spec::{FileContent, MissingFile};
+#[cfg(feature = "ssh")]
+use crate::sh_single_quote;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Edit {
+    /// A line that must be there, or must not.
+    Line {
+        /// The whole line, without its newline. Only optional for an absent
+        /// line, which can be found by `pattern` alone.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        line: Option<String>,
+        /// Lines this one replaces. The first match becomes `line` and any
+        /// later ones are dropped, so the setting is made exactly once
+        /// whichever occurrence the program reading the file honors.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pattern: Option<String>,
+        present: bool,
+    },
+    /// Lines between `# BEGIN cook: name` and `# END cook: name`.
+    Block {
+        name: String,
+        content: String,
+        /// What starts a comment in this file, for the markers.
+        comment: String,
+        present: bool,
+    },
+}
+
+impl Edit {
+    /// Make this edit to `lines`. Only fails on a block whose end marker has
+    /// gone missing, which cook can't safely guess the end of.
+    fn apply(&self, lines: &mut Vec<String>) -> Result<(), String> {
+        match self {
+            Edit::Line { line, pattern, present } => {
+                let pattern = pattern.as_deref().map(|p| Regex::new(p).expect("checked when parsed"));
+                let matches = |l: &str| match &pattern {
+                    Some(pattern) => pattern.is_match(l),
+                    None => Some(l) == line.as_deref(),
+                };
+                if !present {
+                    lines.retain(|l| !matches(l));
+                    return Ok(());
+                }
+                let line = line.as_deref().expect("a present line has its text");
+                match lines.iter().position(|l| matches(l)) {
+                    Some(first) => {
+                        lines[first] = line.to_string();
+                        let mut i = 0;
+                        lines.retain(|l| {
+                            i += 1;
+                            i - 1 <= first || !matches(l)
+                        });
+                    }
+                    None if lines.iter().any(|l| l == line) => {}
+                    None => lines.push(line.to_string()),
+                }
+            }
+            Edit::Block {
+                name,
+                content,
+                comment,
+                present,
+            } => {
+                let begin = format!("{comment} BEGIN cook: {name}");
+                let end = format!("{comment} END cook: {name}");
+                let range = match lines.iter().position(|l| *l == begin) {
+                    Some(b) => match lines[b..].iter().position(|l| *l == end) {
+                        Some(e) => Some(b..b + e + 1),
+                        None => return Err(format!("`{begin}` has no `{end}` after it")),
+                    },
+                    None => None,
+                };
+                let block: Vec<String> = if *present {
+                    std::iter::once(begin)
+                        .chain(content.lines().map(str::to_string))
+                        .chain(std::iter::once(end))
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                match range {
+                    Some(range) => {
+                        lines.splice(range, block);
+                    }
+                    None => lines.extend(block),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `text` with `edits` made in order. A file that ended without a newline
+/// keeps doing so unless a line is added to it, and a file with `\r\n` line
+/// endings keeps them, on the lines added too.
+pub(crate) fn apply_edits(text: &str, edits: &[Edit]) -> Result<String, String> {
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    let before = lines.clone();
+    for edit in edits {
+        edit.apply(&mut lines)?;
+    }
+    if lines == before {
+        return Ok(text.to_string());
+    }
+    let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut out = lines.join(newline);
+    if !out.is_empty() {
+        out.push_str(newline);
+    }
+    Ok(out)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EditSpec {
+    pub path: PathBuf,
+    pub edits: Vec<Edit>,
+    /// As for `file`: a command that must accept the edited file, with `%s`
+    /// for its path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validate: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub backup: bool,
+}
+
+impl FromKdl for EditSpec {
+    fn kdl_keywords() -> &'static [&'static str] {
+        &["line", "block"]
+    }
+
+    fn add_rules_to_state(state: &mut State, node: &KdlNode, context: &Context) {
+        let keyword = node.name().value();
+        let mut args = Vec::new();
+        let mut pattern = None;
+        let mut content = None;
+        let mut comment = "#".to_string();
+        let mut present = true;
+        let mut validate = None;
+        let mut backup = None;
+        for entry in node.entries() {
+            match entry.name().map(|i| i.value()) {
+                None => args.push(entry.expect_str()),
+                Some("match") if keyword == "line" => {
+                    let p = entry.expect_str();
+                    Regex::new(p).unwrap_or_else(|e| panic!("line: match {p:?} is not a valid regex: {e}"));
+                    pattern = Some(p.to_string());
+                }
+                Some("content") if keyword == "block" => content = Some(entry.expect_str().to_string()),
+                Some("comment") if keyword == "block" => comment = entry.expect_str().to_string(),
+                Some("state") => {
+                    present = match entry.value() {
+                        KdlValue::String(s) if s == "present" => true,
+                        KdlValue::String(s) if s == "absent" => false,
+                        v => panic!("{keyword}: state must be \"present\" or \"absent\", not {v}"),
+                    }
+                }
+                Some("validate") => validate = Some(parse_validate(entry, keyword)),
+                Some("backup") => {
+                    backup = Some(
+                        entry
+                            .value()
+                            .as_bool()
+                            .unwrap_or_else(|| panic!("{keyword}: backup must be true or false")),
+                    )
+                }
+                Some(z) => panic!("Unexpected option for {keyword}: {z}"),
+            }
+        }
+        let mut args = args.into_iter();
+        let path = PathBuf::from(args.next().unwrap_or_else(|| panic!("{keyword} requires a path")));
+        let edit = match keyword {
+            "line" => {
+                let line = args.next().map(str::to_string);
+                assert!(
+                    line.as_deref().is_none_or(|l| !l.contains('\n')),
+                    "line {}: a line can't contain a newline; use block",
+                    path.display()
+                );
+                assert!(
+                    line.is_some() || (!present && pattern.is_some()),
+                    "line {}: give the line, or match= to remove lines by pattern",
+                    path.display()
+                );
+                Edit::Line { line, pattern, present }
+            }
+            "block" => {
+                let name = args
+                    .next()
+                    .unwrap_or_else(|| panic!("block {} requires a name", path.display()))
+                    .to_string();
+                if let Some(children) = node.children() {
+                    for n in children.nodes() {
+                        match n.name().value() {
+                            "content" => {
+                                let [entry] = n.entries() else {
+                                    panic!("block {}: content takes exactly one string", path.display());
+                                };
+                                assert!(
+                                    content.is_none(),
+                                    "block {}: content is given more than once",
+                                    path.display()
+                                );
+                                content = Some(entry.expect_str().to_string());
+                            }
+                            z => panic!("Unexpected directive for block: {z}"),
+                        }
+                    }
+                }
+                assert!(
+                    content.is_some() || !present,
+                    "block {} {name}: a present block needs content",
+                    path.display()
+                );
+                Edit::Block {
+                    name,
+                    content: content.unwrap_or_default(),
+                    comment,
+                    present,
+                }
+            }
+            z => panic!("invalid node for EditSpec: {z}"),
+        };
+        if let Some(extra) = args.next() {
+            panic!("{keyword} {}: unexpected argument {extra:?}", path.display());
+        }
+
+        if let Some(spec) = state.rule_mut::<EditSpec>(|spec| spec.path == path) {
+            if let Some(validate) = validate {
+                assert!(
+                    spec.validate.as_ref().is_none_or(|v| *v == validate),
+                    "{keyword} {}: validate differs from an earlier edit of the same file",
+                    path.display()
+                );
+                spec.validate = Some(validate);
+            }
+            spec.backup |= backup.unwrap_or(false);
+            spec.edits.push(edit);
+            return;
+        }
+        state.add_rule(EditSpec {
+            path,
+            edits: vec![edit],
+            validate,
+            backup: backup.unwrap_or_else(|| context.backup()),
+        });
+    }
+}
+
+impl Rule for EditSpec {
+    #[cfg(feature = "ssh")]
+    fn downcast_ssh(&self) -> Option<&dyn crate::RuleOverSsh> {
+        Some(self)
+    }
+
+    fn check(&self) -> Result<Vec<Box<dyn Modification>>, Error> {
+        todo!()
+    }
+
+    /// The same kind as `file`: a `file` and a `line` for one path are two
+    /// owners of the same content.
+    fn kind(&self) -> &'static str {
+        "file"
+    }
+
+    fn identifier(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+#[cfg(feature = "ssh")]
+#[async_trait::async_trait]
+impl RuleOverSsh for EditSpec {
+    async fn check_ssh(&self, session: &openssh::Session) -> Result<Vec<Box<dyn Modification>>, Error> {
+        let current = read_text(session, &self.path).await?;
+        let text = current.as_deref().unwrap_or_default();
+        let edited = apply_edits(text, &self.edits).map_err(|e| format!("{}: {e}", self.path.display()))?;
+        if edited == text {
+            return Ok(Vec::new());
+        }
+        Ok(vec![rewrite(
+            &self.path,
+            edited,
+            current.is_some(),
+            self.backup,
+            &self.validate,
+        )])
+    }
+}
+
+/// A text file on the host, or `None` if there is nothing at `path`.
+#[cfg(feature = "ssh")]
+pub(crate) async fn read_text(session: &openssh::Session, path: &Path) -> Result<Option<String>, Error> {
+    let path = path.to_str().ok_or("file path is not valid utf-8")?;
+    // Exit 3 for a missing file, so it can't be mistaken for one `cat`
+    // could not read.
+    let script = format!("[ -e {p} ] || exit 3; cat {p}", p = sh_single_quote(path));
+    let output = session.command("sh").arg("-c").arg(&script).output().await?;
+    match output.status.code() {
+        Some(0) => {}
+        Some(3) => return Ok(None),
+        _ => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("reading {path} failed: {}", stderr.trim()).into());
+        }
+    }
+    let text = String::from_utf8(output.stdout).map_err(|_| format!("{path} is not utf-8 text"))?;
+    Ok(Some(text))
+}
+
+/// The change that puts `content` at `path` in place of what is there: an
+/// atomic upload that keeps the old file's mode and owner.
+#[cfg(feature = "ssh")]
+pub(crate) fn rewrite(
+    path: &Path,
+    content: String,
+    exists: bool,
+    backup: bool,
+    validate: &Option<String>,
+) -> Box<dyn Modification> {
+    use sha2::{Digest, Sha256};
+    let sha256 = format!("{:x}", Sha256::digest(content.as_bytes()));
+    let backup = (backup && exists).then(|| backup::backup_path(path, &backup::stamp(std::time::SystemTime::now())));
+    Box::new(FileChange::MissingFile(MissingFile {
+        path: path.to_path_buf(),
+        content: FileContent::Content(content.into_bytes(), sha256),
+        owner: None,
+        group: None,
+        mode: None,
+        validate: validate.clone(),
+        backup,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(line: Option<&str>, pattern: Option<&str>, present: bool) -> Edit {
+        Edit::Line {
+            line: line.map(str::to_string),
+            pattern: pattern.map(str::to_string),
+            present,
+        }
+    }
+
+    fn block(content: &str, present: bool) -> Edit {
+        Edit::Block {
+            name: "cluster".into(),
+            content: content.into(),
+            comment: "#".into(),
+            present,
+        }
+    }
+
+    #[test]
+    fn a_matched_line_is_replaced_once() {
+        let sshd = "Port 22\n#PasswordAuthentication yes\nUsePAM yes\nPasswordAuthentication yes\n";
+        let edit = line(
+            Some("PasswordAuthentication no"),
+            Some("^#?PasswordAuthentication\\b"),
+            true,
+        );
+        assert_eq!(
+            apply_edits(sshd, &[edit]).unwrap(),
+            "Port 22\nPasswordAuthentication no\nUsePAM yes\n"
+        );
+    }
+
+    #[test]
+    fn a_file_with_crlf_endings_keeps_them() {
+        let ini = "[server]\r\nport=22\r\n";
+        let edits = [line(Some("port=2222"), Some("^port="), true), block("a=1\nb=2", true)];
+        let edited = apply_edits(ini, &edits).unwrap();
+        assert_eq!(
+            edited,
+            "[server]\r\nport=2222\r\n# BEGIN cook: cluster\r\na=1\r\nb=2\r\n# END cook: cluster\r\n"
+        );
+        assert_eq!(apply_edits(&edited, &edits).unwrap(), edited);
+    }
+
+    #[test]
+    fn a_missing_line_is_appended_and_a_present_one_left_alone() {
+        let hosts = "127.0.0.1 localhost";
+        let edit = line(Some("10.0.0.5 db"), None, true);
+        let edited = apply_edits(hosts, std::slice::from_ref(&edit)).unwrap();
+        assert_eq!(edited, "127.0.0.1 localhost\n10.0.0.5 db\n");
+        assert_eq!(apply_edits(&edited, &[edit]).unwrap(), edited);
+        // Nothing to do leaves even a missing final newline as it was.
+        let edit = line(Some("127.0.0.1 localhost"), None, true);
+        assert_eq!(apply_edits(hosts, &[edit]).unwrap(), hosts);
+    }
+
+    #[test]
+    fn absent_lines_are_removed_by_text_or_pattern() {
+        let env = "PATH=/usr/bin\nDEBUG=1\nDEBUG=2\n";
+        assert_eq!(
+            apply_edits(env, &[line(None, Some("^DEBUG="), false)]).unwrap(),
+            "PATH=/usr/bin\n"
+        );
+        assert_eq!(
+            apply_edits(env, &[line(Some("DEBUG=1"), None, false)]).unwrap(),
+            "PATH=/usr/bin\nDEBUG=2\n"
+        );
+    }
+
+    #[test]
+    fn a_block_is_added_replaced_and_removed() {
+        let hosts = "127.0.0.1 localhost\n";
+        let added = apply_edits(hosts, &[block("10.0.0.5 db", true)]).unwrap();
+        assert_eq!(
+            added,
+            "127.0.0.1 localhost\n# BEGIN cook: cluster\n10.0.0.5 db\n# END cook: cluster\n"
+        );
+        let replaced = apply_edits(&format!("{added}::1 localhost\n"), &[block("10.0.0.6 db", true)]).unwrap();
+        assert_eq!(
+            replaced,
+            "127.0.0.1 localhost\n# BEGIN cook: cluster\n10.0.0.6 db\n# END cook: cluster\n::1 localhost\n"
+        );
+        assert_eq!(apply_edits(&added, &[block("", false)]).unwrap(), hosts);
+    }
+
+    #[test]
+    fn a_block_without_its_end_marker_is_left_alone() {
+        let broken = "# BEGIN cook: cluster\n10.0.0.5 db\n";
+        let err = apply_edits(broken, &[block("10.0.0.6 db", true)]).unwrap_err();
+        assert!(err.contains("# END cook: cluster"), "{err}");
+    }
+}
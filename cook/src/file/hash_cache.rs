@@ -0,0 +1,332 @@
+//! A controller-side cache of the sha256 of files under `cp` sources.
+//!
+//! Hashing every file of every copied tree on every run is most of the time a
+//! large `cook up` spends before it connects to anything. A file whose size,
+//! mtime and inode are what they were when it was last hashed still has that
+//! hash, so it is looked up in `.cook/cache/sha256` instead of read again.
+//!
+//! Metadata only proves a file unchanged if a write would have changed it. A
+//! file written within the same timestamp tick as it was hashed can change
+//! without its mtime moving (git's "racy" index entries), so an entry whose
+//! mtime is that close to when it was hashed is not trusted, and the file is
+//! hashed again.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+/// How close an mtime may come to the time of hashing before the entry is
+/// ambiguous. Two seconds covers the coarsest timestamps in common use (FAT),
+/// as well as nanosecond ones that are only updated once per kernel tick.
+const RACY_NS: i128 = 2_000_000_000;
+
+/// The sha256 of a local file, read in one streaming pass.
+pub(crate) fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    sha256: String,
+    size: u64,
+    mtime_ns: i128,
+    inode: u64,
+    /// When the hash was taken, to tell whether `mtime_ns` can be trusted.
+    hashed_at_ns: i128,
+}
+
+impl Entry {
+    /// Whether `metadata` describes the file this entry hashed.
+    fn matches(&self, metadata: &Metadata) -> bool {
+        self.size == metadata.size
+            && self.mtime_ns == metadata.mtime_ns
+            && self.inode == metadata.inode
+            && self.hashed_at_ns - self.mtime_ns >= RACY_NS
+    }
+
+    /// `sha256 size mtime inode hashed_at path`, one entry per line. The path
+    /// goes last so it may contain spaces.
+    fn to_line(&self, path: &Path) -> Option<String> {
+        let path = path.to_str().filter(|p| !p.contains('\n'))?;
+        Some(format!(
+            "{} {} {} {} {} {path}\n",
+            self.sha256, self.size, self.mtime_ns, self.inode, self.hashed_at_ns
+        ))
+    }
+
+    fn from_line(line: &str) -> Option<(PathBuf, Entry)> {
+        let mut fields = line.splitn(6, ' ');
+        let sha256 = fields.next()?;
+        if sha256.len() != 64 || !sha256.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let entry = Entry {
+            sha256: sha256.to_string(),
+            size: fields.next()?.parse().ok()?,
+            mtime_ns: fields.next()?.parse().ok()?,
+            inode: fields.next()?.parse().ok()?,
+            hashed_at_ns: fields.next()?.parse().ok()?,
+        };
+        Some((PathBuf::from(fields.next()?), entry))
+    }
+}
+
+struct Metadata {
+    size: u64,
+    mtime_ns: i128,
+    inode: u64,
+}
+
+impl Metadata {
+    fn read(path: &Path) -> io::Result<Metadata> {
+        let metadata = fs::metadata(path)?;
+        Ok(Metadata {
+            size: metadata.len(),
+            mtime_ns: i128::from(metadata.mtime()) * 1_000_000_000 + i128::from(metadata.mtime_nsec()),
+            inode: metadata.ino(),
+        })
+    }
+}
+
+fn now_ns() -> i128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as i128)
+        .unwrap_or_default()
+}
+
+/// Entries read from a cache file. A missing file is an empty cache, and a
+/// line that does not parse — a cache from another version, or a torn write
+/// from before writes were atomic — is skipped, so the worst a bad cache can
+/// do is cost a rehash.
+fn load(file: &Path) -> HashMap<PathBuf, Entry> {
+    fs::read_to_string(file)
+        .map(|content| content.lines().filter_map(Entry::from_line).collect())
+        .unwrap_or_default()
+}
+
+pub(crate) struct HashCache {
+    file: PathBuf,
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+    /// Entries hashed this run, which are what `save` has to write.
+    fresh: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+impl HashCache {
+    /// The cache under `dir`, usually `<root>/.cook/cache`.
+    pub(crate) fn open(dir: &Path) -> HashCache {
+        let file = dir.join("sha256");
+        HashCache {
+            entries: Mutex::new(load(&file)),
+            fresh: Mutex::new(HashMap::new()),
+            file,
+        }
+    }
+
+    /// The sha256 of `path`, from the cache if its metadata vouches for the
+    /// cached hash, otherwise by reading it.
+    pub(crate) fn sha256(&self, path: &Path) -> io::Result<String> {
+        // Read before hashing: a write during the hash then leaves the entry
+        // with an mtime that no longer matches, instead of a stale hash under
+        // the new mtime.
+        let metadata = Metadata::read(path)?;
+        if let Some(entry) = self.entries.lock().unwrap().get(path)
+            && entry.matches(&metadata)
+        {
+            return Ok(entry.sha256.clone());
+        }
+        let hashed_at_ns = now_ns();
+        let sha256 = sha256_file(path)?;
+        let entry = Entry {
+            sha256: sha256.clone(),
+            size: metadata.size,
+            mtime_ns: metadata.mtime_ns,
+            inode: metadata.inode,
+            hashed_at_ns,
+        };
+        self.entries.lock().unwrap().insert(path.to_path_buf(), entry.clone());
+        self.fresh.lock().unwrap().insert(path.to_path_buf(), entry);
+        Ok(sha256)
+    }
+
+    /// Write this run's hashes back, if there are any.
+    ///
+    /// Another run may have saved since this one loaded, so the file is read
+    /// again and this run's entries laid over it rather than the whole cache
+    /// written from memory. The result goes to a temporary file renamed into
+    /// place: concurrent runs each replace the file whole, and a reader sees
+    /// one complete version or another. The unlucky run's entries are lost,
+    /// which costs the next run a rehash and nothing else.
+    pub(crate) fn save(&self) -> io::Result<()> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let fresh = std::mem::take(&mut *self.fresh.lock().unwrap());
+        if fresh.is_empty() {
+            return Ok(());
+        }
+        let mut entries = load(&self.file);
+        entries.extend(fresh);
+        // Forget files that are gone, so renames don't grow the cache forever.
+        entries.retain(|path, _| path.exists());
+
+        let dir = self.file.parent().expect("the cache file is in a directory");
+        fs::create_dir_all(dir)?;
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let tmp = dir.join(format!(".sha256.{}-{n}", std::process::id()));
+        let written = (|| {
+            let mut out = io::BufWriter::new(fs::File::create(&tmp)?);
+            let mut paths: Vec<_> = entries.keys().collect();
+            paths.sort();
+            for path in paths {
+                if let Some(line) = entries[path].to_line(path) {
+                    out.write_all(line.as_bytes())?;
+                }
+            }
+            out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            fs::rename(&tmp, &self.file)
+        })();
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cook-hash-cache-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Push the file's mtime back, out of the racy window, the way it would
+    /// be for any file not written in the last couple of seconds.
+    fn age(path: &Path) {
+        let old = SystemTime::now() - std::time::Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+    }
+
+    fn cached_sha(cache: &HashCache, path: &Path) -> Option<String> {
+        cache.entries.lock().unwrap().get(path).map(|e| e.sha256.clone())
+    }
+
+    #[test]
+    fn a_file_is_hashed_across_reads() {
+        let dir = scratch("streamed");
+        let path = dir.join("asset");
+        // Several times the size of one read.
+        let content = vec![b'x'; 200_000];
+        fs::write(&path, &content).unwrap();
+        assert_eq!(sha256_file(&path).unwrap(), format!("{:x}", Sha256::digest(&content)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn an_unchanged_file_is_not_read_again() {
+        let dir = scratch("unchanged");
+        let path = dir.join("asset");
+        fs::write(&path, "one").unwrap();
+        age(&path);
+        let cache = HashCache::open(&dir.join("cache"));
+        let sha = cache.sha256(&path).unwrap();
+        cache.save().unwrap();
+
+        // Plant a wrong hash: if the reopened cache returns it, the file was
+        // not read.
+        let file = dir.join("cache/sha256");
+        let planted = fs::read_to_string(&file).unwrap().replacen(&sha, &"0".repeat(64), 1);
+        fs::write(&file, planted).unwrap();
+        let cache = HashCache::open(&dir.join("cache"));
+        assert_eq!(cache.sha256(&path).unwrap(), "0".repeat(64));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_changed_file_is_hashed_again() {
+        let dir = scratch("changed");
+        let path = dir.join("asset");
+        fs::write(&path, "one").unwrap();
+        age(&path);
+        let cache = HashCache::open(&dir.join("cache"));
+        let before = cache.sha256(&path).unwrap();
+        fs::write(&path, "two!").unwrap();
+        age(&path);
+        let after = cache.sha256(&path).unwrap();
+        assert_ne!(before, after);
+        assert_eq!(after, sha256_file(&path).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_file_modified_as_it_was_hashed_is_not_trusted() {
+        let dir = scratch("racy");
+        let path = dir.join("asset");
+        fs::write(&path, "one").unwrap();
+        let cache = HashCache::open(&dir.join("cache"));
+        cache.sha256(&path).unwrap();
+        let entry = cache.entries.lock().unwrap()[&path].clone();
+        let metadata = Metadata::read(&path).unwrap();
+        assert!(!entry.matches(&metadata), "mtime within the racy window");
+
+        // Same size and mtime, different content: only a rehash notices.
+        cache.entries.lock().unwrap().get_mut(&path).unwrap().sha256 = "0".repeat(64);
+        assert_eq!(cache.sha256(&path).unwrap(), sha256_file(&path).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn saving_keeps_what_another_run_saved() {
+        let dir = scratch("merge");
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+        let first = HashCache::open(&dir.join("cache"));
+        let second = HashCache::open(&dir.join("cache"));
+        first.sha256(&a).unwrap();
+        second.sha256(&b).unwrap();
+        first.save().unwrap();
+        second.save().unwrap();
+        let reopened = HashCache::open(&dir.join("cache"));
+        assert!(cached_sha(&reopened, &a).is_some());
+        assert!(cached_sha(&reopened, &b).is_some());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_damaged_cache_is_ignored_line_by_line() {
+        let dir = scratch("damaged");
+        let path = dir.join("with space");
+        let entry = Entry {
+            sha256: "a".repeat(64),
+            size: 1,
+            mtime_ns: 2,
+            inode: 3,
+            hashed_at_ns: 4,
+        };
+        let line = entry.to_line(&path).unwrap();
+        fs::create_dir_all(dir.join("cache")).unwrap();
+        fs::write(dir.join("cache/sha256"), format!("garbage\n{line}{}", &line[..20])).unwrap();
+        let entries = load(&dir.join("cache/sha256"));
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[&path], entry);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
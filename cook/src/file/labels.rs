@@ -0,0 +1,450 @@
+//! What a file carries besides its content, mode and owner: an SELinux
+//! context, ACL entries and extended attributes.
+//!
+//! ```kdl
+//! cp site /srv/www selinux_context=httpd_sys_content_t {
+//!     acl { user deploy rwx; group web r-x }
+//!     xattr { user.origin cook }
+//! }
+//! ```
+//!
+//! A context with colons is a whole context and is set as given; a bare name
+//! is a type, set with `chcon -t` and compared against the type field only.
+//! ACL entries and attributes are added to whatever the file already has —
+//! an entry cook wasn't told about is left alone, not removed.
+//!
+//! Labels are read for every file under a `cp` root in one pass per tool
+//! (`ls -Z`, `getfacl`, `getfattr`), the same way modes are.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+use kdl::KdlNode;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ssh")]
+use crate::Error;
+use crate::sh_single_quote;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Labels {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selinux_context: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub acl: Vec<AclEntry>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattr: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AclEntry {
+    /// `user` or `group`.
+    pub kind: String,
+    pub name: String,
+    /// In `getfacl`'s form, e.g. `r-x`.
+    pub perms: String,
+}
+
+impl AclEntry {
+    /// The entry as `setfacl -m` takes it, e.g. `u:deploy:rwx`.
+    fn spec(&self) -> String {
+        format!("{}:{}:{}", &self.kind[..1], self.name, self.perms)
+    }
+}
+
+impl Labels {
+    pub fn is_empty(&self) -> bool {
+        self.selinux_context.is_none() && self.acl.is_empty() && self.xattr.is_empty()
+    }
+
+    /// The part of `self` that `current` doesn't satisfy: empty when the
+    /// file is labelled as it should be. No `current` means the file is new
+    /// or its labels unknown, so all of it.
+    pub(crate) fn missing_from(&self, current: Option<&Labels>) -> Labels {
+        let Some(current) = current else {
+            return self.clone();
+        };
+        let selinux_context = self
+            .selinux_context
+            .as_ref()
+            .filter(|wanted| !context_matches(wanted, current.selinux_context.as_deref()))
+            .cloned();
+        let acl = self
+            .acl
+            .iter()
+            .filter(|entry| !current.acl.contains(entry))
+            .cloned()
+            .collect();
+        let xattr = self
+            .xattr
+            .iter()
+            .filter(|(name, value)| current.xattr.get(*name) != Some(value))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        Labels {
+            selinux_context,
+            acl,
+            xattr,
+        }
+    }
+
+    /// Read `acl` and `xattr` children of a `file` or `cp` node. Returns
+    /// whether `child` was one.
+    pub(crate) fn parse_child(&mut self, child: &KdlNode, keyword: &str) -> bool {
+        match child.name().value() {
+            "acl" => {
+                for entry in child.children().map(|c| c.nodes()).unwrap_or_default() {
+                    let kind = entry.name().value();
+                    assert!(
+                        matches!(kind, "user" | "group"),
+                        "{keyword}: acl entries are `user <name> <perms>` or `group <name> <perms>`, not {kind}"
+                    );
+                    let [name, perms] = entry.entries() else {
+                        panic!("{keyword}: acl {kind} takes a name and permissions, e.g. `{kind} alice rwx`");
+                    };
+                    self.acl.push(AclEntry {
+                        kind: kind.to_string(),
+                        name: name.expect_str().to_string(),
+                        perms: parse_perms(perms.expect_str(), keyword),
+                    });
+                }
+                true
+            }
+            "xattr" => {
+                for entry in child.children().map(|c| c.nodes()).unwrap_or_default() {
+                    let [value] = entry.entries() else {
+                        panic!("{keyword}: xattr {} takes one value", entry.name().value());
+                    };
+                    self.xattr
+                        .insert(entry.name().value().to_string(), value.expect_str().to_string());
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// `rx` or `r-x` as `r-x`.
+fn parse_perms(perms: &str, keyword: &str) -> String {
+    let valid = perms.chars().all(|c| matches!(c, 'r' | 'w' | 'x' | '-')) && perms.len() <= 3;
+    assert!(
+        valid,
+        "{keyword}: acl permissions are some of rwx, e.g. r-x, not {perms}"
+    );
+    ['r', 'w', 'x']
+        .map(|p| if perms.contains(p) { p } else { '-' })
+        .iter()
+        .collect()
+}
+
+/// Whether the context a file has satisfies the one wanted: equal, or for a
+/// bare type, with that type.
+fn context_matches(wanted: &str, current: Option<&str>) -> bool {
+    let Some(current) = current else {
+        return false;
+    };
+    if wanted.contains(':') {
+        wanted == current
+    } else {
+        current.split(':').nth(2) == Some(wanted)
+    }
+}
+
+/// Undo the `\ooo` escapes `getfacl` and `getfattr` put in file names.
+fn unescape(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(octal) = s.get(i + 1..i + 4)
+            && octal.bytes().all(|b| matches!(b, b'0'..=b'7'))
+            && let Ok(byte) = u8::from_str_radix(octal, 8)
+        {
+            out.push(byte);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// `ls -Zd` output: `<context> <path>` per line.
+pub(crate) fn parse_contexts(output: &str, labels: &mut HashMap<String, Labels>) {
+    for line in output.lines() {
+        if let Some((context, path)) = line.split_once(' ') {
+            labels.entry(path.to_string()).or_default().selinux_context = Some(context.to_string());
+        }
+    }
+}
+
+/// The `# file:` blocks `getfacl` and `getfattr` both print, as
+/// (path, lines) pairs.
+fn blocks(output: &str) -> Vec<(String, Vec<&str>)> {
+    let mut blocks: Vec<(String, Vec<&str>)> = Vec::new();
+    for line in output.lines() {
+        if let Some(path) = line.strip_prefix("# file: ") {
+            blocks.push((unescape(path), Vec::new()));
+        } else if let Some((_, lines)) = blocks.last_mut()
+            && !line.is_empty()
+            && !line.starts_with('#')
+        {
+            lines.push(line);
+        }
+    }
+    blocks
+}
+
+/// `getfacl -pE` output. Only named `user:` and `group:` entries are kept;
+/// the owner's, group's and other's come from the mode.
+pub(crate) fn parse_acls(output: &str, labels: &mut HashMap<String, Labels>) {
+    for (path, lines) in blocks(output) {
+        let acl = &mut labels.entry(path).or_default().acl;
+        for line in lines {
+            let mut parts = line.splitn(3, ':');
+            if let (Some(kind @ ("user" | "group")), Some(name), Some(perms)) =
+                (parts.next(), parts.next(), parts.next())
+                && !name.is_empty()
+            {
+                acl.push(AclEntry {
+                    kind: kind.to_string(),
+                    name: unescape(name),
+                    perms: perms.trim().to_string(),
+                });
+            }
+        }
+    }
+}
+
+/// `getfattr -d -m - -e hex` output: `name=0x<hex>` lines.
+pub(crate) fn parse_xattrs(output: &str, labels: &mut HashMap<String, Labels>) {
+    for (path, lines) in blocks(output) {
+        let xattr = &mut labels.entry(path).or_default().xattr;
+        for line in lines {
+            let Some((name, value)) = line.split_once('=') else {
+                continue;
+            };
+            let Some(hex) = value.strip_prefix("0x") else {
+                continue;
+            };
+            let bytes: Option<Vec<u8>> = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                .collect();
+            if let Some(bytes) = bytes {
+                xattr.insert(name.to_string(), String::from_utf8_lossy(&bytes).into_owned());
+            }
+        }
+    }
+}
+
+/// The labels of the files `find` finds from `target`, in one round-trip,
+/// reading only what some file in `wanted` asks about. `-maxdepth 0` makes
+/// the same script work for a single file.
+#[cfg(feature = "ssh")]
+pub(crate) async fn read_labels<'a>(
+    session: &openssh::Session,
+    target: &str,
+    single: bool,
+    wanted: impl Iterator<Item = &'a Labels>,
+) -> Result<HashMap<String, Labels>, Error> {
+    let (mut selinux, mut acl, mut xattr) = (false, false, false);
+    for labels in wanted {
+        selinux |= labels.selinux_context.is_some();
+        acl |= !labels.acl.is_empty();
+        xattr |= !labels.xattr.is_empty();
+    }
+    let find = format!(
+        "find {}{} -type f -exec",
+        sh_single_quote(target),
+        if single { " -maxdepth 0" } else { "" }
+    );
+    let commands = [
+        (selinux, "ls -Zd --"),
+        (acl, "getfacl -pE --"),
+        (xattr, "getfattr -d -m - -e hex --absolute-names --"),
+    ];
+    let script = commands
+        .iter()
+        .map(|(wanted, command)| match wanted {
+            true => format!("{find} {command} {{}} + 2>/dev/null"),
+            false => "true".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("; printf '\\0'; ");
+    let output = session.command("sh").arg("-c").arg(&script).output().await?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut parts = stdout.split('\0');
+    let mut labels = HashMap::new();
+    parse_contexts(parts.next().unwrap_or_default(), &mut labels);
+    parse_acls(parts.next().unwrap_or_default(), &mut labels);
+    parse_xattrs(parts.next().unwrap_or_default(), &mut labels);
+    Ok(labels)
+}
+
+/// The commands that give `path` `labels`.
+pub(crate) fn label_script(path: &str, labels: &Labels) -> String {
+    let p = sh_single_quote(path);
+    let mut commands = Vec::new();
+    if let Some(context) = &labels.selinux_context {
+        if context.contains(':') {
+            commands.push(format!("chcon {} -- {p}", sh_single_quote(context)));
+        } else {
+            commands.push(format!("chcon -t {} -- {p}", sh_single_quote(context)));
+        }
+    }
+    if !labels.acl.is_empty() {
+        let specs: Vec<String> = labels.acl.iter().map(AclEntry::spec).collect();
+        commands.push(format!("setfacl -m {} -- {p}", sh_single_quote(&specs.join(","))));
+    }
+    for (name, value) in &labels.xattr {
+        // Hex, so a value that happens to start with `0x` or `0s` isn't
+        // read as already encoded.
+        let hex: String = value.bytes().map(|b| format!("{b:02x}")).collect();
+        commands.push(format!("setfattr -n {} -v 0x{hex} -- {p}", sh_single_quote(name)));
+    }
+    commands.join(" && ")
+}
+
+/// Labels a file is missing: all of them for a file just uploaded, which
+/// replaces whatever the old one had.
+#[derive(Debug, Serialize)]
+pub struct WrongLabels {
+    pub(crate) path: PathBuf,
+    pub(crate) labels: Labels,
+}
+
+#[cfg(feature = "ssh")]
+impl WrongLabels {
+    pub(crate) async fn apply_ssh(&self, session: &openssh::Session) -> Result<(), Error> {
+        let path = self.path.to_str().ok_or("file path is not valid utf-8")?;
+        let output = session
+            .command("sh")
+            .arg("-c")
+            .arg(label_script(path, &self.labels))
+            .output()
+            .await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(format!("labelling {path} failed: {}", stderr.trim()).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn acl(kind: &str, name: &str, perms: &str) -> AclEntry {
+        AclEntry {
+            kind: kind.into(),
+            name: name.into(),
+            perms: perms.into(),
+        }
+    }
+
+    #[test]
+    fn permissions_are_normalized() {
+        assert_eq!(parse_perms("rx", "cp"), "r-x");
+        assert_eq!(parse_perms("rwx", "cp"), "rwx");
+        assert_eq!(parse_perms("-", "cp"), "---");
+    }
+
+    #[test]
+    #[should_panic(expected = "acl permissions are some of rwx")]
+    fn other_permissions_are_rejected() {
+        parse_perms("rws", "cp");
+    }
+
+    #[test]
+    fn a_bare_type_only_compares_the_type() {
+        assert!(context_matches(
+            "httpd_sys_content_t",
+            Some("system_u:object_r:httpd_sys_content_t:s0")
+        ));
+        assert!(!context_matches(
+            "httpd_sys_content_t",
+            Some("unconfined_u:object_r:var_t:s0")
+        ));
+        assert!(!context_matches(
+            "system_u:object_r:httpd_sys_content_t:s0",
+            Some("unconfined_u:object_r:httpd_sys_content_t:s0")
+        ));
+        // SELinux disabled: `ls -Z` prints `?`.
+        assert!(!context_matches("httpd_sys_content_t", Some("?")));
+    }
+
+    #[test]
+    fn labels_are_read_per_file() {
+        let mut labels = HashMap::new();
+        parse_contexts(
+            "system_u:object_r:httpd_sys_content_t:s0 /srv/www/a b.html\n",
+            &mut labels,
+        );
+        parse_acls(
+            "# file: /srv/www/a\\040b.html\n# owner: root\n# group: root\nuser::rw-\nuser:deploy:rwx\ngroup::r--\n\
+             group:web:r-x\nmask::rwx\nother::r--\n\n",
+            &mut labels,
+        );
+        parse_xattrs(
+            "# file: /srv/www/a\\040b.html\nuser.origin=0x636f6f6b\nsecurity.selinux=0x73797374656d5f7500\n\n",
+            &mut labels,
+        );
+        let file = &labels["/srv/www/a b.html"];
+        assert_eq!(
+            file.selinux_context.as_deref(),
+            Some("system_u:object_r:httpd_sys_content_t:s0")
+        );
+        assert_eq!(file.acl, vec![acl("user", "deploy", "rwx"), acl("group", "web", "r-x")]);
+        assert_eq!(file.xattr["user.origin"], "cook");
+    }
+
+    #[test]
+    fn only_what_differs_is_applied() {
+        let wanted = Labels {
+            selinux_context: Some("httpd_sys_content_t".into()),
+            acl: vec![acl("user", "deploy", "rwx"), acl("group", "web", "r-x")],
+            xattr: BTreeMap::from([("user.origin".into(), "cook".into())]),
+        };
+        let current = Labels {
+            selinux_context: Some("system_u:object_r:httpd_sys_content_t:s0".into()),
+            acl: vec![acl("user", "deploy", "r--"), acl("group", "web", "r-x")],
+            xattr: BTreeMap::from([("user.origin".into(), "cook".into())]),
+        };
+        let missing = wanted.missing_from(Some(&current));
+        assert_eq!(
+            missing,
+            Labels {
+                acl: vec![acl("user", "deploy", "rwx")],
+                ..Labels::default()
+            }
+        );
+        assert_eq!(
+            label_script("/srv/www/it's", &missing),
+            "setfacl -m 'u:deploy:rwx' -- '/srv/www/it'\\''s'"
+        );
+        let fixed = Labels {
+            acl: wanted.acl.clone(),
+            ..current
+        };
+        assert!(wanted.missing_from(Some(&fixed)).is_empty());
+        assert_eq!(wanted.missing_from(None), wanted);
+    }
+
+    #[test]
+    fn the_script_sets_each_kind_of_label() {
+        let labels = Labels {
+            selinux_context: Some("httpd_sys_content_t".into()),
+            acl: Vec::new(),
+            xattr: BTreeMap::from([("user.origin".into(), "cook".into())]),
+        };
+        assert_eq!(
+            label_script("/a", &labels),
+            "chcon -t 'httpd_sys_content_t' -- '/a' && setfattr -n 'user.origin' -v 0x636f6f6b -- '/a'"
+        );
+    }
+}
@@ -0,0 +1,269 @@
+//! `link <path> <target>` — a symlink at `path` pointing at `target`.
+//!
+//! `file <path> link=<target>` means the same thing. The target is compared
+//! as `readlink` prints it, without resolving it: `current -> releases/a` and
+//! `current -> /srv/app/releases/a` are different links even when they land
+//! on the same directory, and switching between them is a change.
+
+use std::path::PathBuf;
+
+use kdl::KdlNode;
+use serde::{Deserialize, Serialize};
+
+use crate::file::spec::FileChange;
+use crate::{Context, Error, FromKdl, Modification, Rule, RuleOverSsh, State};
+
+#[cfg(feature = "ssh")]
+use crate::sh_single_quote;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LinkSpec {
+    pub path: PathBuf,
+    /// Written into the link verbatim; a relative target resolves against the
+    /// link's own directory, as with `ln -s`.
+    pub target: String,
+    /// Replace a link pointing elsewhere, or a regular file in the way.
+    /// Without it either is an error, so a typo in a path cannot quietly
+    /// replace something cook did not put there. A directory in the way is
+    /// always an error.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub force: bool,
+}
+
+impl FromKdl for LinkSpec {
+    fn kdl_keywords() -> &'static [&'static str] {
+        &["link"]
+    }
+
+    /// Also reached from `file` when it has a `link=` property.
+    fn add_rules_to_state(state: &mut State, node: &KdlNode, _context: &Context) {
+        let keyword = node.name().value();
+        let mut args = Vec::new();
+        let mut target = None;
+        let mut force = false;
+        for entry in node.entries() {
+            match entry.name().map(|i| i.value()) {
+                None => args.push(entry.expect_str()),
+                Some("link") if keyword == "file" => target = Some(entry.expect_str().to_string()),
+                Some("force") => {
+                    force = entry
+                        .value()
+                        .as_bool()
+                        .unwrap_or_else(|| panic!("{keyword}: force must be true or false"))
+                }
+                Some(z) => panic!("Unexpected option for {keyword}: {z}"),
+            }
+        }
+        let mut args = args.into_iter();
+        let path = PathBuf::from(args.next().unwrap_or_else(|| panic!("{keyword} requires a path")));
+        if keyword == "link" {
+            target = Some(args.next().expect("link requires a target").to_string());
+        }
+        if let Some(extra) = args.next() {
+            panic!("{keyword}: unexpected argument {extra}");
+        }
+        state.add_rule(LinkSpec {
+            path,
+            target: target.expect("a link needs a target"),
+            force,
+        });
+    }
+}
+
+impl Rule for LinkSpec {
+    #[cfg(feature = "ssh")]
+    fn downcast_ssh(&self) -> Option<&dyn RuleOverSsh> {
+        Some(self)
+    }
+
+    fn check(&self) -> Result<Vec<Box<dyn Modification>>, Error> {
+        todo!()
+    }
+
+    fn kind(&self) -> &'static str {
+        "file"
+    }
+
+    fn identifier(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+/// What is at a link's path on the host.
+#[cfg(feature = "ssh")]
+#[derive(Debug, PartialEq, Eq)]
+enum RemotePath {
+    Missing,
+    Link(String),
+    File,
+    Directory,
+}
+
+#[cfg(feature = "ssh")]
+impl RemotePath {
+    /// One round-trip for what is at `path`. `-L` comes first: `-d` and `-e`
+    /// follow links, and a link to a directory is still a link.
+    fn script(path: &str) -> String {
+        format!(
+            "if [ -L {p} ]; then echo link; readlink {p}; elif [ -d {p} ]; then echo directory; \
+             elif [ -e {p} ]; then echo file; else echo missing; fi",
+            p = sh_single_quote(path)
+        )
+    }
+
+    fn parse(stdout: &str) -> Result<RemotePath, Error> {
+        let mut lines = stdout.lines();
+        match lines.next().map(str::trim) {
+            Some("missing") => Ok(RemotePath::Missing),
+            Some("directory") => Ok(RemotePath::Directory),
+            Some("file") => Ok(RemotePath::File),
+            // Not trimmed: a link target may legitimately end in whitespace.
+            Some("link") => Ok(RemotePath::Link(lines.next().unwrap_or_default().to_string())),
+            _ => Err(format!("unexpected link check output {stdout:?}").into()),
+        }
+    }
+}
+
+#[cfg(feature = "ssh")]
+#[async_trait::async_trait]
+impl RuleOverSsh for LinkSpec {
+    async fn check_ssh(&self, session: &openssh::Session) -> Result<Vec<Box<dyn Modification>>, Error> {
+        let path = self.path.to_str().ok_or("link path is not valid utf-8")?;
+        let output = session
+            .command("sh")
+            .arg("-c")
+            .arg(RemotePath::script(path))
+            .output()
+            .await?;
+        let current = match RemotePath::parse(&String::from_utf8_lossy(&output.stdout))? {
+            RemotePath::Missing => {
+                return Ok(vec![Box::new(FileChange::MissingSymlink(MissingSymlink {
+                    path: self.path.clone(),
+                    target: self.target.clone(),
+                }))]);
+            }
+            RemotePath::Link(current) if current == self.target => return Ok(Vec::new()),
+            RemotePath::Directory => {
+                return Err(format!("{path} is a directory on the host; cook will not replace it with a link").into());
+            }
+            RemotePath::Link(current) => current,
+            RemotePath::File => "a regular file".to_string(),
+        };
+        if !self.force {
+            return Err(format!(
+                "{path} should link to {} but is {current}; set force=#true to replace it",
+                self.target
+            )
+            .into());
+        }
+        Ok(vec![Box::new(FileChange::WrongSymlink(WrongSymlink {
+            path: self.path.clone(),
+            target: self.target.clone(),
+            current,
+        }))])
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MissingSymlink {
+    pub path: PathBuf,
+    pub target: String,
+}
+
+/// Something other than the right link is at the path, and the rule is
+/// allowed to replace it.
+#[derive(Debug, Serialize)]
+pub struct WrongSymlink {
+    pub path: PathBuf,
+    pub target: String,
+    /// The link's current target, or "a regular file", for readable output.
+    pub current: String,
+}
+
+/// Point `path` at `target`, replacing whatever link or file is there.
+///
+/// The link is made beside `path` and renamed over it, so a reader following
+/// `current` during a release switch sees the old target or the new one, never
+/// a missing link.
+#[cfg(feature = "ssh")]
+pub(crate) async fn ln_sf(session: &openssh::Session, path: &std::path::Path, target: &str) -> Result<(), Error> {
+    let path_str = path.to_str().ok_or("link path is not valid utf-8")?;
+    let tmp = crate::file::atomic::temp_path(path)?;
+    let script = ln_sf_script(path_str, target, &tmp);
+    let output = session.command("sh").arg("-c").arg(&script).output().await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("linking {path_str} -> {target} failed: {}", stderr.trim()).into());
+    }
+    Ok(())
+}
+
+/// The script behind [`ln_sf`], with `tmp` the temporary link's path.
+///
+/// A plain `mv -f` renames onto anything that is not a directory. Onto a link
+/// to a directory it would move the new link *into* that directory instead,
+/// so there it takes `-T` (GNU, BusyBox) or `-h` (BSD, macOS), which rename
+/// over the link itself. A real directory in the way is refused outright.
+#[cfg(feature = "ssh")]
+fn ln_sf_script(path: &str, target: &str, tmp: &str) -> String {
+    let parent = std::path::Path::new(path)
+        .parent()
+        .and_then(|p| p.to_str())
+        .filter(|p| !p.is_empty())
+        .unwrap_or("/");
+    format!(
+        "if [ -d {p} ] && [ ! -L {p} ]; then echo {p} is a directory >&2; exit 1; fi; \
+         mkdir -p {parent} && ln -sfn {target} {t} || {{ rm -f {t}; exit 1; }}; \
+         if [ -d {p} ]; then mv -Tf {t} {p} 2>/dev/null || mv -hf {t} {p}; else mv -f {t} {p}; fi \
+         || {{ rm -f {t}; exit 1; }}",
+        p = sh_single_quote(path),
+        parent = sh_single_quote(parent),
+        target = sh_single_quote(target),
+        t = sh_single_quote(tmp),
+    )
+}
+
+#[cfg(all(test, feature = "ssh"))]
+mod tests {
+    use super::{RemotePath, ln_sf_script};
+
+    #[test]
+    fn check_output_is_parsed() {
+        assert_eq!(RemotePath::parse("missing\n").unwrap(), RemotePath::Missing);
+        assert_eq!(RemotePath::parse("file\n").unwrap(), RemotePath::File);
+        assert_eq!(RemotePath::parse("directory\n").unwrap(), RemotePath::Directory);
+        assert_eq!(
+            RemotePath::parse("link\nreleases/2026-10-18\n").unwrap(),
+            RemotePath::Link("releases/2026-10-18".to_string())
+        );
+        assert!(RemotePath::parse("").is_err());
+    }
+
+    /// `test -d` follows links, so a link to a directory has to be recognised
+    /// as a link first — otherwise every release switch would be refused as
+    /// "a directory in the way".
+    #[test]
+    fn the_link_test_comes_before_the_directory_test() {
+        let script = RemotePath::script("/srv/app/current");
+        assert!(script.find("-L").unwrap() < script.find("-d").unwrap(), "got: {script}");
+    }
+
+    /// Only a link to a directory needs a flag to be renamed over; the
+    /// GNU-only `-T` is tried first and never used on its own.
+    #[test]
+    fn the_rename_is_portable() {
+        let script = ln_sf_script("/srv/app/current", "releases/b", "/srv/app/.current.cook-1-0");
+        assert!(
+            script.contains(
+                "mv -Tf '/srv/app/.current.cook-1-0' '/srv/app/current' 2>/dev/null \
+                 || mv -hf '/srv/app/.current.cook-1-0' '/srv/app/current'; \
+                 else mv -f '/srv/app/.current.cook-1-0' '/srv/app/current'; fi"
+            ),
+            "got: {script}"
+        );
+        assert!(
+            script.contains("ln -sfn 'releases/b' '/srv/app/.current.cook-1-0'"),
+            "got: {script}"
+        );
+    }
+}
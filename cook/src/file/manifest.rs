@@ -0,0 +1,277 @@
+//! What cook last shipped to a `cp` root, kept on the host.
+//!
+//! Hashing a whole tree over ssh on every run is what makes checking a large
+//! `cp` slow. After a `cp` is applied, cook writes the hash, size, mtime and
+//! mode of every file it placed to a manifest under [`MANIFEST_DIR`]. The next
+//! check lists sizes and mtimes, which is cheap, and trusts the manifest for
+//! any file whose size and mtime are still the ones it recorded; only the
+//! rest are hashed.
+//!
+//! A manifest looks like:
+//!
+//! ```text
+//! # cook manifest for /srv/site
+//! written 1792413296
+//! <sha256> <size> <mtime> <mode> /srv/site/index.html
+//! ```
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+#[cfg(feature = "ssh")]
+use crate::Error;
+#[cfg(feature = "ssh")]
+use crate::sh_single_quote;
+
+pub const MANIFEST_DIR: &str = "/var/lib/cook/manifest";
+
+/// Where the manifest for `root` lives. `/` and `%` in the root are escaped,
+/// so `/srv` and `/srv/site` get a file each rather than a file and a
+/// directory of the same name.
+pub(crate) fn manifest_path(root: &str) -> String {
+    let name = root.trim_start_matches('/').replace('%', "%25").replace('/', "%2F");
+    format!("{MANIFEST_DIR}/{name}")
+}
+
+/// Size, mtime (whole seconds) and mode of a file on the host, from
+/// `stat -c '%s %Y %a  %n'`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Stat {
+    pub(crate) size: u64,
+    pub(crate) mtime: i64,
+    pub(crate) mode: u32,
+}
+
+/// The format string for [`parse_stats`]. The two-space separator before the
+/// name mirrors `sha256sum`'s, so paths with spaces survive.
+pub(crate) const STAT_FORMAT: &str = "%s %Y %a  %n";
+
+pub(crate) fn parse_stats(output: &str) -> HashMap<String, Stat> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (fields, path) = line.split_once("  ")?;
+            let mut fields = fields.split(' ');
+            let stat = Stat {
+                size: fields.next()?.parse().ok()?,
+                mtime: fields.next()?.parse().ok()?,
+                mode: u32::from_str_radix(fields.next()?, 8).ok()?,
+            };
+            Some((path.to_string(), stat))
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    sha256: String,
+    stat: Stat,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Manifest {
+    /// The host's clock when the files were listed, in seconds.
+    written: i64,
+    entries: HashMap<String, Entry>,
+}
+
+impl Manifest {
+    /// A manifest in the form [`Manifest::render`] writes. Anything else —
+    /// including no manifest at all — reads as empty, which only costs a
+    /// full hash.
+    pub(crate) fn parse(text: &str) -> Manifest {
+        let mut manifest = Manifest::default();
+        for line in text.lines() {
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some(written) = line.strip_prefix("written ") {
+                manifest.written = written.trim().parse().unwrap_or(0);
+                continue;
+            }
+            let mut fields = line.splitn(5, ' ');
+            let entry = (|| {
+                let sha256 = fields.next()?.to_string();
+                let stat = Stat {
+                    size: fields.next()?.parse().ok()?,
+                    mtime: fields.next()?.parse().ok()?,
+                    mode: u32::from_str_radix(fields.next()?, 8).ok()?,
+                };
+                Some((fields.next()?.to_string(), Entry { sha256, stat }))
+            })();
+            if let Some((path, entry)) = entry {
+                manifest.entries.insert(path, entry);
+            }
+        }
+        manifest
+    }
+
+    /// The manifest for `files` (path and the hash shipped), as they stand
+    /// in `stats`. A file that wasn't listed is left out, to be hashed.
+    pub(crate) fn new(written: i64, files: &[(PathBuf, String)], stats: &HashMap<String, Stat>) -> Manifest {
+        let entries = files
+            .iter()
+            .filter_map(|(path, sha256)| {
+                let path = path.to_str()?;
+                let stat = *stats.get(path)?;
+                Some((
+                    path.to_string(),
+                    Entry {
+                        sha256: sha256.clone(),
+                        stat,
+                    },
+                ))
+            })
+            .collect();
+        Manifest { written, entries }
+    }
+
+    pub(crate) fn render(&self, root: &str) -> String {
+        let mut paths: Vec<&String> = self.entries.keys().collect();
+        paths.sort();
+        let mut out = format!("# cook manifest for {root}\nwritten {}\n", self.written);
+        for path in paths {
+            let Entry { sha256, stat } = &self.entries[path];
+            out.push_str(&format!(
+                "{sha256} {} {} {:o} {path}\n",
+                stat.size, stat.mtime, stat.mode
+            ));
+        }
+        out
+    }
+
+    /// The recorded hash of `path`, if the file still has the size and mtime
+    /// it had when the manifest was written. A file modified in the second
+    /// the manifest was written could have changed without its mtime doing
+    /// so, and is not vouched for.
+    pub(crate) fn sha256(&self, path: &str, stat: &Stat) -> Option<&str> {
+        let entry = self.entries.get(path)?;
+        (entry.stat.size == stat.size && entry.stat.mtime == stat.mtime && entry.stat.mtime < self.written)
+            .then_some(entry.sha256.as_str())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Record what a `cp` put on the host, once the rest of its changes have
+/// gone through.
+#[derive(Debug, Serialize)]
+pub struct WriteManifest {
+    pub(crate) root: PathBuf,
+    /// Each managed file with a known hash, and that hash.
+    #[serde(skip)]
+    pub(crate) files: Vec<(PathBuf, String)>,
+    /// Nothing else in the tree changed: the manifest is only brought up to
+    /// date, which is not a change to report.
+    #[serde(skip)]
+    pub(crate) refresh_only: bool,
+}
+
+#[cfg(feature = "ssh")]
+impl WriteManifest {
+    pub(crate) async fn apply_ssh(&self, session: &std::sync::Arc<openssh::Session>) -> Result<(), Error> {
+        let root = self.root.to_str().ok_or("root path is not valid utf-8")?;
+        // The clock is read before the listing, so a file changed during it
+        // has an mtime no earlier than `written` and is never vouched for.
+        // Files uploaded in this same second aren't either: the next check
+        // hashes them once and writes a manifest that does vouch for them.
+        let script = format!(
+            "mkdir -p {dir} && date +%s && find {root} -type f -exec stat -c '{STAT_FORMAT}' {{}} + 2>/dev/null",
+            dir = sh_single_quote(MANIFEST_DIR),
+            root = sh_single_quote(root),
+        );
+        let output = session.command("sh").arg("-c").arg(&script).output().await?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let (written, listing) = stdout.split_once('\n').unwrap_or((&stdout, ""));
+        let written: i64 = written.trim().parse().map_err(|_| {
+            format!(
+                "could not read the host's clock: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+        })?;
+        let manifest = Manifest::new(written, &self.files, &parse_stats(listing));
+        let attributes = crate::file::atomic::Attributes {
+            mode: Some(0o600),
+            ..Default::default()
+        };
+        crate::file::atomic::upload(
+            session,
+            std::path::Path::new(&manifest_path(root)),
+            manifest.render(root).as_bytes(),
+            &attributes,
+            &crate::file::atomic::Safeguards::default(),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stat(size: u64, mtime: i64) -> Stat {
+        Stat {
+            size,
+            mtime,
+            mode: 0o644,
+        }
+    }
+
+    #[test]
+    fn nested_roots_get_their_own_manifests() {
+        assert_eq!(manifest_path("/srv"), "/var/lib/cook/manifest/srv");
+        assert_eq!(manifest_path("/srv/site"), "/var/lib/cook/manifest/srv%2Fsite");
+        assert_eq!(manifest_path("/srv/100%"), "/var/lib/cook/manifest/srv%2F100%25");
+    }
+
+    #[test]
+    fn stats_keep_paths_with_spaces() {
+        let stats = parse_stats("12 1792413000 644  /srv/site/a b.txt\n0 1792413001 755  /srv/site/run\n");
+        assert_eq!(stats["/srv/site/a b.txt"], stat(12, 1_792_413_000));
+        assert_eq!(stats["/srv/site/run"].mode, 0o755);
+    }
+
+    #[test]
+    fn a_manifest_reads_back_what_it_wrote() {
+        let files = vec![
+            (PathBuf::from("/srv/site/a b.txt"), "aa".repeat(32)),
+            (PathBuf::from("/srv/site/gone"), "bb".repeat(32)),
+        ];
+        let stats = HashMap::from([("/srv/site/a b.txt".to_string(), stat(12, 1_792_413_000))]);
+        let manifest = Manifest::new(1_792_413_100, &files, &stats);
+        let text = manifest.render("/srv/site");
+        assert!(text.starts_with("# cook manifest for /srv/site\nwritten 1792413100\n"));
+        assert_eq!(Manifest::parse(&text), manifest);
+        // Not listed when the manifest was written, so not in it.
+        assert_eq!(manifest.sha256("/srv/site/gone", &stat(0, 0)), None);
+    }
+
+    #[test]
+    fn only_files_untouched_since_the_manifest_are_vouched_for() {
+        let sha = "aa".repeat(32);
+        let files = vec![
+            (PathBuf::from("/a"), sha.clone()),
+            (PathBuf::from("/racy"), sha.clone()),
+        ];
+        let stats = HashMap::from([
+            ("/a".to_string(), stat(12, 1_792_413_000)),
+            ("/racy".to_string(), stat(12, 1_792_413_100)),
+        ]);
+        let manifest = Manifest::new(1_792_413_100, &files, &stats);
+        assert_eq!(manifest.sha256("/a", &stat(12, 1_792_413_000)), Some(sha.as_str()));
+        assert_eq!(manifest.sha256("/a", &stat(13, 1_792_413_000)), None);
+        assert_eq!(manifest.sha256("/a", &stat(12, 1_792_413_050)), None);
+        // Written in the same second as the manifest.
+        assert_eq!(manifest.sha256("/racy", &stat(12, 1_792_413_100)), None);
+    }
+
+    #[test]
+    fn anything_else_reads_as_an_empty_manifest() {
+        assert!(Manifest::parse("").is_empty());
+        assert!(Manifest::parse("not a manifest\n").is_empty());
+    }
+}
@@ -1,2 +1,13 @@
 pub mod api;
+pub(crate) mod atomic;
+pub mod backup;
+pub(crate) mod batch;
+pub(crate) mod directory;
+pub(crate) mod edit;
+pub(crate) mod hash_cache;
+pub(crate) mod labels;
+pub(crate) mod link;
+pub(crate) mod manifest;
+pub(crate) mod setting;
 pub(crate) mod spec;
+pub(crate) mod url;
@@ -0,0 +1,669 @@
+//! `setting` — one key in a structured config file that cook doesn't own.
+//!
+//! ```kdl
+//! setting /etc/app/config.json path="server.port" value=8080
+//! setting /etc/app/config.toml path="log.level" value=debug
+//! setting /etc/php/8.3/fpm/php.ini path="PHP.memory_limit" value=256M format=ini
+//! setting /etc/app/config.yaml path="features.beta" state=absent
+//! ```
+//!
+//! `path` is a dotted path to the key; a number indexes into a JSON or YAML
+//! array. In an INI file the part before the first dot is the section and the
+//! rest is the key, dots and all (`PHP.date.timezone` is `date.timezone` in
+//! `[PHP]`); a path without a dot names a key above the first section. The file is parsed
+//! and the key compared as a value, so `8080` and `8.08e3` are different but
+//! re-indenting the file is not a change. When a key does differ, the file is
+//! written back as close to how it was as the format allows:
+//!
+//! - TOML keeps its comments, ordering and layout.
+//! - INI changes only the assignment's line, through the same reading of the
+//!   format that `service` uses for unit files.
+//! - JSON keeps its key order and indentation, and a file on one line stays
+//!   on one line.
+//! - YAML keeps its key order, but cannot keep comments: a YAML file with
+//!   comments is refused rather than rewritten without them.
+//!
+//! As with `line`, every `setting` for one path is a single rule and a single
+//! rewrite.
+
+use std::path::PathBuf;
+
+use kdl::{KdlNode, KdlValue};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::file::spec::parse_validate;
+use crate::service::unit;
+use crate::{Context, Error, FromKdl, Modification, Rule, RuleOverSsh, State};
+
+#[cfg(feature = "ssh")]
+use crate::file::edit::{read_text, rewrite};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Json,
+    Yaml,
+    Toml,
+    Ini,
+}
+
+impl Format {
+    fn from_name(name: &str) -> Option<Format> {
+        Some(match name {
+            "json" => Format::Json,
+            "yaml" | "yml" => Format::Yaml,
+            "toml" => Format::Toml,
+            "ini" => Format::Ini,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Setting {
+    pub path: String,
+    /// `None` removes the key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+}
+
+/// `text` with `settings` made, in order, or `None` when every key already
+/// has its value and the file can stay exactly as it is.
+pub(crate) fn apply_settings(text: &str, format: Format, settings: &[Setting]) -> Result<Option<String>, String> {
+    match format {
+        Format::Json => {
+            let mut doc = if text.trim().is_empty() {
+                Value::Object(Default::default())
+            } else {
+                serde_json::from_str(text).map_err(|e| format!("not valid json: {e}"))?
+            };
+            if !set_all(&mut doc, settings)? {
+                return Ok(None);
+            }
+            Ok(Some(render_json(&doc, text)))
+        }
+        Format::Yaml => {
+            let mut doc = if text.trim().is_empty() {
+                Value::Object(Default::default())
+            } else {
+                serde_yaml::from_str(text).map_err(|e| format!("not valid yaml: {e}"))?
+            };
+            if !set_all(&mut doc, settings)? {
+                return Ok(None);
+            }
+            if let Some(line) = yaml_comment(text) {
+                return Err(format!(
+                    "line {line} has a comment, which rewriting the yaml would drop; \
+                     remove the file's comments or manage the whole file"
+                ));
+            }
+            serde_yaml::to_string(&doc).map(Some).map_err(|e| e.to_string())
+        }
+        Format::Toml => {
+            let mut doc: toml_edit::DocumentMut = text.parse().map_err(|e| format!("not valid toml: {e}"))?;
+            let mut changed = false;
+            for setting in settings {
+                let path: Vec<&str> = setting.path.split('.').collect();
+                changed |= set_toml(doc.as_table_mut(), &path, setting.value.as_ref())
+                    .map_err(|e| format!("{}: {e}", setting.path))?;
+            }
+            Ok(changed.then(|| doc.to_string()))
+        }
+        Format::Ini => {
+            let mut text = text.to_string();
+            let mut changed = false;
+            for setting in settings {
+                let (section, key) = match setting.path.split_once('.') {
+                    Some((section, key)) => (Some(section), key),
+                    None => (None, setting.path.as_str()),
+                };
+                let new = setting
+                    .value
+                    .as_ref()
+                    .map(|v| ini_value(v).ok_or_else(|| format!("{}: ini values are single strings", setting.path)))
+                    .transpose()?;
+                let current = unit::value(&text, section, key);
+                if new.is_some() && current.as_deref().map(unit::unquote) == new.as_deref() {
+                    continue;
+                }
+                let edited = unit::set_value(&text, section, key, new.as_deref());
+                changed |= edited != text;
+                text = edited;
+            }
+            Ok(changed.then_some(text))
+        }
+    }
+}
+
+/// Make every setting in `doc`, and say whether any changed it.
+fn set_all(doc: &mut Value, settings: &[Setting]) -> Result<bool, String> {
+    let mut changed = false;
+    for setting in settings {
+        let path: Vec<&str> = setting.path.split('.').collect();
+        changed |= set_json(doc, &path, setting.value.as_ref()).map_err(|e| format!("{}: {e}", setting.path))?;
+    }
+    Ok(changed)
+}
+
+/// Set (or with `None`, remove) the value at `path` under `node`, creating
+/// objects on the way as needed. Returns whether anything changed.
+fn set_json(node: &mut Value, path: &[&str], value: Option<&Value>) -> Result<bool, String> {
+    let (first, rest) = path.split_first().expect("a path has at least one part");
+    match node {
+        Value::Object(map) => {
+            if rest.is_empty() {
+                return Ok(match value {
+                    Some(value) if map.get(*first) == Some(value) => false,
+                    Some(value) => {
+                        map.insert(first.to_string(), value.clone());
+                        true
+                    }
+                    None => map.shift_remove(*first).is_some(),
+                });
+            }
+            match map.get_mut(*first) {
+                Some(child) => set_json(child, rest, value),
+                None if value.is_none() => Ok(false),
+                None => {
+                    let child = map
+                        .entry(first.to_string())
+                        .or_insert(Value::Object(Default::default()));
+                    set_json(child, rest, value)
+                }
+            }
+        }
+        Value::Array(items) => {
+            let index: usize = first.parse().map_err(|_| format!("{first} indexes an array"))?;
+            if index >= items.len() {
+                return match value {
+                    None => Ok(false),
+                    Some(_) => Err(format!("the array has no item {index}")),
+                };
+            }
+            if !rest.is_empty() {
+                return set_json(&mut items[index], rest, value);
+            }
+            Ok(match value {
+                Some(value) if items[index] == *value => false,
+                Some(value) => {
+                    items[index] = value.clone();
+                    true
+                }
+                None => {
+                    items.remove(index);
+                    true
+                }
+            })
+        }
+        _ if value.is_none() => Ok(false),
+        other => Err(format!("{first} is under a {}, not an object", json_type(other))),
+    }
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// The first line of a YAML document with a comment on it: a `#` that starts
+/// the line or follows whitespace, outside a quoted string. A `#` in a block
+/// scalar counts too, which only errs on the side of refusing.
+fn yaml_comment(text: &str) -> Option<usize> {
+    text.lines()
+        .position(|line| {
+            let mut quote = None;
+            let mut previous = ' ';
+            for c in line.chars() {
+                match (c, quote) {
+                    ('#', None) if previous.is_whitespace() => return true,
+                    ('"' | '\'', None) => quote = Some(c),
+                    (c, Some(open)) if c == open => quote = None,
+                    _ => {}
+                }
+                previous = c;
+            }
+            false
+        })
+        .map(|index| index + 1)
+}
+
+/// `doc` as JSON in the layout `original` used — on one line if it was,
+/// otherwise in its indentation — with a final newline if it had one (or was
+/// empty).
+fn render_json(doc: &Value, original: &str) -> String {
+    if original.trim().lines().count() == 1 {
+        let mut out = doc.to_string();
+        if original.ends_with('\n') {
+            out.push('\n');
+        }
+        return out;
+    }
+    let indent: String = original
+        .lines()
+        .nth(1)
+        .map(|l| l.chars().take_while(|c| c.is_whitespace()).collect())
+        .filter(|i: &String| !i.is_empty())
+        .unwrap_or_else(|| "  ".to_string());
+    let mut out = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    let mut serializer = serde_json::Serializer::with_formatter(&mut out, formatter);
+    doc.serialize(&mut serializer).expect("a json value always serializes");
+    let mut out = String::from_utf8(out).expect("serde_json writes utf-8");
+    if original.is_empty() || original.ends_with('\n') {
+        out.push('\n');
+    }
+    out
+}
+
+/// [`set_json`] for a TOML document, through `toml_edit` so the rest of
+/// the file keeps its layout. A replaced value keeps its own comments.
+fn set_toml(table: &mut dyn toml_edit::TableLike, path: &[&str], value: Option<&Value>) -> Result<bool, String> {
+    let (first, rest) = path.split_first().expect("a path has at least one part");
+    if rest.is_empty() {
+        let Some(value) = value else {
+            return Ok(table.remove(first).is_some());
+        };
+        if table.get(first).map(toml_to_json).as_ref() == Some(value) {
+            return Ok(false);
+        }
+        let mut new = json_to_toml(value)?;
+        match table.get_mut(first).and_then(toml_edit::Item::as_value_mut) {
+            Some(old) => {
+                *new.decor_mut() = old.decor().clone();
+                *old = new;
+            }
+            None => {
+                table.insert(first, toml_edit::Item::Value(new));
+            }
+        }
+        return Ok(true);
+    }
+    if table.get(first).is_none() {
+        if value.is_none() {
+            return Ok(false);
+        }
+        let mut child = toml_edit::Table::new();
+        child.set_implicit(true);
+        table.insert(first, toml_edit::Item::Table(child));
+    }
+    let child = table
+        .get_mut(first)
+        .and_then(toml_edit::Item::as_table_like_mut)
+        .ok_or_else(|| format!("{first} is not a table"))?;
+    set_toml(child, rest, value)
+}
+
+fn json_to_toml(value: &Value) -> Result<toml_edit::Value, String> {
+    Ok(match value {
+        Value::Null => return Err("toml has no null".to_string()),
+        Value::Bool(b) => (*b).into(),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => i.into(),
+            None => n.as_f64().expect("a json number is an i64, u64 or f64").into(),
+        },
+        Value::String(s) => s.as_str().into(),
+        Value::Array(items) => {
+            let items = items.iter().map(json_to_toml).collect::<Result<Vec<_>, _>>()?;
+            toml_edit::Value::Array(items.into_iter().collect())
+        }
+        Value::Object(map) => {
+            let mut table = toml_edit::InlineTable::new();
+            for (k, v) in map {
+                table.insert(k, json_to_toml(v)?);
+            }
+            toml_edit::Value::InlineTable(table)
+        }
+    })
+}
+
+/// A TOML item as the JSON value it compares equal to. Dates, which JSON
+/// doesn't have, become their text.
+fn toml_to_json(item: &toml_edit::Item) -> Value {
+    fn value(v: &toml_edit::Value) -> Value {
+        match v {
+            toml_edit::Value::String(s) => Value::String(s.value().clone()),
+            toml_edit::Value::Integer(i) => Value::from(*i.value()),
+            toml_edit::Value::Float(f) => Value::from(*f.value()),
+            toml_edit::Value::Boolean(b) => Value::Bool(*b.value()),
+            toml_edit::Value::Datetime(d) => Value::String(d.value().to_string()),
+            toml_edit::Value::Array(items) => Value::Array(items.iter().map(value).collect()),
+            toml_edit::Value::InlineTable(t) => {
+                Value::Object(t.iter().map(|(k, v)| (k.to_string(), value(v))).collect())
+            }
+        }
+    }
+    match item {
+        toml_edit::Item::None => Value::Null,
+        toml_edit::Item::Value(v) => value(v),
+        toml_edit::Item::Table(t) => Value::Object(t.iter().map(|(k, v)| (k.to_string(), toml_to_json(v))).collect()),
+        toml_edit::Item::ArrayOfTables(tables) => Value::Array(
+            tables
+                .iter()
+                .map(|t| Value::Object(t.iter().map(|(k, v)| (k.to_string(), toml_to_json(v))).collect()))
+                .collect(),
+        ),
+    }
+}
+
+/// A value as an INI file writes it, if it is a single one.
+fn ini_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Bool(_) | Value::Number(_) => Some(value.to_string()),
+        Value::Null | Value::Array(_) | Value::Object(_) => None,
+    }
+}
+
+fn kdl_to_json(value: &KdlValue) -> Value {
+    match value {
+        KdlValue::String(s) => Value::String(s.clone()),
+        KdlValue::Integer(i) => match i64::try_from(*i) {
+            Ok(i) => Value::from(i),
+            Err(_) => panic!("setting: {i} is too large for a config value"),
+        },
+        KdlValue::Float(f) => Value::from(*f),
+        KdlValue::Bool(b) => Value::Bool(*b),
+        KdlValue::Null => Value::Null,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingSpec {
+    pub path: PathBuf,
+    pub format: Format,
+    pub settings: Vec<Setting>,
+    /// As for `file`: a command that must accept the edited file, with `%s`
+    /// for its path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validate: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub backup: bool,
+}
+
+impl FromKdl for SettingSpec {
+    fn kdl_keywords() -> &'static [&'static str] {
+        &["setting"]
+    }
+
+    fn add_rules_to_state(state: &mut State, node: &KdlNode, context: &Context) {
+        let mut args = Vec::new();
+        let mut key = None;
+        let mut value = None;
+        let mut format = None;
+        let mut present = true;
+        let mut validate = None;
+        let mut backup = None;
+        for entry in node.entries() {
+            match entry.name().map(|i| i.value()) {
+                None => args.push(entry.expect_str()),
+                Some("path") => key = Some(entry.expect_str().to_string()),
+                Some("value") => value = Some(kdl_to_json(entry.value())),
+                Some("format") => {
+                    let name = entry.expect_str();
+                    format = Some(
+                        Format::from_name(name)
+                            .unwrap_or_else(|| panic!("setting: format must be json, yaml, toml or ini, not {name}")),
+                    )
+                }
+                Some("state") => {
+                    present = match entry.value() {
+                        KdlValue::String(s) if s == "present" => true,
+                        KdlValue::String(s) if s == "absent" => false,
+                        v => panic!("setting: state must be \"present\" or \"absent\", not {v}"),
+                    }
+                }
+                Some("validate") => validate = Some(parse_validate(entry, "setting")),
+                Some("backup") => {
+                    backup = Some(
+                        entry
+                            .value()
+                            .as_bool()
+                            .unwrap_or_else(|| panic!("setting: backup must be true or false")),
+                    )
+                }
+                Some(z) => panic!("Unexpected option for setting: {z}"),
+            }
+        }
+        let [path] = args[..] else {
+            panic!("setting requires exactly one file path");
+        };
+        let path = PathBuf::from(path);
+        let key = key.unwrap_or_else(|| panic!("setting {}: path= names the key to set", path.display()));
+        assert!(
+            !key.is_empty() && key.split('.').all(|part| !part.is_empty()),
+            "setting {}: path {key:?} has an empty part",
+            path.display()
+        );
+        let format = format
+            .or_else(|| Format::from_name(path.extension()?.to_str()?))
+            .unwrap_or_else(|| panic!("setting {}: give format=json, yaml, toml or ini", path.display()));
+        let value = match (present, value) {
+            (true, Some(value)) => Some(value),
+            (true, None) => panic!("setting {} {key}: give a value, or state=absent", path.display()),
+            (false, None) => None,
+            (false, Some(_)) => panic!("setting {} {key}: an absent key has no value", path.display()),
+        };
+        let setting = Setting { path: key, value };
+
+        if let Some(spec) = state.rule_mut::<SettingSpec>(|spec| spec.path == path) {
+            assert!(
+                spec.format == format,
+                "setting {}: read as {:?} by an earlier setting",
+                path.display(),
+                spec.format
+            );
+            if let Some(validate) = validate {
+                assert!(
+                    spec.validate.as_ref().is_none_or(|v| *v == validate),
+                    "setting {}: validate differs from an earlier setting of the same file",
+                    path.display()
+                );
+                spec.validate = Some(validate);
+            }
+            spec.backup |= backup.unwrap_or(false);
+            spec.settings.push(setting);
+            return;
+        }
+        state.add_rule(SettingSpec {
+            path,
+            format,
+            settings: vec![setting],
+            validate,
+            backup: backup.unwrap_or_else(|| context.backup()),
+        });
+    }
+}
+
+impl Rule for SettingSpec {
+    #[cfg(feature = "ssh")]
+    fn downcast_ssh(&self) -> Option<&dyn crate::RuleOverSsh> {
+        Some(self)
+    }
+
+    fn check(&self) -> Result<Vec<Box<dyn Modification>>, Error> {
+        todo!()
+    }
+
+    fn kind(&self) -> &'static str {
+        "file"
+    }
+
+    fn identifier(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+}
+
+#[cfg(feature = "ssh")]
+#[async_trait::async_trait]
+impl RuleOverSsh for SettingSpec {
+    async fn check_ssh(&self, session: &openssh::Session) -> Result<Vec<Box<dyn Modification>>, Error> {
+        let current = read_text(session, &self.path).await?;
+        let text = current.as_deref().unwrap_or_default();
+        let edited =
+            apply_settings(text, self.format, &self.settings).map_err(|e| format!("{}: {e}", self.path.display()))?;
+        let Some(edited) = edited else {
+            return Ok(Vec::new());
+        };
+        Ok(vec![rewrite(
+            &self.path,
+            edited,
+            current.is_some(),
+            self.backup,
+            &self.validate,
+        )])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn set(path: &str, value: Value) -> Setting {
+        Setting {
+            path: path.into(),
+            value: Some(value),
+        }
+    }
+
+    fn unset(path: &str) -> Setting {
+        Setting {
+            path: path.into(),
+            value: None,
+        }
+    }
+
+    #[test]
+    fn json_keeps_its_order_and_indentation() {
+        let config =
+            "{\n    \"server\": {\n        \"port\": 80,\n        \"host\": \"::\"\n    },\n    \"debug\": false\n}\n";
+        let edited = apply_settings(config, Format::Json, &[set("server.port", json!(8080))]).unwrap();
+        assert_eq!(
+            edited.as_deref(),
+            Some(
+                "{\n    \"server\": {\n        \"port\": 8080,\n        \"host\": \"::\"\n    },\n    \"debug\": false\n}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn an_equal_value_is_no_change_however_it_is_written() {
+        let config = "{\"server\":{\"port\":8080}}";
+        assert_eq!(
+            apply_settings(config, Format::Json, &[set("server.port", json!(8080))]).unwrap(),
+            None
+        );
+        let config = "[server]\nport = 8080 # the public port\n";
+        assert_eq!(
+            apply_settings(config, Format::Toml, &[set("server.port", json!(8080))]).unwrap(),
+            None
+        );
+        let config = "[server]\nname = \"web 1\"\n";
+        assert_eq!(
+            apply_settings(config, Format::Ini, &[set("server.name", json!("web 1"))]).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn missing_objects_are_created_and_keys_removed() {
+        let edited = apply_settings("", Format::Json, &[set("a.b.c", json!(true))]).unwrap();
+        assert_eq!(
+            edited.as_deref(),
+            Some("{\n  \"a\": {\n    \"b\": {\n      \"c\": true\n    }\n  }\n}\n")
+        );
+        let edited = apply_settings(
+            "{\"a\": 1, \"b\": [1, 2, 3]}",
+            Format::Json,
+            &[unset("a"), unset("b.1")],
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&edited.unwrap()).unwrap(),
+            json!({"b": [1, 3]})
+        );
+        assert_eq!(apply_settings("{}", Format::Json, &[unset("a.b")]).unwrap(), None);
+    }
+
+    #[test]
+    fn a_key_under_a_scalar_is_an_error() {
+        let err = apply_settings("{\"a\": 1}", Format::Json, &[set("a.b", json!(2))]).unwrap_err();
+        assert!(err.contains("a.b"), "{err}");
+    }
+
+    #[test]
+    fn toml_keeps_comments_and_layout() {
+        let config = "# app\n[server]\nport = 80 # public\nhost = \"::\"\n\n[log]\nlevel = \"info\"\n";
+        let edited = apply_settings(
+            config,
+            Format::Toml,
+            &[
+                set("server.port", json!(8080)),
+                set("log.file", json!("/var/log/app.log")),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            edited.as_deref(),
+            Some(
+                "# app\n[server]\nport = 8080 # public\nhost = \"::\"\n\n[log]\nlevel = \"info\"\nfile = \"/var/log/app.log\"\n"
+            )
+        );
+    }
+
+    #[test]
+    fn yaml_is_compared_and_written_as_yaml() {
+        let config = "server:\n  port: 80\n  host: '::'\n";
+        assert_eq!(
+            apply_settings(config, Format::Yaml, &[set("server.port", json!(80))]).unwrap(),
+            None
+        );
+        let edited = apply_settings(config, Format::Yaml, &[set("server.port", json!(8080))]).unwrap();
+        assert_eq!(edited.as_deref(), Some("server:\n  port: 8080\n  host: '::'\n"));
+    }
+
+    #[test]
+    fn ini_changes_only_the_assignment() {
+        let config = "; php\n[PHP]\nmemory_limit = 128M\nmax_execution_time = 30\n";
+        let edited = apply_settings(config, Format::Ini, &[set("PHP.memory_limit", json!("256M"))]).unwrap();
+        assert_eq!(
+            edited.as_deref(),
+            Some("; php\n[PHP]\nmemory_limit = 256M\nmax_execution_time = 30\n")
+        );
+        let err = apply_settings(config, Format::Ini, &[set("PHP.list", json!([1, 2]))]).unwrap_err();
+        assert!(err.contains("single strings"), "{err}");
+    }
+
+    #[test]
+    fn an_ini_key_may_have_dots_in_it() {
+        let config = "[PHP]\ndate.timezone = UTC\n";
+        let edited = apply_settings(config, Format::Ini, &[set("PHP.date.timezone", json!("Europe/Berlin"))]).unwrap();
+        assert_eq!(edited.as_deref(), Some("[PHP]\ndate.timezone = Europe/Berlin\n"));
+    }
+
+    #[test]
+    fn yaml_with_comments_is_refused_rather_than_stripped() {
+        let config = "server:\n  port: 80 # the default\n  name: 'a # b'\n";
+        let err = apply_settings(config, Format::Yaml, &[set("server.port", json!(8080))]).unwrap_err();
+        assert!(err.starts_with("line 2 has a comment"), "{err}");
+        // Nothing to write, nothing lost.
+        assert_eq!(
+            apply_settings(config, Format::Yaml, &[set("server.port", json!(80))]).unwrap(),
+            None
+        );
+        assert_eq!(yaml_comment("name: 'a # b'\nurl: \"x#y\"\n"), None);
+    }
+
+    #[test]
+    fn compact_json_stays_compact() {
+        let edited = apply_settings("{\"a\":1,\"b\":[1,2]}\n", Format::Json, &[set("a", json!(2))]).unwrap();
+        assert_eq!(edited.as_deref(), Some("{\"a\":2,\"b\":[1,2]}\n"));
+    }
+}
@@ -4,20 +4,44 @@ use std::{
     collections::HashMap,
     fs,
     path::{Path, PathBuf},
+    time::SystemTime,
 };
 
 use kdl::{KdlEntry, KdlNode, KdlValue};
 use serde::{Deserialize, Serialize};
 
+use crate::file::backup;
+use crate::file::batch::MissingFiles;
+use crate::file::directory::{DirectorySpec, MissingDirectory, WrongDirectory};
+use crate::file::labels::{Labels, WrongLabels};
+use crate::file::link::{LinkSpec, MissingSymlink, WrongSymlink};
+use crate::file::manifest::WriteManifest;
+use crate::file::url::UrlSource;
 use crate::{Context, Error, FromKdl, Modification, ModificationOverSsh, Rule, RuleOverSsh, State};
 
+#[cfg(feature = "ssh")]
+use crate::file::atomic;
+#[cfg(feature = "ssh")]
+use crate::file::labels::read_labels;
+#[cfg(feature = "ssh")]
+use crate::file::link::ln_sf;
+#[cfg(feature = "ssh")]
+use crate::file::manifest::{self, Manifest};
+#[cfg(feature = "ssh")]
+use crate::file::url;
 #[cfg(feature = "ssh")]
 use crate::sh_single_quote;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum FileContent {
+    /// Bytes given in the Cookfile itself, and their sha256.
     Content(Vec<u8>, String),
-    Url(String),
+    /// A local file and its sha256. Only the hash is taken when the Cookfile
+    /// is read; the bytes are read from disk, in chunks, if and when the file
+    /// is uploaded — so a tree of large assets costs a path per file in
+    /// memory, not its size.
+    Path(PathBuf, String),
+    Url(UrlSource),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,7 +51,7 @@ pub struct FileSpec {
     ///
     /// `None` means the mode is not cook's to manage: the file is uploaded and
     /// left at whatever mode it lands at (sftp's default for a new file, the
-    /// existing mode for one being replaced). Most files don't care, and a
+    /// replaced file's mode for an existing one). Most files don't care, and a
     /// default of 0644 here would silently strip the exec bit off anything the
     /// host had already made executable.
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -37,6 +61,17 @@ pub struct FileSpec {
     pub owner: Option<u32>, // UID
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub group: Option<u32>, // GID
+    /// A command that must accept the new content before it replaces the old,
+    /// e.g. `visudo -cf %s`. `%s` is the path of the uploaded temporary copy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validate: Option<String>,
+    /// Copy the file on the host under [`backup::BACKUP_DIR`] before
+    /// replacing it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub backup: bool,
+    /// SELinux context, ACL entries and extended attributes; see [`labels`](crate::file::labels).
+    #[serde(default, skip_serializing_if = "Labels::is_empty")]
+    pub labels: Labels,
 }
 
 impl FileSpec {
@@ -50,35 +85,57 @@ impl FileSpec {
             content: FileContent::Content(content, sha256),
             owner: None,
             group: None,
+            validate: None,
+            backup: false,
+            labels: Labels::default(),
         }
     }
 
-    pub fn new_copy(src: PathBuf, dst: PathBuf, mode: Option<u32>) -> Self {
-        let content = fs::read(src).expect("Failed to read path");
-        let mut hasher = Sha256::new();
-        hasher.update(&content);
-        let sha256 = format!("{:x}", hasher.finalize());
+    /// A copy of `src`, whose hash the caller has taken — through the
+    /// context, so unchanged files come out of its hash cache.
+    fn from_local(src: PathBuf, dst: PathBuf, mode: Option<u32>, sha256: String) -> Self {
         FileSpec {
             path: dst,
             mode,
-            content: FileContent::Content(content, sha256),
+            content: FileContent::Path(src, sha256),
             owner: None,
             group: None,
+            validate: None,
+            backup: false,
+            labels: Labels::default(),
         }
     }
 
     /// The change that puts this file on the host. Applying it writes the
     /// content *and* sets `mode`, so a spec that needs uploading never needs a
     /// separate [`WrongMode`] change too.
-    fn missing_file(&self) -> MissingFile {
+    ///
+    /// `exists` is whether there is a file at the path now, which is what
+    /// decides if a backup is taken.
+    fn missing_file(&self, exists: bool) -> MissingFile {
+        let backup =
+            (self.backup && exists).then(|| backup::backup_path(&self.path, &backup::stamp(SystemTime::now())));
         MissingFile {
             path: self.path.clone(),
             content: self.content.clone(),
             owner: self.owner,
             group: self.group,
             mode: self.mode,
+            validate: self.validate.clone(),
+            backup,
         }
     }
+
+    /// The labels this file is missing, as a change — none if it has them
+    /// all. `current` is what the host reported; `None` for a file about to
+    /// be uploaded, which gets every label afresh.
+    fn wrong_labels(&self, current: Option<&Labels>) -> Option<WrongLabels> {
+        let labels = self.labels.missing_from(current);
+        (!labels.is_empty()).then(|| WrongLabels {
+            path: self.path.clone(),
+            labels,
+        })
+    }
 }
 
 /// Parse a `mode="755"` property.
@@ -86,7 +143,7 @@ impl FileSpec {
 /// The value has to be a quoted octal string. A bare `755` is rejected rather
 /// than silently misread: KDL parses it as the decimal number 755, which is a
 /// perfectly valid — and completely different — mode (0o1363, setgid + rwx--x-wx).
-fn parse_mode(entry: &KdlEntry, keyword: &str) -> u32 {
+pub(crate) fn parse_mode(entry: &KdlEntry, keyword: &str) -> u32 {
     let KdlValue::String(s) = entry.value() else {
         panic!(
             "{keyword}: mode {} is read as a decimal number — quote it as octal, e.g. mode=\"755\"",
@@ -97,6 +154,122 @@ fn parse_mode(entry: &KdlEntry, keyword: &str) -> u32 {
         .unwrap_or_else(|_| panic!("{keyword}: mode \"{s}\" is not an octal number, e.g. mode=\"755\""))
 }
 
+/// Parse a `validate="visudo -cf %s"` property.
+///
+/// A command without `%s` would check whatever is already installed (or
+/// nothing at all) and pass a broken upload straight through, so it is
+/// rejected rather than run.
+pub(crate) fn parse_validate(entry: &KdlEntry, keyword: &str) -> String {
+    let command = entry.expect_str();
+    assert!(
+        command.contains("%s"),
+        "{keyword}: validate needs %s where the new file's path goes, e.g. validate=\"visudo -cf %s\""
+    );
+    command.to_string()
+}
+
+/// `newline=#true`: end the content with exactly one newline.
+///
+/// A KDL multi-line string drops the newline before its closing quote, so
+/// without this a config file written inline would end mid-line — which some
+/// parsers, and `cat`, take badly. Extra trailing newlines are collapsed
+/// rather than kept, so re-indenting the string in the Cookfile doesn't
+/// change the file on the host.
+fn with_trailing_newline(content: &str) -> String {
+    let mut content = content.trim_end_matches(['\n', '\r']).to_string();
+    if !content.is_empty() {
+        content.push('\n');
+    }
+    content
+}
+
+/// The name of the ignore file `cp` reads besides `.gitignore`, for what the
+/// repository tracks but a host has no use for — tests, docs, the Cookfile.
+const COOKIGNORE: &str = ".cookignore";
+
+/// Every file under `src`, in path order, and the ignore rules that left the
+/// rest out.
+///
+/// With `ignore_files`, `.gitignore` and `.cookignore` files inside the tree
+/// apply with git's semantics — negation, anchoring, and nested files that
+/// override their parents — whether or not the tree is in a repository.
+/// Ignore files *above* `src` are not read: a project that gitignores its
+/// build output still means to deploy it when it names that directory as the
+/// source. `.git` and `.cookignore` themselves are never shipped.
+fn walk_tree(src: &Path, ignore_files: bool) -> (Vec<PathBuf>, Vec<IgnoreRules>) {
+    let mut walk = ignore::WalkBuilder::new(src);
+    walk.standard_filters(false).sort_by_file_name(|a, b| a.cmp(b));
+    if ignore_files {
+        walk.git_ignore(true)
+            .require_git(false)
+            .parents(false)
+            .add_custom_ignore_filename(COOKIGNORE)
+            .filter_entry(|e| e.depth() == 0 || (e.file_name() != ".git" && e.file_name() != COOKIGNORE));
+    }
+    let mut files = Vec::new();
+    let mut ignored = Vec::new();
+    for entry in walk.build() {
+        let entry = entry.unwrap_or_else(|e| panic!("cp: walking {}: {e}", src.display()));
+        if entry.path().is_file() {
+            files.push(entry.into_path());
+        } else if ignore_files && entry.path().is_dir() {
+            let mut patterns = Vec::new();
+            for name in [".gitignore", COOKIGNORE] {
+                if let Ok(text) = fs::read_to_string(entry.path().join(name)) {
+                    patterns.extend(text.lines().map(str::to_string));
+                }
+            }
+            if entry.depth() == 0 {
+                // Last, so a `!.git` in the tree can't bring them back.
+                patterns.extend([".git".to_string(), COOKIGNORE.to_string()]);
+            }
+            if !patterns.is_empty() {
+                let dir = entry.path().strip_prefix(src).expect("Path should be under src");
+                ignored.push(IgnoreRules {
+                    dir: dir.to_path_buf(),
+                    patterns,
+                });
+            }
+        }
+    }
+    (files, ignored)
+}
+
+/// The permission bits of a local file or directory, for `preserve_mode`.
+fn local_mode(path: &Path) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    let metadata = fs::metadata(path).unwrap_or_else(|e| panic!("cp: reading the mode of {}: {e}", path.display()));
+    metadata.permissions().mode() & 0o7777
+}
+
+/// The directories a tree copy puts `files` in — `dst` itself and every
+/// directory between it and a file — each with the mode `mode_of` gives its
+/// local counterpart under `src`. Parents sort before their children, so
+/// creating them in order never relies on `mkdir -p` picking a mode.
+fn directory_modes(src: &Path, dst: &Path, files: &[FileSpec], mode_of: impl Fn(&Path) -> u32) -> Vec<DirectoryMode> {
+    let mut relative = std::collections::BTreeSet::new();
+    relative.insert(PathBuf::new());
+    for file in files {
+        let path = file
+            .path
+            .strip_prefix(dst)
+            .expect("copied files are under the destination");
+        relative.extend(path.ancestors().skip(1).map(Path::to_path_buf));
+    }
+    relative
+        .into_iter()
+        .map(|relative| DirectoryMode {
+            // `dst.join("")` would add a trailing slash.
+            path: if relative.as_os_str().is_empty() {
+                dst.to_path_buf()
+            } else {
+                dst.join(&relative)
+            },
+            mode: mode_of(&src.join(&relative)),
+        })
+        .collect()
+}
+
 /// Check if a path should be included based on include/exclude patterns.
 /// Returns true if the path or any of its ancestors match an include pattern
 /// and don't match an exclude pattern.
@@ -130,15 +303,74 @@ impl FromKdl for FileSpec {
 
     fn add_rules_to_state(state: &mut State, node: &KdlNode, context: &Context) {
         let keyword = node.name().value();
-        // Paths are positional; `mode` is the only property either keyword takes.
-        // Splitting them here means `cp a b mode="755"` and `cp mode="755" a b`
-        // both work, instead of the property being consumed as a path.
+        // `file /srv/app/` is a directory, and takes a directory's options.
+        if keyword == "file"
+            && let Some(path) = node.entries().iter().find(|e| e.name().is_none())
+            && path.value().as_string().is_some_and(|p| p.ends_with('/'))
+        {
+            DirectorySpec::add_rules_to_state(state, node, context);
+            return;
+        }
+        // `file current link=releases/a` is a symlink, and takes a link's options.
+        if keyword == "file" && node.entries().iter().any(|e| e.name().is_some_and(|n| n.value() == "link")) {
+            LinkSpec::add_rules_to_state(state, node, context);
+            return;
+        }
+        // Paths are positional, everything else is a property. Splitting them
+        // here means `cp a b mode="755"` and `cp mode="755" a b` both work,
+        // instead of the property being consumed as a path.
         let mut args = Vec::new();
         let mut mode = None;
+        let mut content = None;
+        let mut newline = false;
+        let mut url = None;
+        let mut sha256 = None;
+        let mut fetch = None;
+        let mut cache = None;
+        let mut validate = None;
+        let mut backup = context.backup();
+        let mut file_mode = None;
+        let mut dir_mode = None;
+        let mut preserve_mode = false;
+        let mut ignore_files = true;
+        let mut labels = Labels::default();
         for entry in node.entries() {
             match entry.name().map(|i| i.value()) {
                 None => args.push(entry),
                 Some("mode") => mode = Some(parse_mode(entry, keyword)),
+                Some("validate") => validate = Some(parse_validate(entry, keyword)),
+                Some("selinux_context") => labels.selinux_context = Some(entry.expect_str().to_string()),
+                Some("file_mode") if keyword == "cp" => file_mode = Some(parse_mode(entry, keyword)),
+                Some("dir_mode") if keyword == "cp" => dir_mode = Some(parse_mode(entry, keyword)),
+                Some("ignore_files") if keyword == "cp" => {
+                    ignore_files = entry
+                        .value()
+                        .as_bool()
+                        .unwrap_or_else(|| panic!("{keyword}: ignore_files must be true or false"))
+                }
+                Some("preserve_mode") if keyword == "cp" => {
+                    preserve_mode = entry
+                        .value()
+                        .as_bool()
+                        .unwrap_or_else(|| panic!("{keyword}: preserve_mode must be true or false"))
+                }
+                Some("backup") => {
+                    backup = entry
+                        .value()
+                        .as_bool()
+                        .unwrap_or_else(|| panic!("{keyword}: backup must be true or false"))
+                }
+                Some("content") if keyword == "file" => content = Some(entry.expect_str().to_string()),
+                Some("url") if keyword == "file" => url = Some(entry.expect_str().to_string()),
+                Some("sha256") if keyword == "file" => sha256 = Some(entry.expect_str().to_string()),
+                Some("fetch") if keyword == "file" => fetch = Some(entry.expect_str()),
+                Some("cache") if keyword == "file" => cache = Some(entry.expect_str()),
+                Some("newline") if keyword == "file" => {
+                    newline = entry
+                        .value()
+                        .as_bool()
+                        .unwrap_or_else(|| panic!("{keyword}: newline must be true or false"))
+                }
                 Some(z) => panic!("Unexpected option for {keyword}: {z}"),
             }
         }
@@ -146,7 +378,56 @@ impl FromKdl for FileSpec {
         match keyword {
             "file" => {
                 let dst = PathBuf::from(args.next().expect("file requires a path").expect_str());
-                let file = FileSpec::new(dst, Vec::new(), mode);
+                // Longer content reads better as a child node, where a raw
+                // string can hold quotes and backslashes as they are:
+                // `file /etc/motd { content #"..."# }`.
+                if let Some(children) = node.children() {
+                    for n in children.nodes() {
+                        match n.name().value() {
+                            "content" => {
+                                let [entry] = n.entries() else {
+                                    panic!("file {}: content takes exactly one string", dst.display());
+                                };
+                                if content.is_some() {
+                                    panic!("file {}: content is given more than once", dst.display());
+                                }
+                                content = Some(entry.expect_str().to_string());
+                            }
+                            _ if labels.parse_child(n, keyword) => {}
+                            z => panic!("Unexpected directive for file: {z}"),
+                        }
+                    }
+                }
+                if let Some(url) = url {
+                    assert!(
+                        content.is_none(),
+                        "file {}: give either content or url, not both",
+                        dst.display()
+                    );
+                    let source = UrlSource::new(url, sha256, fetch, cache, &dst);
+                    state.add_rule(FileSpec {
+                        path: dst,
+                        mode,
+                        content: FileContent::Url(source),
+                        owner: None,
+                        group: None,
+                        validate,
+                        backup,
+                        labels,
+                    });
+                    return;
+                }
+                if sha256.is_some() || fetch.is_some() || cache.is_some() {
+                    panic!("file {}: sha256, fetch and cache only apply to a url", dst.display());
+                }
+                let mut content = content.unwrap_or_default();
+                if newline {
+                    content = with_trailing_newline(&content);
+                }
+                let mut file = FileSpec::new(dst, content.into_bytes(), mode);
+                file.validate = validate;
+                file.backup = backup;
+                file.labels = labels;
                 state.add_rule(file);
             }
             "cp" => {
@@ -155,6 +436,8 @@ impl FromKdl for FileSpec {
                 let mut dst = PathBuf::from(dst_str);
                 let mut includes: GlobSetBuilder = GlobSetBuilder::new();
                 let mut excludes: GlobSetBuilder = GlobSetBuilder::new();
+                let mut keep = Vec::new();
+                let mut purge = false;
                 if let Some(child) = node.children() {
                     for n in child.nodes() {
                         match n.name().value() {
@@ -167,38 +450,80 @@ impl FromKdl for FileSpec {
                             }
                             "exclude" => {
                                 for e in n.entries() {
-                                    let glob = format!("**/{}", e.expect_str().trim_end_matches("/"));
-                                    let glob = Glob::new(&glob).expect("Invalid path for exclude directive");
+                                    let pattern = format!("**/{}", e.expect_str().trim_end_matches("/"));
+                                    let glob = Glob::new(&pattern).expect("Invalid path for exclude directive");
                                     excludes.add(glob);
+                                    keep.push(pattern);
                                 }
                             }
+                            "purge" => {
+                                purge = n
+                                    .entries()
+                                    .first()
+                                    .and_then(|e| e.value().as_bool())
+                                    .expect("cp: purge must be true or false");
+                            }
+                            _ if labels.parse_child(n, keyword) => {}
                             _ => panic!("Unexpected directive for cp: {}", n.name().value()),
                         }
                     }
                 }
                 let includes = includes.build().expect("Failed to build includes");
                 let excludes = excludes.build().expect("Failed to build excludes");
+                assert!(
+                    mode.is_none() || file_mode.is_none(),
+                    "cp: mode and file_mode mean the same thing; give one"
+                );
+                // As with rsync's `-p --chmod`, an explicit mode wins over the
+                // preserved one for the kind of entry it names.
+                let file_mode = file_mode.or(mode);
+                let file_mode_of = |local: &Path| file_mode.or_else(|| preserve_mode.then(|| local_mode(local)));
                 if src.is_dir() {
-                    let entries = walkdir::WalkDir::new(&src)
-                        .into_iter()
-                        .flat_map(|e| e.ok())
-                        .filter(|e| e.path().is_file());
                     let mut files = Vec::new();
-                    for entry in entries {
-                        let entry = entry.path();
+                    let (walked, ignored) = walk_tree(&src, ignore_files);
+                    for entry in walked {
+                        let entry = entry.as_path();
                         let relative_path = entry.strip_prefix(&src).expect("Path should be under src");
                         if !should_include_path(relative_path, &includes, &excludes) {
                             continue;
                         }
                         let target_path = dst.join(relative_path);
-                        files.push(FileSpec::new_copy(entry.to_path_buf(), target_path, mode));
+                        let sha256 = context.sha256_file(entry);
+                        let mut file =
+                            FileSpec::from_local(entry.to_path_buf(), target_path, file_mode_of(entry), sha256);
+                        file.validate = validate.clone();
+                        file.backup = backup;
+                        file.labels = labels.clone();
+                        files.push(file);
                     } // walk the dir recursively. collect every included file into one fileset
-                    state.add_rule(FileSetSpec { root: dst, files });
+                    let directories = if dir_mode.is_some() || preserve_mode {
+                        directory_modes(&src, &dst, &files, |local| {
+                            dir_mode.unwrap_or_else(|| local_mode(local))
+                        })
+                    } else {
+                        Vec::new()
+                    };
+                    state.add_rule(FileSetSpec {
+                        root: dst,
+                        files,
+                        directories,
+                        purge,
+                        keep,
+                        ignored,
+                        paranoid: context.paranoid(),
+                    });
                 } else {
+                    assert!(!purge, "cp: purge only applies when copying a directory");
+                    assert!(dir_mode.is_none(), "cp: dir_mode only applies when copying a directory");
                     if dst_str.ends_with('/') {
                         dst.push(src.file_name().expect("Must have a file name."));
                     }
-                    let file = FileSpec::new_copy(src, dst, mode);
+                    let mode = file_mode_of(&src);
+                    let sha256 = context.sha256_file(&src);
+                    let mut file = FileSpec::from_local(src, dst, mode, sha256);
+                    file.validate = validate;
+                    file.backup = backup;
+                    file.labels = labels;
                     state.add_rule(file);
                 }
             }
@@ -230,38 +555,65 @@ impl Rule for FileSpec {
 impl RuleOverSsh for FileSpec {
     async fn check_ssh(&self, session: &openssh::Session) -> Result<Vec<Box<dyn Modification>>, Error> {
         let path = self.path.to_str().ok_or("file path is not valid utf-8")?;
-        let needs_upload = match &self.content {
-            FileContent::Content(_, sha256) => {
+        let expected_sha256 = match &self.content {
+            FileContent::Content(_, sha256) | FileContent::Path(_, sha256) => Some(sha256),
+            FileContent::Url(source) => source.sha256.as_ref(),
+        };
+        let (needs_upload, exists) = match (expected_sha256, &self.content) {
+            (Some(sha256), _) => {
                 let output = session.command("sha256sum").arg(path).output().await?;
                 let output = String::from_utf8_lossy(&output.stdout);
                 let remote_hash = output.split_whitespace().next().unwrap_or_default();
-                sha256 != remote_hash
+                (sha256 != remote_hash, !remote_hash.is_empty())
+            }
+            (None, FileContent::Url(source)) => {
+                let needs_download = url::needs_download(source, session, &self.path).await?;
+                // Only worth asking when the answer decides on a backup.
+                let exists = needs_download
+                    && self.backup
+                    && session.command("test").arg("-e").arg(path).status().await?.success();
+                (needs_download, exists)
+            }
+            (None, FileContent::Content(..) | FileContent::Path(..)) => {
+                unreachable!("inline and local content always has a hash")
             }
-            FileContent::Url(_) => !session.command("test").arg("-f").arg(path).output().await?.status.success(),
         };
 
-        // An upload sets the mode on its way out, so it subsumes a mode change.
+        // An upload sets the mode on its way out, so it subsumes a mode
+        // change. It replaces the file, labels and all, so they all follow.
         if needs_upload {
-            return Ok(vec![Box::new(FileChange::MissingFile(self.missing_file()))]);
+            let mut changes: Vec<Box<dyn Modification>> =
+                vec![Box::new(FileChange::MissingFile(self.missing_file(exists)))];
+            if let Some(labels) = self.wrong_labels(None) {
+                changes.push(Box::new(FileChange::WrongLabels(labels)));
+            }
+            return Ok(changes);
         }
 
         // The content is already right, but the mode may have drifted. Only
         // worth a round-trip when there's a mode to enforce; the file is known
         // to exist here, so `stat` failing means something else is wrong.
-        let Some(mode) = self.mode else {
-            return Ok(Vec::new());
-        };
-        let output = session.command("stat").arg("-c").arg("%a").arg(path).output().await?;
-        let remote = String::from_utf8_lossy(&output.stdout);
-        let remote = u32::from_str_radix(remote.trim(), 8)
-            .map_err(|_| format!("could not read the mode of {path}: stat printed {remote:?}"))?;
-        if remote == mode {
-            return Ok(Vec::new());
+        let mut changes: Vec<Box<dyn Modification>> = Vec::new();
+        if let Some(mode) = self.mode {
+            let output = session.command("stat").arg("-c").arg("%a").arg(path).output().await?;
+            let remote = String::from_utf8_lossy(&output.stdout);
+            let remote = u32::from_str_radix(remote.trim(), 8)
+                .map_err(|_| format!("could not read the mode of {path}: stat printed {remote:?}"))?;
+            if remote != mode {
+                changes.push(Box::new(FileChange::WrongMode(WrongMode {
+                    path: self.path.clone(),
+                    mode,
+                })));
+            }
         }
-        Ok(vec![Box::new(FileChange::WrongMode(WrongMode {
-            path: self.path.clone(),
-            mode,
-        }))])
+        // Likewise the labels, when there are any to enforce.
+        if !self.labels.is_empty() {
+            let current = read_labels(session, path, true, std::iter::once(&self.labels)).await?;
+            if let Some(labels) = self.wrong_labels(current.get(path)) {
+                changes.push(Box::new(FileChange::WrongLabels(labels)));
+            }
+        }
+        Ok(changes)
     }
 }
 
@@ -275,6 +627,224 @@ pub struct FileSetSpec {
     pub root: PathBuf,
     /// Every included file, with its absolute target `path`, content and hash.
     pub files: Vec<FileSpec>,
+    /// Modes for `root` and the directories under it, from `dir_mode` or
+    /// `preserve_mode`. Empty when directory modes are not cook's to manage.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub directories: Vec<DirectoryMode>,
+    /// Mirror the source: delete remote files under `root` that are not in
+    /// `files`, except those matching `keep`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub purge: bool,
+    /// The `exclude` globs, as matched against paths relative to `root`. An
+    /// excluded file was never cook's to ship, so it is not cook's to delete
+    /// either — a `.env` written on the host, or uploads under `media/`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keep: Vec<String>,
+    /// The ignore files of the source tree. What they name was left out of
+    /// `files`, so a purge leaves it alone on the host, as with `keep`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ignored: Vec<IgnoreRules>,
+    /// Hash every file under `root` on each check instead of trusting the
+    /// manifest of what was last applied under `/var/lib/cook/manifest`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub paranoid: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectoryMode {
+    pub path: PathBuf,
+    pub mode: u32,
+}
+
+/// The `.gitignore` and `.cookignore` patterns of one directory of a `cp`
+/// source.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IgnoreRules {
+    /// The directory, relative to the root of the copy.
+    pub dir: PathBuf,
+    /// Its `.gitignore` lines, then its `.cookignore` lines, so the latter
+    /// win where they disagree.
+    pub patterns: Vec<String>,
+}
+
+/// Whether `relative`, a file under `root`, is one the ignore rules left
+/// out. As in git, each directory on the way down is checked first, and a
+/// directory that is ignored takes everything under it along; for each
+/// path, the nearest ignore file with an opinion decides.
+fn is_ignored(root: &Path, rules: &[(PathBuf, ignore::gitignore::Gitignore)], relative: &Path) -> bool {
+    use ignore::Match;
+
+    let components: Vec<_> = relative.components().collect();
+    let mut path = PathBuf::new();
+    for (i, component) in components.iter().enumerate() {
+        path.push(component);
+        let is_dir = i + 1 < components.len();
+        // Walked parents first, so in reverse the nearest file comes first.
+        for (dir, matcher) in rules.iter().rev() {
+            if !path.strip_prefix(dir).is_ok_and(|rest| !rest.as_os_str().is_empty()) {
+                continue;
+            }
+            match matcher.matched(root.join(&path), is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => break,
+                Match::None => {}
+            }
+        }
+    }
+    false
+}
+
+impl FileSetSpec {
+    /// Remote files under `root` that a purge would delete, in path order.
+    fn extra_files<'a>(&self, remote: impl IntoIterator<Item = &'a str>) -> Result<Vec<PathBuf>, Error> {
+        let mut keep = GlobSetBuilder::new();
+        for pattern in &self.keep {
+            keep.add(Glob::new(pattern)?);
+        }
+        let keep = keep.build()?;
+        let mut ignored = Vec::new();
+        for rules in &self.ignored {
+            let mut matcher = ignore::gitignore::GitignoreBuilder::new(self.root.join(&rules.dir));
+            for pattern in &rules.patterns {
+                matcher.add_line(None, pattern)?;
+            }
+            ignored.push((rules.dir.clone(), matcher.build()?));
+        }
+        let no_includes = GlobSet::empty();
+        let local: std::collections::HashSet<&Path> = self.files.iter().map(|f| f.path.as_path()).collect();
+        let mut extra: Vec<PathBuf> = remote
+            .into_iter()
+            .map(Path::new)
+            .filter(|path| !local.contains(path))
+            .filter(|path| {
+                // A file `find` reported outside `root` can't happen, but if
+                // it did it is certainly not ours to delete.
+                path.strip_prefix(&self.root).is_ok_and(|relative| {
+                    should_include_path(relative, &no_includes, &keep) && !is_ignored(&self.root, &ignored, relative)
+                })
+            })
+            .map(Path::to_path_buf)
+            .collect();
+        extra.sort();
+        Ok(extra)
+    }
+
+    /// Each file whose content is known up front, with its hash: everything
+    /// but a `url` given without a `sha256`.
+    fn known_hashes(&self) -> Vec<(PathBuf, String)> {
+        self.files
+            .iter()
+            .filter_map(|file| {
+                let sha256 = match &file.content {
+                    FileContent::Content(_, sha256) | FileContent::Path(_, sha256) => sha256,
+                    FileContent::Url(source) => source.sha256.as_ref()?,
+                };
+                Some((file.path.clone(), sha256.clone()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "ssh")]
+impl FileSetSpec {
+    /// Hash every file under `root`, and take the modes if any are being
+    /// enforced: the `--paranoid` check, and the one for a root with no
+    /// manifest yet.
+    async fn hash_remote_tree(
+        &self,
+        session: &openssh::Session,
+        root: &str,
+    ) -> Result<(HashMap<String, String>, HashMap<String, u32>), Error> {
+        // Hash every existing file under `root` in a single round-trip. `find`
+        // errors (e.g. missing root) are swallowed so it degrades to "no remote
+        // files", which makes every local file appear missing and get uploaded.
+        let script = format!(
+            "find {} -type f -print0 2>/dev/null | xargs -0 sha256sum 2>/dev/null",
+            sh_single_quote(root)
+        );
+        let output = session.command("sh").arg("-c").arg(&script).output().await?;
+        let hashes = parse_sha256sums(&String::from_utf8_lossy(&output.stdout));
+
+        // Modes, if any are being enforced: a second whole-tree walk rather
+        // than a `stat` per file, for the same reason the hashes are one call.
+        let mut modes: HashMap<String, u32> = HashMap::new();
+        if self.files.iter().any(|f| f.mode.is_some()) {
+            let script = format!(
+                "find {} -type f -exec stat -c '{}' {{}} + 2>/dev/null",
+                sh_single_quote(root),
+                manifest::STAT_FORMAT
+            );
+            let output = session.command("sh").arg("-c").arg(&script).output().await?;
+            modes = manifest::parse_stats(&String::from_utf8_lossy(&output.stdout))
+                .into_iter()
+                .map(|(path, stat)| (path, stat.mode))
+                .collect();
+        }
+        Ok((hashes, modes))
+    }
+
+    /// Hashes and modes of the files under `root`, from a listing of sizes
+    /// and mtimes checked against the manifest. Only files the manifest
+    /// can't vouch for are hashed. A file whose hash nobody needs, one cook
+    /// doesn't manage or a `url` without a `sha256`, maps to an empty hash:
+    /// all that matters is that it exists.
+    ///
+    /// The returned flag says the manifest is missing or out of date, and
+    /// should be written again even if nothing else changes.
+    async fn scan_remote_tree(
+        &self,
+        session: &openssh::Session,
+        root: &str,
+    ) -> Result<(HashMap<String, String>, HashMap<String, u32>, bool), Error> {
+        let script = format!(
+            "cat {} 2>/dev/null; printf '\\0'; find {} -type f -exec stat -c '{}' {{}} + 2>/dev/null",
+            sh_single_quote(&manifest::manifest_path(root)),
+            sh_single_quote(root),
+            manifest::STAT_FORMAT
+        );
+        let output = session.command("sh").arg("-c").arg(&script).output().await?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let (manifest, listing) = stdout.split_once('\0').unwrap_or(("", &stdout));
+        let manifest = Manifest::parse(manifest);
+        if manifest.is_empty() {
+            let (hashes, modes) = self.hash_remote_tree(session, root).await?;
+            return Ok((hashes, modes, true));
+        }
+
+        let wanted: HashMap<PathBuf, String> = self.known_hashes().into_iter().collect();
+        let stats = manifest::parse_stats(listing);
+        let mut hashes: HashMap<String, String> = HashMap::new();
+        let mut suspicious: Vec<&str> = Vec::new();
+        for (path, stat) in &stats {
+            if !wanted.contains_key(Path::new(path)) {
+                hashes.insert(path.clone(), String::new());
+            } else if let Some(sha256) = manifest.sha256(path, stat) {
+                hashes.insert(path.clone(), sha256.to_string());
+            } else {
+                suspicious.push(path);
+            }
+        }
+        // In batches, to stay well under the host's limit on arguments.
+        for paths in suspicious.chunks(512) {
+            let quoted: Vec<String> = paths.iter().map(|p| sh_single_quote(p)).collect();
+            let script = format!("sha256sum -- {} 2>/dev/null", quoted.join(" "));
+            let output = session.command("sh").arg("-c").arg(&script).output().await?;
+            hashes.extend(parse_sha256sums(&String::from_utf8_lossy(&output.stdout)));
+        }
+        let modes = stats.iter().map(|(path, stat)| (path.clone(), stat.mode)).collect();
+        Ok((hashes, modes, !suspicious.is_empty()))
+    }
+}
+
+/// Parse `sha256sum` output: "<64-hex-hash>  <path>" per line. The path can
+/// contain spaces, so split on the two-space separator only.
+#[cfg(feature = "ssh")]
+fn parse_sha256sums(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once("  "))
+        .map(|(hash, path)| (path.to_string(), hash.to_string()))
+        .collect()
 }
 
 impl Rule for FileSetSpec {
@@ -300,56 +870,85 @@ impl Rule for FileSetSpec {
 #[async_trait::async_trait]
 impl RuleOverSsh for FileSetSpec {
     async fn check_ssh(&self, session: &openssh::Session) -> Result<Vec<Box<dyn Modification>>, Error> {
-        // Hash every existing file under `root` in a single round-trip. `find`
-        // errors (e.g. missing root) are swallowed so it degrades to "no remote
-        // files", which makes every local file appear missing and get uploaded.
         let root = self.root.to_str().ok_or("root path is not valid utf-8")?;
-        let script = format!(
-            "find {} -type f -print0 2>/dev/null | xargs -0 sha256sum 2>/dev/null",
-            sh_single_quote(root)
-        );
-        let output = session.command("sh").arg("-c").arg(&script).output().await?;
-        let stdout = String::from_utf8_lossy(&output.stdout);
-
-        // Parse `sha256sum` output: "<64-hex-hash>  <path>" per line. The path can
-        // contain spaces, so split on the two-space separator only.
-        let mut remote: HashMap<&str, &str> = HashMap::new();
-        for line in stdout.lines() {
-            if let Some((hash, path)) = line.split_once("  ") {
-                remote.insert(path, hash);
-            }
-        }
+        let (hashes, remote_modes, refresh) = if self.paranoid {
+            let (hashes, modes) = self.hash_remote_tree(session, root).await?;
+            (hashes, modes, false)
+        } else {
+            self.scan_remote_tree(session, root).await?
+        };
+        let remote: HashMap<&str, &str> = hashes.iter().map(|(p, h)| (p.as_str(), h.as_str())).collect();
 
-        // Modes, if any are being enforced: a second whole-tree walk rather
-        // than a `stat` per file, for the same reason the hashes are one call.
-        // The `%a  %n` separator mirrors sha256sum's, so paths with spaces
-        // survive the same way.
-        let mut remote_modes: HashMap<String, u32> = HashMap::new();
-        if self.files.iter().any(|f| f.mode.is_some()) {
+        let mut changes: Vec<Box<dyn Modification>> = Vec::new();
+        // Directories first, so files are uploaded into directories that
+        // already have their modes.
+        if !self.directories.is_empty() {
             let script = format!(
-                "find {} -type f -exec stat -c '%a  %n' {{}} + 2>/dev/null",
+                "find {} -type d -exec stat -c '%a  %n' {{}} + 2>/dev/null",
                 sh_single_quote(root)
             );
             let output = session.command("sh").arg("-c").arg(&script).output().await?;
-            for line in String::from_utf8_lossy(&output.stdout).lines() {
-                if let Some((mode, path)) = line.split_once("  ")
-                    && let Ok(mode) = u32::from_str_radix(mode.trim(), 8)
-                {
-                    remote_modes.insert(path.to_string(), mode);
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let remote_dirs: HashMap<&str, u32> = stdout
+                .lines()
+                .filter_map(|line| line.split_once("  "))
+                .filter_map(|(mode, path)| Some((path, u32::from_str_radix(mode.trim(), 8).ok()?)))
+                .collect();
+            for directory in &self.directories {
+                let path_str = directory.path.to_str().ok_or("directory path is not valid utf-8")?;
+                match remote_dirs.get(path_str) {
+                    None => changes.push(Box::new(FileChange::MissingDirectory(MissingDirectory {
+                        path: directory.path.clone(),
+                        mode: Some(directory.mode),
+                        owner: None,
+                        group: None,
+                    }))),
+                    Some(&mode) if mode != directory.mode => changes.push(Box::new(FileChange::WrongMode(WrongMode {
+                        path: directory.path.clone(),
+                        mode: directory.mode,
+                    }))),
+                    Some(_) => {}
                 }
             }
         }
-
-        let mut changes: Vec<Box<dyn Modification>> = Vec::new();
+        // Labels, if any are being enforced, in one walk of the tree like the
+        // modes. Only the files already right are compared against them;
+        // the rest are about to be replaced, and get every label afresh.
+        let current_labels = if self.files.iter().any(|f| !f.labels.is_empty()) {
+            read_labels(session, root, false, self.files.iter().map(|f| &f.labels)).await?
+        } else {
+            HashMap::new()
+        };
+        let mut labels = Vec::new();
+        // New files go to the host together when there are enough of them to
+        // be worth a tar stream; see `batch`.
+        let mut batch = Vec::new();
         for file in &self.files {
             let path_str = file.path.to_str().ok_or("file path is not valid utf-8")?;
             let needs_change = match &file.content {
-                FileContent::Content(_, sha256) => remote.get(path_str) != Some(&sha256.as_str()),
-                FileContent::Url(_) => !remote.contains_key(path_str),
+                FileContent::Content(_, sha256) | FileContent::Path(_, sha256) => {
+                    remote.get(path_str) != Some(&sha256.as_str())
+                }
+                FileContent::Url(source) => match &source.sha256 {
+                    Some(sha256) => remote.get(path_str) != Some(&sha256.as_str()),
+                    None => !remote.contains_key(path_str),
+                },
+            };
+            let current = if needs_change {
+                None
+            } else {
+                current_labels.get(path_str)
             };
+            labels.extend(file.wrong_labels(current));
             if needs_change {
                 // The upload carries the mode with it.
-                changes.push(Box::new(FileChange::MissingFile(file.missing_file())));
+                let exists = remote.contains_key(path_str);
+                let missing = file.missing_file(exists);
+                if !exists && MissingFiles::accepts(&missing) {
+                    batch.push(missing);
+                } else {
+                    changes.push(Box::new(FileChange::MissingFile(missing)));
+                }
             } else if let Some(mode) = file.mode
                 && remote_modes.get(path_str) != Some(&mode)
             {
@@ -359,18 +958,88 @@ impl RuleOverSsh for FileSetSpec {
                 })));
             }
         }
+        match batch.len() {
+            0 => {}
+            1 => changes.push(Box::new(FileChange::MissingFile(batch.remove(0)))),
+            _ => changes.push(Box::new(FileChange::MissingFiles(MissingFiles { files: batch }))),
+        }
+        for wrong in labels {
+            changes.push(Box::new(FileChange::WrongLabels(wrong)));
+        }
+        // Recorded before any deletion, so that a failed one still leaves
+        // the manifest describing the files that did arrive.
+        if refresh || !changes.is_empty() {
+            changes.push(Box::new(FileChange::WriteManifest(WriteManifest {
+                root: self.root.clone(),
+                files: self.known_hashes(),
+                refresh_only: changes.is_empty(),
+            })));
+        }
+        // Deletions come last, so the new tree is in place before anything
+        // the old one had is taken away.
+        if self.purge {
+            for path in self.extra_files(remote.keys().copied())? {
+                changes.push(Box::new(FileChange::ExtraFile(ExtraFile { path })));
+            }
+        }
         Ok(changes)
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 pub struct MissingFile {
-    path: PathBuf,
+    pub(crate) path: PathBuf,
     #[serde(skip)]
-    content: FileContent,
-    owner: Option<u32>,
-    group: Option<u32>,
-    mode: Option<u32>,
+    pub(crate) content: FileContent,
+    pub(crate) owner: Option<u32>,
+    pub(crate) group: Option<u32>,
+    pub(crate) mode: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) validate: Option<String>,
+    /// Where the file being replaced is copied to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) backup: Option<PathBuf>,
+}
+
+#[cfg(feature = "ssh")]
+impl MissingFile {
+    /// Upload the file on its own: over sftp to a temporary file, renamed
+    /// into place once its mode, validation and backup are done.
+    pub(crate) async fn apply_ssh(&self, session: &std::sync::Arc<openssh::Session>) -> Result<(), Error> {
+        session
+            .command("mkdir")
+            .arg("-p")
+            .arg(self.path.parent().unwrap().to_str().unwrap())
+            .status()
+            .await?;
+        // Mode and owner are set on the temporary file, so the new
+        // content is never visible with the wrong permissions.
+        let attributes = atomic::Attributes {
+            mode: self.mode,
+            owner: self.owner,
+            group: self.group,
+        };
+        let safeguards = atomic::Safeguards {
+            validate: self.validate.as_deref(),
+            backup: self.backup.as_deref(),
+        };
+        match &self.content {
+            FileContent::Content(content, _) => {
+                atomic::upload(session, &self.path, content, &attributes, &safeguards).await?
+            }
+            FileContent::Path(local, sha256) => {
+                atomic::upload_local(session, &self.path, local, sha256, &attributes, &safeguards).await?
+            }
+            FileContent::Url(source) => url::install(source, session, &self.path, &attributes, &safeguards).await?,
+        }
+        Ok(())
+    }
+}
+
+/// A file under a purged `cp` root that the source no longer has.
+#[derive(Debug, Serialize)]
+pub struct ExtraFile {
+    path: PathBuf,
 }
 
 /// The file is already correct, but its permission bits are not.
@@ -380,29 +1049,18 @@ pub struct WrongMode {
     mode: u32,
 }
 
-// #[derive(Debug, Serialize)]
-// pub struct MissingDirectory {
-//     path: String,
-//     owner: Option<u32>,
-//     group: Option<u32>,
-//     mode: u32,
-// }
-
-// #[derive(Debug, Serialize)]
-// pub struct MissingSymlink {
-//     path: String,
-//     target: String,
-//     owner: Option<u32>,
-//     group: Option<u32>,
-//     mode: u32,
-// }
-
 #[derive(Debug, Serialize)]
 pub enum FileChange {
     MissingFile(MissingFile),
+    MissingFiles(MissingFiles),
+    WriteManifest(WriteManifest),
     WrongMode(WrongMode),
-    // MissingDirectory(MissingDirectory),
-    // MissingSymlink(MissingSymlink),
+    WrongLabels(WrongLabels),
+    ExtraFile(ExtraFile),
+    MissingDirectory(MissingDirectory),
+    WrongDirectory(WrongDirectory),
+    MissingSymlink(MissingSymlink),
+    WrongSymlink(WrongSymlink),
 }
 
 impl Modification for FileChange {
@@ -415,9 +1073,15 @@ impl Modification for FileChange {
         // let path = Path::new(&rule.path);
         match self {
             FileChange::MissingFile { .. } => todo!(),
+            FileChange::MissingFiles { .. } => todo!(),
+            FileChange::WriteManifest { .. } => todo!(),
             FileChange::WrongMode { .. } => todo!(),
-            // FileChange::MissingDirectory { .. } => todo!(),
-            // FileChange::MissingSymlink { .. } => todo!(),
+            FileChange::WrongLabels { .. } => todo!(),
+            FileChange::ExtraFile { .. } => todo!(),
+            FileChange::MissingDirectory { .. } => todo!(),
+            FileChange::WrongDirectory { .. } => todo!(),
+            FileChange::MissingSymlink { .. } => todo!(),
+            FileChange::WrongSymlink { .. } => todo!(),
             // FileChange::WrongOwner { path, mode } => todo!(),
             // FileChange::WrongGroup { path, mode } => todo!(),
         }
@@ -430,50 +1094,34 @@ impl Modification for FileChange {
     fn fmt_json(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "file change")
     }
+
+    fn is_bookkeeping(&self) -> bool {
+        matches!(self, FileChange::WriteManifest(manifest) if manifest.refresh_only)
+    }
 }
 
 #[cfg(feature = "ssh")]
 #[async_trait::async_trait]
 impl ModificationOverSsh for FileChange {
     async fn apply_ssh(&self, session: std::sync::Arc<openssh::Session>) -> Result<(), Error> {
-        use openssh_sftp_client::{Sftp, SftpOptions};
         match self {
-            FileChange::MissingFile(file) => {
-                session
-                    .command("mkdir")
-                    .arg("-p")
-                    .arg(file.path.parent().unwrap().to_str().unwrap())
-                    .status()
-                    .await?;
-                match &file.content {
-                    FileContent::Content(content, _) => {
-                        let sftp = Sftp::from_clonable_session(session.clone(), SftpOptions::new()).await?;
-                        let mut f = sftp.create(file.path.to_str().unwrap()).await?;
-                        f.write_all(&content).await?;
-                        f.close().await?;
-                    }
-                    FileContent::Url(url) => {
-                        // Download file using curl over SSH and save to the target path
-                        session
-                            .command("curl")
-                            .arg("-L")
-                            .arg("-o")
-                            .arg(file.path.to_str().unwrap())
-                            .arg(url)
-                            .status()
-                            .await?;
-                    }
-                }
-                // sftp creates at its own default and leaves an existing file's
-                // mode alone, so anything that has to be executable (or has to
-                // not be world-readable) is set here, after the bytes land.
-                if let Some(mode) = file.mode {
-                    chmod(&session, &file.path, mode).await?;
+            FileChange::MissingFile(file) => file.apply_ssh(&session).await?,
+            FileChange::MissingFiles(files) => files.apply_ssh(&session).await?,
+            FileChange::WriteManifest(manifest) => manifest.apply_ssh(&session).await?,
+            FileChange::WrongMode(change) => chmod(&session, &change.path, change.mode).await?,
+            FileChange::WrongLabels(wrong) => wrong.apply_ssh(&session).await?,
+            FileChange::ExtraFile(extra) => {
+                let path = extra.path.to_str().ok_or("file path is not valid utf-8")?;
+                let output = session.command("rm").arg("-f").arg(path).output().await?;
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    return Err(format!("removing {path} failed: {}", stderr.trim()).into());
                 }
             }
-            FileChange::WrongMode(change) => chmod(&session, &change.path, change.mode).await?,
-            // FileChange::MissingDirectory { .. } => todo!(),
-            // FileChange::MissingSymlink { .. } => todo!(),
+            FileChange::MissingDirectory(missing) => missing.apply_ssh(&session).await?,
+            FileChange::WrongDirectory(wrong) => wrong.apply_ssh(&session).await?,
+            FileChange::MissingSymlink(link) => ln_sf(&session, &link.path, &link.target).await?,
+            FileChange::WrongSymlink(link) => ln_sf(&session, &link.path, &link.target).await?,
         }
         Ok(())
     }
@@ -533,6 +1181,127 @@ mod tests {
         mode_of(r#"cp a b mode="799""#);
     }
 
+    #[test]
+    fn newline_normalization_ends_content_with_one_newline() {
+        assert_eq!(with_trailing_newline("motd"), "motd\n");
+        assert_eq!(with_trailing_newline("motd\n"), "motd\n");
+        assert_eq!(with_trailing_newline("motd\n\n\r\n"), "motd\n");
+        assert_eq!(with_trailing_newline("a\nb"), "a\nb\n");
+        // An empty file stays empty rather than becoming a blank line.
+        assert_eq!(with_trailing_newline(""), "");
+    }
+
+    fn fileset(purge: bool, keep: &[&str], files: &[&str]) -> FileSetSpec {
+        FileSetSpec {
+            root: PathBuf::from("/srv/site"),
+            files: files
+                .iter()
+                .map(|f| FileSpec::new(PathBuf::from(f), Vec::new(), None))
+                .collect(),
+            directories: Vec::new(),
+            purge,
+            keep: keep.iter().map(|k| format!("**/{k}")).collect(),
+            ignored: Vec::new(),
+            paranoid: false,
+        }
+    }
+
+    #[test]
+    fn a_copied_file_is_hashed_without_being_kept() {
+        let src = std::env::temp_dir().join(format!("cook-new-copy-{}", std::process::id()));
+        // Larger than one read, so the hash spans several chunks.
+        let content = vec![b'x'; 200_000];
+        fs::write(&src, &content).unwrap();
+        let context = Context::new(std::env::temp_dir());
+        let sha256 = context.sha256_file(&src);
+        let file = FileSpec::from_local(src.clone(), PathBuf::from("/srv/x"), None, sha256);
+        fs::remove_file(&src).unwrap();
+        let FileContent::Path(path, sha256) = file.content else {
+            panic!("a copy refers to its source: {:?}", file.content);
+        };
+        assert_eq!(path, src);
+        assert_eq!(sha256, format!("{:x}", Sha256::digest(&content)));
+    }
+
+    #[test]
+    fn every_directory_between_the_root_and_a_file_gets_a_mode() {
+        let files = [
+            "/srv/site/index.html",
+            "/srv/site/css/vendor/a.css",
+            "/srv/site/css/b.css",
+        ]
+        .map(|f| FileSpec::new(PathBuf::from(f), Vec::new(), None));
+        let directories = directory_modes(Path::new("/src/site"), Path::new("/srv/site"), &files, |local| {
+            if local.ends_with("vendor") { 0o700 } else { 0o755 }
+        });
+        let directories: Vec<(&str, u32)> = directories.iter().map(|d| (d.path.to_str().unwrap(), d.mode)).collect();
+        assert_eq!(
+            directories,
+            vec![
+                ("/srv/site", 0o755),
+                ("/srv/site/css", 0o755),
+                ("/srv/site/css/vendor", 0o700),
+            ]
+        );
+    }
+
+    #[test]
+    fn purge_deletes_only_what_the_source_lacks() {
+        let set = fileset(true, &[], &["/srv/site/index.html", "/srv/site/css/a.css"]);
+        let remote = ["/srv/site/css/a.css", "/srv/site/old.html", "/srv/site/index.html"];
+        assert_eq!(
+            set.extra_files(remote).unwrap(),
+            vec![PathBuf::from("/srv/site/old.html")]
+        );
+    }
+
+    #[test]
+    fn purge_keeps_excluded_files_and_directories() {
+        let set = fileset(true, &[".env", "media"], &["/srv/site/index.html"]);
+        let remote = [
+            "/srv/site/.env",
+            "/srv/site/media/upload.png",
+            "/srv/site/media/2026/deep.png",
+            "/srv/site/stale.js",
+        ];
+        assert_eq!(
+            set.extra_files(remote).unwrap(),
+            vec![PathBuf::from("/srv/site/stale.js")]
+        );
+    }
+
+    #[test]
+    fn purge_keeps_what_the_ignore_files_name() {
+        let mut set = fileset(true, &[], &["/srv/site/index.html", "/srv/site/docs/notes.md"]);
+        let rules = |dir: &str, patterns: &[&str]| IgnoreRules {
+            dir: PathBuf::from(dir),
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+        };
+        set.ignored = vec![
+            rules("", &[".env", "uploads/", "*.log", "!keep.log", ".git", ".cookignore"]),
+            rules("docs", &["draft.md", "!debug.log"]),
+        ];
+        let remote = [
+            "/srv/site/.env",
+            "/srv/site/.git/HEAD",
+            "/srv/site/uploads/2026/a.png",
+            "/srv/site/app.log",
+            "/srv/site/keep.log",
+            "/srv/site/docs/draft.md",
+            "/srv/site/docs/debug.log",
+            "/srv/site/docs/old.md",
+            "/srv/site/index.html",
+        ];
+        assert_eq!(
+            set.extra_files(remote).unwrap(),
+            vec![
+                PathBuf::from("/srv/site/docs/debug.log"),
+                PathBuf::from("/srv/site/docs/old.md"),
+                PathBuf::from("/srv/site/keep.log"),
+            ]
+        );
+    }
+
     #[test]
     fn test_include_matches_directory_and_children() {
         let includes = GlobSetBuilder::new()
@@ -586,4 +1355,17 @@ mod tests {
         // Should not match non-matching files
         assert!(!should_include_path(Path::new("foo/bar/main.rs"), &includes, &excludes));
     }
+
+    #[test]
+    fn a_manifest_refreshed_on_its_own_is_not_a_change() {
+        let manifest = |refresh_only| {
+            FileChange::WriteManifest(WriteManifest {
+                root: PathBuf::from("/srv/site"),
+                files: Vec::new(),
+                refresh_only,
+            })
+        };
+        assert!(manifest(true).is_bookkeeping());
+        assert!(!manifest(false).is_bookkeeping());
+    }
 }
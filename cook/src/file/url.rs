@@ -0,0 +1,451 @@
+//! `file <path> url=<url>` — content downloaded rather than shipped from the
+//! Cookfile's tree.
+//!
+//! With a `sha256`, the download is checked like any other content: the host's
+//! file is hashed, and a fresh download is verified against the same hash
+//! before it replaces anything. Without one, `cache=etag` or
+//! `cache=last-modified` asks the server whether the file changed since the
+//! last download instead, remembering its answer on the host under
+//! [`VALIDATOR_DIR`]. With neither, a file that exists is left alone.
+//!
+//! The download runs on the host by default. `fetch=controller` runs it on the
+//! machine running cook and pushes the result, for hosts that cannot reach the
+//! URL (or should not hold the credentials in it). Either way the commands are
+//! the same `curl` invocations, so they are tested here by running them
+//! locally against a stand-in server.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{Error, sh_single_quote};
+
+#[cfg(feature = "ssh")]
+use crate::file::atomic;
+
+/// Where the server's `ETag`/`Last-Modified` for each downloaded file is kept
+/// on the host, one file per destination path.
+pub const VALIDATOR_DIR: &str = "/var/lib/cook/url-cache";
+
+/// Where a download runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fetch {
+    #[default]
+    Host,
+    Controller,
+}
+
+/// How to tell whether a download without a checksum is still current.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Freshness {
+    Etag,
+    LastModified,
+}
+
+impl Freshness {
+    fn response_header(self) -> &'static str {
+        match self {
+            Freshness::Etag => "etag",
+            Freshness::LastModified => "last-modified",
+        }
+    }
+
+    fn request_header(self) -> &'static str {
+        match self {
+            Freshness::Etag => "If-None-Match",
+            Freshness::LastModified => "If-Modified-Since",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UrlSource {
+    pub url: String,
+    /// Expected sha256 of the download, lowercase hex.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub fetch: Fetch,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache: Option<Freshness>,
+}
+
+impl UrlSource {
+    /// Build a source from a node's `url`/`sha256`/`fetch`/`cache` properties,
+    /// rejecting combinations that could never be what the author meant.
+    pub(crate) fn new(
+        url: String,
+        sha256: Option<String>,
+        fetch: Option<&str>,
+        cache: Option<&str>,
+        path: &Path,
+    ) -> UrlSource {
+        let path = path.display();
+        if let Some(sha256) = &sha256 {
+            assert!(
+                sha256.len() == 64 && sha256.bytes().all(|b| b.is_ascii_hexdigit()),
+                "file {path}: sha256 must be 64 hex digits, got {sha256:?}"
+            );
+        }
+        let fetch = match fetch {
+            None | Some("host") => Fetch::Host,
+            Some("controller") => Fetch::Controller,
+            Some(z) => panic!("file {path}: fetch must be host or controller, got {z:?}"),
+        };
+        let cache = match cache {
+            None => None,
+            Some("etag") => Some(Freshness::Etag),
+            Some("last-modified") => Some(Freshness::LastModified),
+            Some(z) => panic!("file {path}: cache must be etag or last-modified, got {z:?}"),
+        };
+        assert!(
+            sha256.is_none() || cache.is_none(),
+            "file {path}: a sha256 already says whether the file changed; drop `cache`"
+        );
+        UrlSource {
+            url,
+            sha256: sha256.map(|s| s.to_ascii_lowercase()),
+            fetch,
+            cache,
+        }
+    }
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Host path holding the validator for the file downloaded to `path`.
+pub(crate) fn validator_path(path: &Path) -> String {
+    format!("{VALIDATOR_DIR}/{}", sha256_hex(path.as_os_str().as_encoded_bytes()))
+}
+
+/// A `HEAD` request that prints only the status code: `304` when the server
+/// says the copy identified by `validator` is still current.
+fn conditional_head_script(url: &str, cache: Freshness, validator: &str) -> String {
+    let header = format!("{}: {validator}", cache.request_header());
+    format!(
+        "curl -sSLI -o /dev/null -w '%{{http_code}}' -H {} {}",
+        sh_single_quote(&header),
+        sh_single_quote(url)
+    )
+}
+
+/// Read [`conditional_head_script`]'s output: `Ok(true)` if the file must be
+/// downloaded again.
+fn is_stale(stdout: &str, url: &str) -> Result<bool, Error> {
+    match stdout.trim() {
+        "304" => Ok(false),
+        "200" => Ok(true),
+        code => Err(format!("asking {url} whether it changed returned HTTP {code:?}").into()),
+    }
+}
+
+/// Download `url` to `out`, printing the response headers (every hop's, when
+/// redirected) to stdout. `-f` turns an HTTP error into a failed command
+/// rather than an error page saved as the file.
+fn download_script(url: &str, out: &str) -> String {
+    format!("curl -fsSL -D - -o {} {}", sh_single_quote(out), sh_single_quote(url))
+}
+
+/// The value of the `cache` header in the final response of a `curl -D -`
+/// dump. Redirects print one header block per hop; only the last one
+/// describes the content that was saved.
+fn validator_from_headers(headers: &str, cache: Freshness) -> Option<String> {
+    let last = headers
+        .split("\r\n\r\n")
+        .map(str::trim)
+        .filter(|block| !block.is_empty())
+        .last()?;
+    last.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case(cache.response_header())
+            .then(|| value.trim().to_string())
+    })
+}
+
+fn verify(bytes: &[u8], expected: &str, url: &str) -> Result<(), Error> {
+    let actual = sha256_hex(bytes);
+    if actual != expected {
+        return Err(format!("sha256 mismatch for {url}: expected {expected}, downloaded {actual}").into());
+    }
+    Ok(())
+}
+
+fn run_local(script: &str) -> Result<std::process::Output, Error> {
+    Ok(Command::new("sh").arg("-c").arg(script).output()?)
+}
+
+/// A fresh path for a controller-side download. Distinct per call, so
+/// concurrent downloads in one run do not share a file.
+fn local_download_path() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("cook-download-{}-{n}", std::process::id()))
+}
+
+/// Download `source` on the machine running cook, verified against its
+/// `sha256` when there is one. Returns the content and, with `cache` set, the
+/// validator to remember for the next run.
+pub(crate) fn fetch_on_controller(source: &UrlSource) -> Result<(Vec<u8>, Option<String>), Error> {
+    let out = local_download_path();
+    let out_str = out.to_str().ok_or("temporary path is not valid utf-8")?;
+    let output = run_local(&download_script(&source.url, out_str));
+    let content = std::fs::read(&out);
+    let _ = std::fs::remove_file(&out);
+    let output = output?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("downloading {} failed: {}", source.url, stderr.trim()).into());
+    }
+    let content = content?;
+    if let Some(expected) = &source.sha256 {
+        verify(&content, expected, &source.url)?;
+    }
+    let validator = source
+        .cache
+        .and_then(|cache| validator_from_headers(&String::from_utf8_lossy(&output.stdout), cache));
+    Ok((content, validator))
+}
+
+/// Run controller-side work, which blocks on `curl`, on tokio's blocking
+/// pool: on a worker thread a slow download would stall every other host's
+/// tasks until it finished.
+#[cfg(feature = "ssh")]
+async fn off_runtime<T: Send + 'static>(work: impl FnOnce() -> Result<T, Error> + Send + 'static) -> Result<T, Error> {
+    tokio::task::spawn_blocking(work).await?
+}
+
+#[cfg(feature = "ssh")]
+async fn run_remote(session: &openssh::Session, script: &str) -> Result<std::process::Output, Error> {
+    Ok(session.command("sh").arg("-c").arg(script).output().await?)
+}
+
+/// Whether the file at `path` must be downloaded again. Only meaningful
+/// without a `sha256`; a checksummed source is compared by hash instead.
+#[cfg(feature = "ssh")]
+pub(crate) async fn needs_download(source: &UrlSource, session: &openssh::Session, path: &Path) -> Result<bool, Error> {
+    let path_str = path.to_str().ok_or("file path is not valid utf-8")?;
+    // Existence and the stored validator in one round-trip.
+    let script = format!(
+        "if [ -f {p} ]; then echo present; cat {v} 2>/dev/null; else echo missing; fi",
+        p = sh_single_quote(path_str),
+        v = sh_single_quote(&validator_path(path)),
+    );
+    let output = run_remote(session, &script).await?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut lines = stdout.lines();
+    if lines.next() != Some("present") {
+        return Ok(true);
+    }
+    let Some(cache) = source.cache else {
+        return Ok(false);
+    };
+    // A file without a validator predates `cache` (or lost it); download once
+    // more to learn one rather than trusting a file of unknown vintage.
+    let Some(validator) = lines.next().map(str::trim).filter(|v| !v.is_empty()) else {
+        return Ok(true);
+    };
+    // Ask from wherever the download itself would run: a host that fetches
+    // nothing may not be able to reach the URL at all.
+    let script = conditional_head_script(&source.url, cache, validator);
+    let output = match source.fetch {
+        Fetch::Host => run_remote(session, &script).await?,
+        Fetch::Controller => off_runtime(move || run_local(&script)).await?,
+    };
+    is_stale(&String::from_utf8_lossy(&output.stdout), &source.url)
+}
+
+/// Download `source` and put it at `path`, remembering the server's validator
+/// when `cache` is set.
+///
+/// A host-side download lands beside `path` and is only renamed into place
+/// once its checksum checks out, so a bad download never replaces a good
+/// file. A controller-side download is verified before it is sent at all.
+#[cfg(feature = "ssh")]
+pub(crate) async fn install(
+    source: &UrlSource,
+    session: &std::sync::Arc<openssh::Session>,
+    path: &Path,
+    attributes: &atomic::Attributes,
+    safeguards: &atomic::Safeguards<'_>,
+) -> Result<(), Error> {
+    let path_str = path.to_str().ok_or("file path is not valid utf-8")?;
+    let validator = match source.fetch {
+        Fetch::Host => {
+            let tmp = atomic::temp_path(path)?;
+            let mut script = format!("set -e; {}", download_script(&source.url, &tmp));
+            if let Some(expected) = &source.sha256 {
+                script.push_str(&format!(
+                    "; echo {} | sha256sum -c --status - || {{ rm -f {t}; echo 'sha256 mismatch: expected {expected}' >&2; exit 1; }}",
+                    sh_single_quote(&format!("{expected}  {tmp}")),
+                    t = sh_single_quote(&tmp),
+                ));
+            }
+            let output = run_remote(session, &script).await?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(format!("downloading {} to {path_str} failed: {}", source.url, stderr.trim()).into());
+            }
+            atomic::finish(session, &tmp, path, attributes, safeguards).await?;
+            let headers = String::from_utf8_lossy(&output.stdout);
+            source.cache.and_then(|cache| validator_from_headers(&headers, cache))
+        }
+        Fetch::Controller => {
+            let source = source.clone();
+            let (content, validator) = off_runtime(move || fetch_on_controller(&source)).await?;
+            atomic::upload(session, path, &content, attributes, safeguards).await?;
+            validator
+        }
+    };
+    if source.cache.is_some() {
+        remember_validator(session, path, validator).await?;
+    }
+    Ok(())
+}
+
+/// Store (or, when the server sent none, forget) the validator for `path`.
+#[cfg(feature = "ssh")]
+async fn remember_validator(session: &openssh::Session, path: &Path, validator: Option<String>) -> Result<(), Error> {
+    let file = sh_single_quote(&validator_path(path));
+    let script = match validator {
+        Some(v) => format!(
+            "mkdir -p {VALIDATOR_DIR} && printf '%s\\n' {} > {file}",
+            sh_single_quote(&v)
+        ),
+        None => {
+            tracing::warn!(path = %path.display(), "server sent no validator; the file will be downloaded every run");
+            format!("rm -f {file}")
+        }
+    };
+    let output = run_remote(session, &script).await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!(
+            "remembering the validator for {} failed: {}",
+            path.display(),
+            stderr.trim()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    const BODY: &[u8] = b"#!/bin/sh\necho downloaded\n";
+    const ETAG: &str = "\"v1\"";
+
+    /// A stand-in for the download server: serves [`BODY`] with an `ETag`,
+    /// answers a matching `If-None-Match` with `304`, and redirects
+    /// `/redirect` to `/file` the way release hosting usually does.
+    fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind a local port");
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let request = String::from_utf8_lossy(&request).to_lowercase();
+                let head = request.starts_with("head ");
+                let response = if request.starts_with("get /redirect") || request.starts_with("head /redirect") {
+                    b"HTTP/1.1 302 Found\r\nLocation: /file\r\nContent-Length: 0\r\n\r\n".to_vec()
+                } else if request.contains(&format!("if-none-match: {}", ETAG.to_lowercase())) {
+                    format!("HTTP/1.1 304 Not Modified\r\nETag: {ETAG}\r\n\r\n").into_bytes()
+                } else {
+                    let mut r = format!(
+                        "HTTP/1.1 200 OK\r\nETag: {ETAG}\r\nContent-Length: {}\r\n\r\n",
+                        BODY.len()
+                    )
+                    .into_bytes();
+                    if !head {
+                        r.extend_from_slice(BODY);
+                    }
+                    r
+                };
+                let _ = stream.write_all(&response);
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    fn source(url: String, sha256: Option<String>, cache: Option<&str>) -> UrlSource {
+        UrlSource::new(url, sha256, Some("controller"), cache, Path::new("/usr/local/bin/x"))
+    }
+
+    #[test]
+    fn a_controller_download_is_verified_against_its_checksum() {
+        let base = serve();
+        let (content, _) =
+            fetch_on_controller(&source(format!("{base}/redirect"), Some(sha256_hex(BODY)), None)).expect("download");
+        assert_eq!(content, BODY);
+
+        let wrong = "0".repeat(64);
+        let err = fetch_on_controller(&source(format!("{base}/file"), Some(wrong), None)).expect_err("mismatch");
+        assert!(err.to_string().contains("sha256 mismatch"), "got: {err}");
+    }
+
+    #[test]
+    fn a_controller_download_reports_the_final_responses_validator() {
+        let base = serve();
+        let (_, validator) =
+            fetch_on_controller(&source(format!("{base}/redirect"), None, Some("etag"))).expect("download");
+        assert_eq!(validator.as_deref(), Some(ETAG));
+    }
+
+    #[test]
+    fn the_conditional_request_tells_current_from_changed() {
+        let url = format!("{}/file", serve());
+        let ask = |validator: &str| {
+            let output = run_local(&conditional_head_script(&url, Freshness::Etag, validator)).expect("curl runs");
+            is_stale(&String::from_utf8_lossy(&output.stdout), &url)
+        };
+        assert!(!ask(ETAG).unwrap(), "a matching etag is current");
+        assert!(ask("\"v0\"").unwrap(), "a different etag means a new download");
+    }
+
+    #[test]
+    fn only_the_last_redirect_hops_headers_count() {
+        let headers = "HTTP/1.1 302 Found\r\nETag: \"hop\"\r\nLocation: /b\r\n\r\n\
+                       HTTP/1.1 200 OK\r\nLast-Modified: Mon, 19 Oct 2026 10:00:00 GMT\r\netag: \"final\"\r\n\r\n";
+        assert_eq!(
+            validator_from_headers(headers, Freshness::Etag).as_deref(),
+            Some("\"final\"")
+        );
+        assert_eq!(
+            validator_from_headers(headers, Freshness::LastModified).as_deref(),
+            Some("Mon, 19 Oct 2026 10:00:00 GMT")
+        );
+        let no_etag = "HTTP/1.1 302 Found\r\nETag: \"hop\"\r\n\r\nHTTP/1.1 200 OK\r\n\r\n";
+        assert_eq!(validator_from_headers(no_etag, Freshness::Etag), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "drop `cache`")]
+    fn a_checksum_and_a_cache_mode_are_exclusive() {
+        source("http://example.invalid".to_string(), Some("a".repeat(64)), Some("etag"));
+    }
+
+    #[test]
+    #[should_panic(expected = "64 hex digits")]
+    fn a_malformed_checksum_is_rejected() {
+        source("http://example.invalid".to_string(), Some("abc".to_string()), None);
+    }
+}
@@ -5,7 +5,7 @@ use std::{
     ops::Range,
 };
 
-use crate::{Host, Rule, Sequencing};
+use crate::{Host, Notification, Rule, Sequencing};
 
 /// A schedulable group of rules produced by a single config node, plus the
 /// ordering/dependency edges declared on it. Units are the granularity at which
@@ -23,6 +23,8 @@ pub struct Unit {
     pub after: Vec<String>,
     pub before: Vec<String>,
     pub requires: Vec<String>,
+    /// Services to restart or reload if the unit applies any modification.
+    pub notify: Vec<Notification>,
 }
 
 impl Unit {
@@ -31,6 +33,12 @@ impl Unit {
     pub fn qualified(&self) -> String {
         format!("{}:{}", self.kind, self.name)
     }
+
+    /// Whether this is a `flush` node: no rules of its own, just the point
+    /// where notifications queued so far are delivered.
+    pub fn is_flush(&self) -> bool {
+        self.kind == "flush"
+    }
 }
 
 /// Resolved dependencies for one unit, by unit index.
@@ -63,6 +71,15 @@ pub struct State {
     // schedulable units over `host_rules`, one per config node that produced rules
     units: Vec<Unit>,
     hosts: Vec<Host>,
+    /// The rule the last [`State::rule_mut`] handed out, so the node that
+    /// merged into it can bring its sequencing along.
+    merged_into: Option<usize>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl State {
@@ -72,6 +89,7 @@ impl State {
             units: Vec::new(),
             hosts: Vec::new(),
             _infra_rules: Vec::new(),
+            merged_into: None,
         }
     }
 
@@ -104,6 +122,65 @@ impl State {
             after: sequencing.after,
             before: sequencing.before,
             requires: sequencing.requires,
+            notify: sequencing.notify,
+        });
+    }
+
+    /// Attach the sequencing of a node that added to an earlier node's rule
+    /// (a second `line` for the same file, say) to the unit owning that rule,
+    /// which is where the node's work now runs. A unit has one name, so the
+    /// two nodes may not name it differently.
+    pub fn merge_unit(&mut self, rule: usize, sequencing: Sequencing) {
+        let unit = self
+            .units
+            .iter_mut()
+            .find(|unit| unit.rules.contains(&rule))
+            .expect("a merged rule belongs to the unit of the node that made it");
+        let default_name = self.host_rules[unit.rules.start].identifier();
+        if let Some(name) = sequencing.name {
+            assert!(
+                unit.name == default_name || unit.name == name,
+                "{} is named both {} and {name}; name it on one of its nodes",
+                unit.qualified(),
+                unit.name
+            );
+            unit.name = name;
+        }
+        unit.after.extend(sequencing.after);
+        unit.before.extend(sequencing.before);
+        unit.requires.extend(sequencing.requires);
+        for notification in sequencing.notify {
+            if !unit.notify.contains(&notification) {
+                unit.notify.push(notification);
+            }
+        }
+    }
+
+    /// The rule the last [`State::rule_mut`] call merged into, if any since
+    /// this was last asked.
+    pub fn take_merged_rule(&mut self) -> Option<usize> {
+        self.merged_into.take()
+    }
+
+    /// Register a `flush` node. It runs after every unit declared before it
+    /// and before every unit declared after it, so those are the ones whose
+    /// notifications it delivers. Flushes are named by their position among
+    /// flushes (`flush:1`, `flush:2`, ...) unless given a `name`.
+    pub fn add_flush(&mut self, sequencing: Sequencing) {
+        assert!(
+            sequencing.notify.is_empty(),
+            "flush delivers notifications; it can't send one"
+        );
+        let count = self.units.iter().filter(|unit| unit.is_flush()).count();
+        let at = self.host_rules.len();
+        self.units.push(Unit {
+            kind: "flush",
+            name: sequencing.name.unwrap_or_else(|| (count + 1).to_string()),
+            rules: at..at,
+            after: sequencing.after,
+            before: sequencing.before,
+            requires: sequencing.requires,
+            notify: Vec::new(),
         });
     }
 
@@ -189,6 +266,18 @@ impl State {
                 }
             }
         }
+        // A flush splits the config in two: everything declared before it
+        // runs first, everything declared after it runs later. Ordering each
+        // unit against the nearest flush before it is enough.
+        let mut last_flush = None;
+        for (u, unit) in self.units.iter().enumerate() {
+            if unit.is_flush() {
+                edges[u].extend(last_flush.unwrap_or(0)..u);
+                last_flush = Some(u);
+            } else if let Some(flush) = last_flush {
+                edges[u].insert(flush);
+            }
+        }
         for (u, unit) in self.units.iter().enumerate() {
             if edges[u].contains(&u) {
                 return Err(anyhow::anyhow!("unit '{}' depends on itself", unit.qualified()).into());
@@ -243,6 +332,20 @@ impl State {
         self.host_rules.push(Box::new(rule));
     }
 
+    /// The first rule of type `R` that `f` accepts, for a node that adds to
+    /// an earlier node's rule rather than making one of its own.
+    ///
+    /// The rule is remembered for [`State::take_merged_rule`], so the merging
+    /// node's sequencing ends up on the earlier node's unit.
+    pub(crate) fn rule_mut<R: Rule>(&mut self, f: impl Fn(&R) -> bool) -> Option<&mut R> {
+        let (index, rule) = self.host_rules.iter_mut().enumerate().find_map(|(index, rule)| {
+            let rule: &mut dyn std::any::Any = rule.as_mut();
+            Some((index, rule.downcast_mut::<R>().filter(|rule| f(rule))?))
+        })?;
+        self.merged_into = Some(index);
+        Some(rule)
+    }
+
     pub fn merge(&mut self, other: State) {
         let offset = self.host_rules.len();
         self.host_rules.extend(other.host_rules);
@@ -9,7 +9,16 @@ pub fn add_node(node: &KdlNode, context: &Context, state: &mut State) {
     let sequencing = extract_sequencing(&mut node);
 
     let value = node.name().value();
+    if value == "flush" {
+        assert!(
+            node.entries().is_empty() && node.children().is_none_or(|c| c.nodes().is_empty()),
+            "flush takes no arguments"
+        );
+        state.add_flush(sequencing);
+        return;
+    }
     let start = state.rules().len();
+    state.take_merged_rule();
     let mut handled = false;
     for (keyword, add_rules_to_state) in context.kdl_rule_deserializers.iter() {
         if *keyword == value {
@@ -22,7 +31,11 @@ pub fn add_node(node: &KdlNode, context: &Context, state: &mut State) {
         panic!("Unknown rule type: {}", value);
     }
 
-    // Everything the node produced becomes one schedulable unit.
+    // Everything the node produced becomes one schedulable unit. A node that
+    // only added to an earlier node's rule joins that rule's unit instead.
     let end = state.rules().len();
-    state.add_unit(sequencing, start..end);
+    match state.take_merged_rule() {
+        Some(rule) if start == end => state.merge_unit(rule, sequencing),
+        _ => state.add_unit(sequencing, start..end),
+    }
 }
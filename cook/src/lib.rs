@@ -12,6 +12,7 @@ mod which;
 use ::kdl::KdlNode;
 use async_trait::async_trait;
 pub use file::api::*;
+pub use file::backup;
 pub use host::*;
 pub use kdl::add_node;
 pub use package::api::*;
@@ -21,9 +22,10 @@ pub use which::api::*;
 
 pub use context::Context;
 pub use global_state::{Schedule, State, Unit, UnitDeps};
-pub use seq::{SEQUENCING_KEYWORDS, Sequencing};
+pub use seq::{Notification, NotifyAction, NotifyQueue, SEQUENCING_KEYWORDS, Sequencing};
 
 use crate::{
+    file::directory::DirectorySpec, file::edit::EditSpec, file::link::LinkSpec, file::setting::SettingSpec,
     file::spec::FileSpec, package::spec::PackageSpec, service::spec::ServiceSpec, user::spec::UserSpec,
     which::spec::WhichSpec,
 };
@@ -35,7 +37,7 @@ pub trait FromKdl {
 }
 
 /// defines how to interact with a rule about a system/resource
-pub trait Rule: erased_serde::Serialize + std::fmt::Debug + Send + Sync + 'static {
+pub trait Rule: std::any::Any + erased_serde::Serialize + std::fmt::Debug + Send + Sync + 'static {
     fn downcast_ssh(&self) -> Option<&dyn RuleOverSsh> {
         None
     }
@@ -81,6 +83,13 @@ pub trait Modification: erased_serde::Serialize + Send + Sync + 'static {
     fn fmt_human_readable(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result;
 
     fn fmt_json(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result;
+
+    /// Whether this only updates cook's own records on the host, such as a
+    /// `cp` manifest refreshed while every file was already right. It is
+    /// applied like any other modification but not reported as a change.
+    fn is_bookkeeping(&self) -> bool {
+        false
+    }
 }
 
 #[async_trait]
@@ -108,6 +117,10 @@ impl VecDyn for Vec<Box<dyn Modification>> {
 
 pub fn add_kdl_deserializers_to_context(cx: &mut Context) {
     cx.add_deserializers_for_keywords(FileSpec::kdl_keywords(), FileSpec::add_rules_to_state);
+    cx.add_deserializers_for_keywords(DirectorySpec::kdl_keywords(), DirectorySpec::add_rules_to_state);
+    cx.add_deserializers_for_keywords(LinkSpec::kdl_keywords(), LinkSpec::add_rules_to_state);
+    cx.add_deserializers_for_keywords(EditSpec::kdl_keywords(), EditSpec::add_rules_to_state);
+    cx.add_deserializers_for_keywords(SettingSpec::kdl_keywords(), SettingSpec::add_rules_to_state);
     cx.add_deserializers_for_keywords(Host::kdl_keywords(), Host::add_rules_to_state);
     cx.add_deserializers_for_keywords(ServiceSpec::kdl_keywords(), ServiceSpec::add_rules_to_state);
     cx.add_deserializers_for_keywords(UserSpec::kdl_keywords(), UserSpec::add_rules_to_state);
@@ -14,8 +14,8 @@ impl FromKdl for PackageSpec {
     }
 
     fn add_rules_to_state(state: &mut State, node: &KdlNode, _context: &Context) {
-        let mut args = node.entries().iter();
-        while let Some(p) = args.next() {
+        let args = node.entries().iter();
+        for p in args {
             let name = p.expect_str().to_string();
             let spec = PackageSpec { name };
             state.add_rule(spec);
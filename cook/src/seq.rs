@@ -1,11 +1,13 @@
 use kdl::KdlNode;
+use serde::Serialize;
 
 /// Directive keywords that express ordering and dependencies between units,
-/// inspired by systemd unit directives (`After=`, `Before=`, `Requires=`).
+/// inspired by systemd unit directives (`After=`, `Before=`, `Requires=`), and
+/// `notify`, which ties a unit's changes to a service restart.
 ///
 /// These are handled centrally (see [`extract_sequencing`]) rather than by each
 /// spec, so they work uniformly on every keyword.
-pub const SEQUENCING_KEYWORDS: &[&str] = &["name", "after", "before", "requires"];
+pub const SEQUENCING_KEYWORDS: &[&str] = &["name", "after", "before", "requires", "notify"];
 
 /// Ordering and dependency metadata parsed from a node's sequencing directives.
 ///
@@ -22,6 +24,52 @@ pub struct Sequencing {
     /// Units that must finish successfully first. Implies `after`; if a required
     /// unit fails or is skipped, this unit is skipped too.
     pub requires: Vec<String>,
+    /// Services to restart or reload once this unit has changed something.
+    pub notify: Vec<Notification>,
+}
+
+/// A restart or reload of a service, queued by a unit that applied at least
+/// one modification and delivered at the next `flush` node or at the end of
+/// the host's run. A service that isn't running is left stopped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Notification {
+    pub service: String,
+    pub action: NotifyAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifyAction {
+    Restart,
+    Reload,
+}
+
+/// Notifications waiting to be delivered, one per service, in the order the
+/// services were first notified.
+#[derive(Debug, Default)]
+pub struct NotifyQueue {
+    pending: Vec<Notification>,
+}
+
+impl NotifyQueue {
+    /// Queue `notification`. A service already queued is not queued again;
+    /// if either asked for a restart, a restart is what it gets, since a
+    /// restart also picks up whatever a reload would have.
+    pub fn push(&mut self, notification: Notification) {
+        match self.pending.iter_mut().find(|n| n.service == notification.service) {
+            Some(queued) => {
+                if notification.action == NotifyAction::Restart {
+                    queued.action = NotifyAction::Restart;
+                }
+            }
+            None => self.pending.push(notification),
+        }
+    }
+
+    /// Everything queued so far, leaving the queue empty.
+    pub fn take(&mut self) -> Vec<Notification> {
+        std::mem::take(&mut self.pending)
+    }
 }
 
 /// Strip sequencing directives from `node` and return the parsed [`Sequencing`].
@@ -30,6 +78,9 @@ pub struct Sequencing {
 /// - inline properties: `service caddy caddy.service requires=postgres after=network`
 /// - child directives: `requires postgres caddy` inside the node's `{ ... }` block
 ///
+/// `notify` is taken the same way: `notify=caddy` restarts, and a child
+/// `notify service:caddy action=reload` can ask for a reload instead.
+///
 /// After this returns, `node` contains only the arguments and child directives
 /// its own spec deserializer understands, so specs need no knowledge of
 /// sequencing.
@@ -50,6 +101,9 @@ pub fn extract_sequencing(node: &mut KdlNode) -> Sequencing {
             "after" => seq.after.extend(split_names(value)),
             "before" => seq.before.extend(split_names(value)),
             "requires" => seq.requires.extend(split_names(value)),
+            "notify" => seq
+                .notify
+                .extend(split_names(value).map(|target| notification(&target, None))),
             _ => return true,
         }
         false
@@ -64,6 +118,23 @@ pub fn extract_sequencing(node: &mut KdlNode) -> Sequencing {
                 "after" => seq.after.extend(names()),
                 "before" => seq.before.extend(names()),
                 "requires" => seq.requires.extend(names()),
+                "notify" => {
+                    let mut action = None;
+                    let mut targets = Vec::new();
+                    for entry in child.entries() {
+                        match entry.name().map(|i| i.value()) {
+                            None => targets.push(entry.expect_str()),
+                            Some("action") => action = Some(entry.expect_str()),
+                            Some(z) => panic!("Unexpected option for notify: {z}"),
+                        }
+                    }
+                    assert!(
+                        !targets.is_empty(),
+                        "notify needs a service, e.g. `notify service:caddy`"
+                    );
+                    seq.notify
+                        .extend(targets.into_iter().map(|target| notification(target, action)));
+                }
                 _ => return true,
             }
             false
@@ -79,6 +150,26 @@ fn split_names(value: &str) -> impl Iterator<Item = String> + '_ {
     value.split_whitespace().map(str::to_string)
 }
 
+/// A `notify` target, `service:caddy` or just `caddy`, and its `action=`.
+/// Only services can be notified; the service need not be declared in the
+/// config, since restarting one the host already runs is the common case.
+fn notification(target: &str, action: Option<&str>) -> Notification {
+    let service = match target.split_once(':') {
+        Some(("service", service)) => service,
+        Some((kind, _)) => panic!("notify {target}: only services can be notified, not a {kind}"),
+        None => target,
+    };
+    let action = match action {
+        None | Some("restart") => NotifyAction::Restart,
+        Some("reload") => NotifyAction::Reload,
+        Some(z) => panic!("notify {target}: action must be restart or reload, not {z}"),
+    };
+    Notification {
+        service: service.to_string(),
+        action,
+    }
+}
+
 /// Reject an explicit `name` that would be read as a qualified `kind:name`
 /// reference. Units are already qualified by their rule type, so a colon in the
 /// name itself would make references to it ambiguous to parse.
@@ -89,3 +180,41 @@ fn check_name(name: &str) -> String {
     );
     name.to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn restart(service: &str) -> Notification {
+        Notification {
+            service: service.to_string(),
+            action: NotifyAction::Restart,
+        }
+    }
+
+    fn reload(service: &str) -> Notification {
+        Notification {
+            service: service.to_string(),
+            action: NotifyAction::Reload,
+        }
+    }
+
+    #[test]
+    fn a_service_is_notified_once() {
+        let mut queue = NotifyQueue::default();
+        queue.push(reload("caddy"));
+        queue.push(restart("postgres"));
+        queue.push(reload("caddy"));
+        assert_eq!(queue.take(), vec![reload("caddy"), restart("postgres")]);
+        assert!(queue.take().is_empty());
+    }
+
+    #[test]
+    fn a_restart_outranks_a_reload() {
+        let mut queue = NotifyQueue::default();
+        queue.push(reload("caddy"));
+        queue.push(restart("caddy"));
+        queue.push(reload("caddy"));
+        assert_eq!(queue.take(), vec![restart("caddy")]);
+    }
+}
@@ -0,0 +1,364 @@
+//! Drop-in overrides for a unit cook doesn't own.
+//!
+//! ```kdl
+//! service postgresql {
+//!     dropin "limits.conf" path="postgres/limits.conf"
+//!     environment PGTZ="UTC"
+//! }
+//! ```
+//!
+//! Each drop-in becomes `/etc/systemd/system/postgresql.service.d/<file>`;
+//! `environment` becomes one more, `cook-environment.conf`. Every file cook
+//! writes there starts with [`MARKER`], which is how a later run tells the
+//! drop-ins it wrote from ones the distro or an admin put there: a marked
+//! file the config no longer names is removed, anything else is left alone.
+
+use std::fs;
+use std::path::PathBuf;
+
+use kdl::{KdlNode, KdlValue};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "ssh")]
+use crate::Error;
+#[cfg(feature = "ssh")]
+use crate::sh_single_quote;
+
+/// The first line of every drop-in cook writes.
+pub const MARKER: &str = "# Managed by cook";
+
+/// The drop-in `environment` variables are written to.
+const ENVIRONMENT_FILE: &str = "cook-environment.conf";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dropin {
+    /// File name in the unit's `.d` directory, always ending in `.conf`.
+    pub file: String,
+    /// Content as written, [`MARKER`] included.
+    pub content: String,
+}
+
+impl Dropin {
+    fn new(file: &str, body: &str) -> Dropin {
+        assert!(
+            !file.is_empty() && !file.contains('/'),
+            "dropin {file:?}: give a file name, not a path"
+        );
+        // systemd only reads `*.conf` from a drop-in directory.
+        let file = if file.ends_with(".conf") {
+            file.to_string()
+        } else {
+            format!("{file}.conf")
+        };
+        let mut content = format!("{MARKER}\n{body}");
+        if !content.ends_with('\n') {
+            content.push('\n');
+        }
+        Dropin { file, content }
+    }
+}
+
+/// The drop-ins and environment of a `service` node's children, as they are
+/// read: `dropin` and `environment` are collected, anything else is left for
+/// the caller. Call [`Dropins::finish`] once all children are seen.
+#[derive(Debug, Default)]
+pub(crate) struct Dropins {
+    dropins: Vec<Dropin>,
+    environment: Vec<(String, String)>,
+}
+
+impl Dropins {
+    /// Read `child` if it is a `dropin` or `environment`, and say whether it
+    /// was.
+    pub(crate) fn parse_child(&mut self, child: &KdlNode, service: &str, context: &crate::Context) -> bool {
+        match child.name().value() {
+            "dropin" => {
+                let mut file = None;
+                let mut body = None;
+                for entry in child.entries() {
+                    match entry.name().map(|i| i.value()) {
+                        None => file = Some(entry.expect_str()),
+                        Some("path") => {
+                            let path = context.local_path(entry.expect_str());
+                            let content = fs::read_to_string(&path)
+                                .unwrap_or_else(|e| panic!("service {service}: reading {}: {e}", path.display()));
+                            body = Some(content);
+                        }
+                        Some("content") => body = Some(entry.expect_str().to_string()),
+                        Some(z) => panic!("Unexpected option for dropin: {z}"),
+                    }
+                }
+                let file = file.unwrap_or_else(|| panic!("service {service}: dropin needs a file name"));
+                let body = body.unwrap_or_else(|| panic!("service {service}: dropin {file} needs path= or content="));
+                let dropin = Dropin::new(file, &body);
+                assert!(
+                    dropin.file != ENVIRONMENT_FILE,
+                    "service {service}: {ENVIRONMENT_FILE} is where `environment` goes; name the dropin something else"
+                );
+                assert!(
+                    self.dropins.iter().all(|d| d.file != dropin.file),
+                    "service {service}: dropin {} is given more than once",
+                    dropin.file
+                );
+                self.dropins.push(dropin);
+                true
+            }
+            "environment" => {
+                for entry in child.entries() {
+                    let Some(key) = entry.name() else {
+                        panic!("service {service}: environment takes KEY=\"value\" pairs");
+                    };
+                    let key = key.value().to_string();
+                    // Bare, as the process will read them: KDL's own
+                    // `#true` means nothing to it.
+                    let value = match entry.value() {
+                        KdlValue::String(s) => s.clone(),
+                        KdlValue::Integer(i) => i.to_string(),
+                        KdlValue::Float(f) => f.to_string(),
+                        KdlValue::Bool(b) => b.to_string(),
+                        KdlValue::Null => panic!("service {service}: environment {key} is #null; give it a value"),
+                    };
+                    match self.environment.iter_mut().find(|(k, _)| *k == key) {
+                        Some(existing) => existing.1 = value,
+                        None => self.environment.push((key, value)),
+                    }
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Every drop-in, `environment` as its own.
+    pub(crate) fn finish(mut self) -> Vec<Dropin> {
+        if !self.environment.is_empty() {
+            let mut body = String::from("[Service]\n");
+            for (key, value) in &self.environment {
+                body.push_str(&format!("Environment=\"{}\"\n", escape(&format!("{key}={value}"))));
+            }
+            self.dropins.push(Dropin::new(ENVIRONMENT_FILE, &body));
+        }
+        self.dropins
+    }
+}
+
+/// Quote-safe for a double-quoted `Environment=` assignment, with `%` kept
+/// from being read as a specifier.
+fn escape(assignment: &str) -> String {
+    assignment.replace('\\', "\\\\").replace('"', "\\\"").replace('%', "%%")
+}
+
+/// A `*.conf` file found in the drop-in directory.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct RemoteDropin {
+    pub(crate) file: String,
+    pub(crate) sha256: String,
+    /// Starts with [`MARKER`].
+    pub(crate) managed: bool,
+}
+
+/// Lists the drop-in directory `dir`: `<sha256> <cook|other> <file>` per
+/// `*.conf` file. A missing directory lists nothing.
+#[cfg(feature = "ssh")]
+pub(crate) fn list_script(dir: &str) -> String {
+    format!(
+        "for f in {}/*.conf; do [ -f \"$f\" ] || continue; \
+         s=$(sha256sum < \"$f\") || continue; \
+         if [ \"$(head -n 1 \"$f\")\" = {} ]; then m=cook; else m=other; fi; \
+         printf '%s %s %s\\n' \"${{s%% *}}\" \"$m\" \"${{f##*/}}\"; done",
+        sh_single_quote(dir),
+        sh_single_quote(MARKER)
+    )
+}
+
+pub(crate) fn parse_listing(output: &str) -> Vec<RemoteDropin> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let sha256 = fields.next()?.to_string();
+            let managed = fields.next()? == "cook";
+            let file = fields.next()?.to_string();
+            Some(RemoteDropin { file, sha256, managed })
+        })
+        .collect()
+}
+
+/// What it takes to get a drop-in directory to what the rule asks for.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Diff {
+    /// Drop-ins to write, each with whether it replaces a file already
+    /// there (and so may need backing up).
+    pub(crate) write: Vec<(Dropin, bool)>,
+    /// Cook-managed files the rule no longer names.
+    pub(crate) remove: Vec<String>,
+}
+
+/// What it takes to get the drop-in directory from `remote` to `wanted`, or
+/// `None` if nothing.
+pub(crate) fn diff(wanted: &[Dropin], remote: &[RemoteDropin]) -> Option<Diff> {
+    let write: Vec<(Dropin, bool)> = wanted
+        .iter()
+        .filter_map(|dropin| {
+            let existing = remote.iter().find(|r| r.file == dropin.file);
+            let sha256 = crate::service::spec::sha256_hex(&dropin.content);
+            match existing {
+                Some(r) if r.sha256 == sha256 => None,
+                _ => Some((dropin.clone(), existing.is_some())),
+            }
+        })
+        .collect();
+    let remove: Vec<String> = remote
+        .iter()
+        .filter(|r| r.managed && wanted.iter().all(|d| d.file != r.file))
+        .map(|r| r.file.clone())
+        .collect();
+    (!write.is_empty() || !remove.is_empty()).then_some(Diff { write, remove })
+}
+
+/// The unit's drop-in directory is not what its rule asks for.
+#[derive(Debug, Serialize)]
+pub struct WrongDropins {
+    pub service: String,
+    pub dir: String,
+    pub write: Vec<DropinWrite>,
+    /// Drop-ins cook wrote on an earlier run that the config no longer has.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub remove: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DropinWrite {
+    pub file: String,
+    #[serde(skip)]
+    pub content: String,
+    /// Where the drop-in being replaced is copied to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup: Option<PathBuf>,
+}
+
+#[cfg(feature = "ssh")]
+impl WrongDropins {
+    /// Write and remove drop-ins, then have systemd re-read the unit.
+    pub(crate) async fn apply_ssh(
+        &self,
+        session: &std::sync::Arc<openssh::Session>,
+        manager: &dyn crate::service::manager::ServiceManager,
+    ) -> Result<(), Error> {
+        use crate::file::atomic;
+        use std::path::Path;
+
+        let status = session.command("mkdir").arg("-p").arg(&self.dir).status().await?;
+        if !status.success() {
+            return Err(anyhow::anyhow!("failed to create {}", self.dir).into());
+        }
+        for write in &self.write {
+            let path = format!("{}/{}", self.dir, write.file);
+            atomic::upload(
+                session,
+                Path::new(&path),
+                write.content.as_bytes(),
+                &atomic::Attributes {
+                    mode: Some(0o644),
+                    ..atomic::Attributes::default()
+                },
+                &atomic::Safeguards {
+                    backup: write.backup.as_deref(),
+                    ..atomic::Safeguards::default()
+                },
+            )
+            .await?;
+        }
+        for file in &self.remove {
+            let path = format!("{}/{}", self.dir, file);
+            let output = session.command("rm").arg("-f").arg(&path).output().await?;
+            if !output.status.success() {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                return Err(anyhow::anyhow!("removing {path} failed: {}", stderr.trim()).into());
+            }
+        }
+        manager.daemon_reload(session).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(file: &str, content: &str, managed: bool) -> RemoteDropin {
+        RemoteDropin {
+            file: file.to_string(),
+            sha256: crate::service::spec::sha256_hex(content),
+            managed,
+        }
+    }
+
+    #[test]
+    fn a_dropin_is_marked_and_named_conf() {
+        let dropin = Dropin::new("limits", "[Service]\nLimitNOFILE=65536");
+        assert_eq!(dropin.file, "limits.conf");
+        assert_eq!(dropin.content, "# Managed by cook\n[Service]\nLimitNOFILE=65536\n");
+    }
+
+    #[test]
+    fn environment_values_are_quoted_for_systemd() {
+        let dropins = Dropins {
+            dropins: Vec::new(),
+            environment: vec![
+                ("PGTZ".to_string(), "UTC".to_string()),
+                ("GREETING".to_string(), "say \"hi\" 100%".to_string()),
+            ],
+        };
+        assert_eq!(
+            dropins.finish(),
+            vec![Dropin {
+                file: "cook-environment.conf".to_string(),
+                content: "# Managed by cook\n[Service]\nEnvironment=\"PGTZ=UTC\"\n\
+                          Environment=\"GREETING=say \\\"hi\\\" 100%%\"\n"
+                    .to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn the_listing_reads_back_names_with_spaces() {
+        let listing = format!(
+            "{} cook my limits.conf\n{} other 50-distro.conf\n",
+            "a".repeat(64),
+            "b".repeat(64)
+        );
+        assert_eq!(
+            parse_listing(&listing),
+            vec![
+                RemoteDropin {
+                    file: "my limits.conf".to_string(),
+                    sha256: "a".repeat(64),
+                    managed: true
+                },
+                RemoteDropin {
+                    file: "50-distro.conf".to_string(),
+                    sha256: "b".repeat(64),
+                    managed: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn only_changed_dropins_are_written_and_only_cooks_removed() {
+        let limits = Dropin::new("limits.conf", "[Service]\nLimitNOFILE=65536\n");
+        let nice = Dropin::new("nice.conf", "[Service]\nNice=5\n");
+        let on_host = [
+            remote("limits.conf", &limits.content, true),
+            remote("nice.conf", "# Managed by cook\n[Service]\nNice=0\n", true),
+            remote("old.conf", "# Managed by cook\n", true),
+            remote("50-distro.conf", "[Service]\n", false),
+        ];
+        let Diff { write, remove } = diff(&[limits.clone(), nice.clone()], &on_host).expect("changes");
+        assert_eq!(write, vec![(nice, true)]);
+        assert_eq!(remove, vec!["old.conf".to_string()]);
+
+        let unchanged = [remote("limits.conf", &limits.content, true)];
+        assert_eq!(diff(&[limits], &unchanged), None);
+    }
+}
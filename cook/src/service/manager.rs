@@ -1,34 +1,145 @@
 //! Platform-aware service management.
 //!
 //! `ser` (the homegrown CLI) abstracts over `systemctl` (Linux/systemd) and
-//! `launchctl` (macOS/launchd). This module reimplements that abstraction for
+//! `launchctl` (macOS/launchd); cook adds OpenRC and runit for the Linux hosts
+//! that run those instead of systemd. This module reimplements that abstraction for
 //! cook's use over SSH, with one important subtlety: the platform we care about
 //! is the OS of the host *executing* the commands (the remote end of the SSH
 //! session), not the OS running cook. So platform detection happens over the
-//! wire via `uname`, never via `cfg!(target_os = ...)`.
+//! wire via `uname` and a look at the running init system, never via
+//! `cfg!(target_os = ...)`.
 //!
 //! The [`ServiceManager`] trait is the composable seam: each platform owns its
 //! own unit-file locations and control commands, so adding/altering a platform
-//! is a single self-contained `impl`.
+//! is a single self-contained `impl`. Services are modelled as systemd units;
+//! launchd gets them translated by [`crate::service::plist`], OpenRC and runit
+//! by [`crate::service::script`].
+
+use serde::Serialize;
 
 #[cfg(feature = "ssh")]
 use crate::Error;
+#[cfg(feature = "ssh")]
+use crate::sh_single_quote;
 
 /// The kind of unit file being managed. Names map to the platform's conventions
 /// in [`ServiceManager::unit_path`].
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum UnitKind {
     Service,
     Timer,
 }
 
+/// What the init system reports about a unit at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnitStatus {
+    /// Running, or on its way up or reloading: anything but stopped or failed.
+    pub active: bool,
+    /// Whether the unit comes up on boot. `None` for a unit that can be
+    /// neither enabled nor disabled — a `static` one with no `[Install]`
+    /// section, or one generated at boot.
+    pub enabled: Option<bool>,
+}
+
+impl UnitStatus {
+    /// Read `systemctl is-active` followed by `systemctl is-enabled`, one
+    /// word per line. A unit systemd has never heard of prints `inactive`
+    /// and `not-found`, and reads as stopped and disabled.
+    pub fn parse_systemctl(output: &str) -> UnitStatus {
+        let mut lines = output.lines().map(str::trim);
+        let active = matches!(lines.next(), Some("active" | "activating" | "reloading" | "refreshing"));
+        let enabled = match lines.next() {
+            Some("enabled" | "enabled-runtime" | "linked" | "linked-runtime" | "alias") => Some(true),
+            Some("static" | "indirect" | "generated" | "transient") => None,
+            _ => Some(false),
+        };
+        UnitStatus { active, enabled }
+    }
+
+    /// Read the two words the launchd, OpenRC and runit status scripts print:
+    /// `running`, `loaded` or anything else for stopped, then `enabled` or
+    /// anything else for disabled. A loaded launchd job that isn't running
+    /// counts as active for a timer, whose job only runs on its calendar.
+    pub fn parse_status_words(output: &str, kind: UnitKind) -> UnitStatus {
+        let mut lines = output.lines().map(str::trim);
+        let active = matches!(
+            (lines.next(), kind),
+            (Some("running"), _) | (Some("loaded"), UnitKind::Timer)
+        );
+        let enabled = Some(lines.next() == Some("enabled"));
+        UnitStatus { active, enabled }
+    }
+}
+
+/// The lines of `systemd-analyze verify` output that are about `units` (file
+/// names such as `caddy.service`) and that should stop an install.
+///
+/// The tool also complains about other units on the host, which are not
+/// cook's to fix, and about an `ExecStart=` binary that isn't there yet —
+/// which is expected when the deploy that ships it runs after the unit is
+/// installed.
+pub fn verify_failures<'a>(output: &'a str, units: &[&str]) -> Vec<&'a str> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| units.iter().any(|unit| line.contains(unit)))
+        .filter(|line| !line.contains("is not executable"))
+        .collect()
+}
+
 /// Operating system of a (possibly remote) host.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Platform {
-    Linux,
+    /// Linux, with the init system that runs its services.
+    Linux(InitSystem),
     Macos,
 }
 
+/// The init systems cook manages services under on Linux.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitSystem {
+    Systemd,
+    OpenRc,
+    Runit,
+}
+
+/// Prints `uname -s`, then the init system running on a Linux host.
+///
+/// A running systemd is told by `/run/systemd/system` (what `sd_booted` checks)
+/// and a running OpenRC by `/run/openrc`; the installed tools decide for a host
+/// where neither has run, such as a container being provisioned.
+pub const DETECT_SCRIPT: &str = "uname -s; \
+    if [ -d /run/systemd/system ]; then echo systemd; \
+    elif [ -d /run/openrc ] || command -v openrc-run >/dev/null 2>&1; then echo openrc; \
+    elif [ -d /etc/runit ] || command -v runsvdir >/dev/null 2>&1; then echo runit; \
+    elif command -v systemctl >/dev/null 2>&1; then echo systemd; \
+    else echo unknown; fi";
+
+impl Platform {
+    /// Read what [`DETECT_SCRIPT`] printed.
+    pub fn parse_detect(output: &str) -> Result<Platform, String> {
+        let mut lines = output.lines().map(str::trim);
+        match (lines.next(), lines.next()) {
+            (Some("Darwin"), _) => Ok(Platform::Macos),
+            (Some("Linux"), Some("systemd")) => Ok(Platform::Linux(InitSystem::Systemd)),
+            (Some("Linux"), Some("openrc")) => Ok(Platform::Linux(InitSystem::OpenRc)),
+            (Some("Linux"), Some("runit")) => Ok(Platform::Linux(InitSystem::Runit)),
+            (Some("Linux"), _) => Err("no supported init system (systemd, OpenRC or runit) on the remote".to_string()),
+            (other, _) => Err(format!("unsupported remote platform: {}", other.unwrap_or_default())),
+        }
+    }
+}
+
+/// Where a service's unit files come from: its systemd units, and the
+/// init-specific script `script=` gives for OpenRC or runit hosts.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnitSources<'a> {
+    pub service: Option<&'a str>,
+    pub timer: Option<&'a str>,
+    pub script: Option<&'a str>,
+}
+
 #[cfg(feature = "ssh")]
 impl Platform {
     /// Detect the platform of the host on the far end of an SSH session.
@@ -37,22 +148,19 @@ impl Platform {
     /// distinct from the OS of the machine running cook — hence we ask the
     /// remote via `uname -s` rather than consulting a compile-time `cfg`.
     pub async fn detect(session: &openssh::Session) -> Result<Platform, Error> {
-        let output = session.command("uname").arg("-s").output().await?;
+        let output = session.command("sh").arg("-c").arg(DETECT_SCRIPT).output().await?;
         if !output.status.success() {
             return Err(anyhow::anyhow!("failed to detect remote platform via `uname -s`").into());
         }
-        let os = String::from_utf8(output.stdout)?.trim().to_string();
-        match os.as_str() {
-            "Linux" => Ok(Platform::Linux),
-            "Darwin" => Ok(Platform::Macos),
-            other => Err(anyhow::anyhow!("unsupported remote platform: {other}").into()),
-        }
+        Ok(Platform::parse_detect(&String::from_utf8(output.stdout)?)?)
     }
 
     /// The [`ServiceManager`] appropriate for this platform.
     pub fn service_manager(self) -> Box<dyn ServiceManager> {
         match self {
-            Platform::Linux => Box::new(Systemd),
+            Platform::Linux(InitSystem::Systemd) => Box::new(Systemd),
+            Platform::Linux(InitSystem::OpenRc) => Box::new(OpenRc),
+            Platform::Linux(InitSystem::Runit) => Box::new(Runit),
             Platform::Macos => Box::new(Launchd),
         }
     }
@@ -70,6 +178,20 @@ pub trait ServiceManager: Send + Sync {
     /// should be written.
     fn unit_path(&self, name: &str, kind: UnitKind) -> String;
 
+    /// The files to write for a service's `.service` and `.timer` units, in
+    /// that order: the units themselves where the init system reads them,
+    /// a translation or the given script where it doesn't.
+    fn unit_files(&self, name: &str, sources: UnitSources<'_>) -> Result<(Option<String>, Option<String>), Error>;
+
+    /// Where a service's drop-ins go, or `None` on a platform without them.
+    fn dropin_dir(&self, name: &str) -> Option<String>;
+
+    /// The mode unit files are written with: `None` keeps the file's own,
+    /// while an init system that runs them as scripts needs them executable.
+    fn unit_mode(&self) -> Option<u32> {
+        None
+    }
+
     /// The sha256 of a remote file, or `None` if it does not exist. Used to
     /// decide whether a unit file needs (re)writing. The hashing command itself
     /// differs by platform (`sha256sum` vs `shasum`).
@@ -78,13 +200,51 @@ pub trait ServiceManager: Send + Sync {
     /// Re-read unit definitions after unit files change. This is the
     /// "reload-daemon dance" that `ser` performs automatically (systemd
     /// `daemon-reload`); on platforms that don't need it this is a no-op.
-    async fn reload(&self, session: &openssh::Session) -> Result<(), Error>;
+    async fn daemon_reload(&self, session: &openssh::Session) -> Result<(), Error>;
 
     /// Enable a unit so that it comes up on boot, and start it right away when
     /// `start` is set. Registering a unit and starting it are separate concerns:
     /// a unit whose binary is deployed later can be enabled now and started by
     /// the deploy, which is what `start=false` in a Cookfile means.
     async fn enable(&self, session: &openssh::Session, name: &str, kind: UnitKind, start: bool) -> Result<(), Error>;
+
+    /// Stop a unit from coming up on boot. A running unit keeps running.
+    async fn disable(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error>;
+
+    async fn start(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error>;
+
+    async fn stop(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error>;
+
+    /// Stop and start a unit, or just start it if it wasn't running.
+    async fn restart(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error>;
+
+    /// Restart a unit that is running, and leave one that isn't stopped: a
+    /// notification must not start a service the config keeps stopped.
+    async fn try_restart(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error>;
+
+    /// Have a running unit re-read its configuration without stopping. A
+    /// unit that can't do that in place is restarted instead, and one that
+    /// isn't running is left stopped, as with [`ServiceManager::try_restart`].
+    async fn reload(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error>;
+
+    /// Whether a unit is running and whether it is enabled, in one round-trip.
+    async fn status(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<UnitStatus, Error>;
+
+    /// Check the unit files at `paths` the way the init system will load
+    /// them, before it is told to. A host without a checker passes.
+    async fn verify(&self, session: &openssh::Session, paths: &[String]) -> Result<(), Error>;
+}
+
+#[cfg(feature = "ssh")]
+impl crate::Notification {
+    /// Restart or reload the notified service on the host.
+    pub async fn deliver(&self, session: &openssh::Session) -> Result<(), Error> {
+        let manager = Platform::detect(session).await?.service_manager();
+        match self.action {
+            crate::NotifyAction::Restart => manager.try_restart(session, &self.service, UnitKind::Service).await,
+            crate::NotifyAction::Reload => manager.reload(session, &self.service, UnitKind::Service).await,
+        }
+    }
 }
 
 /// systemd-backed service management (Linux).
@@ -102,21 +262,22 @@ impl ServiceManager for Systemd {
         format!("/etc/systemd/system/{name}.{ext}")
     }
 
-    async fn remote_checksum(&self, session: &openssh::Session, path: &str) -> Result<Option<String>, Error> {
-        let output = session.command("sha256sum").arg(path).output().await?;
-        if !output.status.success() {
-            // A failed `sha256sum` (e.g. missing file) means "not present".
-            return Ok(None);
+    fn unit_files(&self, name: &str, sources: UnitSources<'_>) -> Result<(Option<String>, Option<String>), Error> {
+        if sources.service.is_none() && sources.script.is_some() {
+            return Err(format!("service {name}: script= serves OpenRC and runit; systemd needs a unit file").into());
         }
-        let sha = String::from_utf8(output.stdout)?
-            .split_whitespace()
-            .next()
-            .unwrap_or("")
-            .to_string();
-        Ok(Some(sha))
+        Ok((sources.service.map(str::to_string), sources.timer.map(str::to_string)))
     }
 
-    async fn reload(&self, session: &openssh::Session) -> Result<(), Error> {
+    fn dropin_dir(&self, name: &str) -> Option<String> {
+        Some(format!("{}.d", self.unit_path(name, UnitKind::Service)))
+    }
+
+    async fn remote_checksum(&self, session: &openssh::Session, path: &str) -> Result<Option<String>, Error> {
+        sha256sum(session, path).await
+    }
+
+    async fn daemon_reload(&self, session: &openssh::Session) -> Result<(), Error> {
         let success = session
             .command("systemctl")
             .arg("daemon-reload")
@@ -144,10 +305,79 @@ impl ServiceManager for Systemd {
         }
         Ok(())
     }
+
+    async fn disable(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error> {
+        self.systemctl(session, "disable", name, kind).await
+    }
+
+    async fn start(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error> {
+        self.systemctl(session, "start", name, kind).await
+    }
+
+    async fn stop(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error> {
+        self.systemctl(session, "stop", name, kind).await
+    }
+
+    async fn restart(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error> {
+        self.systemctl(session, "restart", name, kind).await
+    }
+
+    async fn try_restart(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error> {
+        self.systemctl(session, "try-restart", name, kind).await
+    }
+
+    async fn reload(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error> {
+        self.systemctl(session, "try-reload-or-restart", name, kind).await
+    }
+
+    async fn status(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<UnitStatus, Error> {
+        // Both commands exit non-zero for the answer "no", so only the
+        // words they print count.
+        let unit = sh_single_quote(&self.unit_name(name, kind));
+        let script = format!("systemctl is-active {unit}; systemctl is-enabled {unit}");
+        let output = session.command("sh").arg("-c").arg(&script).output().await?;
+        Ok(UnitStatus::parse_systemctl(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    async fn verify(&self, session: &openssh::Session, paths: &[String]) -> Result<(), Error> {
+        let files: Vec<String> = paths.iter().map(|path| sh_single_quote(path)).collect();
+        // Minimal images ship systemd without `systemd-analyze`; they get
+        // the unit unchecked rather than not at all.
+        let script = format!(
+            "command -v systemd-analyze >/dev/null || exit 0; systemd-analyze verify {} 2>&1",
+            files.join(" ")
+        );
+        let output = session.command("sh").arg("-c").arg(&script).output().await?;
+        if output.status.success() {
+            return Ok(());
+        }
+        let output = String::from_utf8_lossy(&output.stdout);
+        let units: Vec<&str> = paths
+            .iter()
+            .map(|path| path.rsplit('/').next().unwrap_or(path))
+            .collect();
+        let failures = verify_failures(&output, &units);
+        if failures.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow::anyhow!("`systemd-analyze verify` failed: {}", failures.join("; ")).into())
+    }
 }
 
 #[cfg(feature = "ssh")]
 impl Systemd {
+    /// Run `systemctl <verb> <unit>`, with systemctl's own complaint as the
+    /// error when it fails.
+    async fn systemctl(&self, session: &openssh::Session, verb: &str, name: &str, kind: UnitKind) -> Result<(), Error> {
+        let unit = self.unit_name(name, kind);
+        let output = session.command("systemctl").arg(verb).arg(&unit).output().await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow::anyhow!("`systemctl {verb} {unit}` failed: {}", stderr.trim()).into());
+        }
+        Ok(())
+    }
+
     /// The unit name as systemctl refers to it, e.g. `caddy.timer`.
     fn unit_name(&self, name: &str, kind: UnitKind) -> String {
         match kind {
@@ -157,14 +387,302 @@ impl Systemd {
     }
 }
 
+/// The sha256 of a file on a Linux host, or `None` if it does not exist.
+#[cfg(feature = "ssh")]
+async fn sha256sum(session: &openssh::Session, path: &str) -> Result<Option<String>, Error> {
+    let output = session.command("sha256sum").arg(path).output().await?;
+    if !output.status.success() {
+        // A failed `sha256sum` (e.g. missing file) means "not present".
+        return Ok(None);
+    }
+    let sha = String::from_utf8(output.stdout)?
+        .split_whitespace()
+        .next()
+        .unwrap_or("")
+        .to_string();
+    Ok(Some(sha))
+}
+
+/// Run `script` with `sh`, with its complaint as the error when it fails.
+#[cfg(feature = "ssh")]
+async fn run_script(session: &openssh::Session, what: &str, script: &str) -> Result<(), Error> {
+    let output = session.command("sh").arg("-c").arg(script).output().await?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!("{what} failed: {}", stderr.trim()).into());
+    }
+    Ok(())
+}
+
+/// Parse service scripts without running them, which is all the checking
+/// OpenRC and runit scripts get before they are used.
+#[cfg(feature = "ssh")]
+async fn sh_syntax_check(session: &openssh::Session, paths: &[String]) -> Result<(), Error> {
+    for path in paths {
+        let output = session.command("sh").arg("-n").arg(path).output().await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow::anyhow!("`sh -n {path}` failed: {}", stderr.trim()).into());
+        }
+    }
+    Ok(())
+}
+
+/// OpenRC-backed service management (Alpine, Gentoo).
+///
+/// A service is an `/etc/init.d` script: the one `script=` gives, or one
+/// generated from the unit by [`crate::service::script::openrc_script`].
+/// Enabling adds it to the `default` runlevel. OpenRC has no timers.
+#[cfg(feature = "ssh")]
+pub struct OpenRc;
+
+#[cfg(feature = "ssh")]
+#[async_trait::async_trait]
+impl ServiceManager for OpenRc {
+    fn unit_path(&self, name: &str, _kind: UnitKind) -> String {
+        format!("/etc/init.d/{name}")
+    }
+
+    fn unit_files(&self, name: &str, sources: UnitSources<'_>) -> Result<(Option<String>, Option<String>), Error> {
+        if sources.timer.is_some() {
+            return Err(format!("service {name}: OpenRC has no timers; schedule the job with cron instead").into());
+        }
+        match (sources.script, sources.service) {
+            (Some(script), _) => Ok((Some(script.to_string()), None)),
+            (None, Some(service)) => Ok((Some(crate::service::script::openrc_script(name, service)?), None)),
+            (None, None) => Ok((None, None)),
+        }
+    }
+
+    fn dropin_dir(&self, _name: &str) -> Option<String> {
+        None
+    }
+
+    fn unit_mode(&self) -> Option<u32> {
+        Some(0o755)
+    }
+
+    async fn remote_checksum(&self, session: &openssh::Session, path: &str) -> Result<Option<String>, Error> {
+        sha256sum(session, path).await
+    }
+
+    /// OpenRC reads the init script each time it runs it.
+    async fn daemon_reload(&self, _session: &openssh::Session) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn enable(&self, session: &openssh::Session, name: &str, kind: UnitKind, start: bool) -> Result<(), Error> {
+        let script = format!("rc-update add {} default", sh_single_quote(name));
+        run_script(session, "`rc-update add`", &script).await?;
+        if start {
+            self.start(session, name, kind).await?;
+        }
+        Ok(())
+    }
+
+    async fn disable(&self, session: &openssh::Session, name: &str, _kind: UnitKind) -> Result<(), Error> {
+        let script = format!("rc-update del {} default", sh_single_quote(name));
+        run_script(session, "`rc-update del`", &script).await
+    }
+
+    async fn start(&self, session: &openssh::Session, name: &str, _kind: UnitKind) -> Result<(), Error> {
+        self.rc_service(session, name, "start").await
+    }
+
+    async fn stop(&self, session: &openssh::Session, name: &str, _kind: UnitKind) -> Result<(), Error> {
+        self.rc_service(session, name, "stop").await
+    }
+
+    async fn restart(&self, session: &openssh::Session, name: &str, _kind: UnitKind) -> Result<(), Error> {
+        self.rc_service(session, name, "restart").await
+    }
+
+    async fn try_restart(&self, session: &openssh::Session, name: &str, _kind: UnitKind) -> Result<(), Error> {
+        let name = sh_single_quote(name);
+        let script = format!("{}; rc-service --ifstarted {name} restart", Self::if_started(&name));
+        run_script(session, "`rc-service restart`", &script).await
+    }
+
+    /// Only scripts that define `reload` have one; the rest are restarted.
+    async fn reload(&self, session: &openssh::Session, name: &str, _kind: UnitKind) -> Result<(), Error> {
+        let name = sh_single_quote(name);
+        let script = format!(
+            "{}; rc-service --ifstarted {name} reload 2>/dev/null || rc-service --ifstarted {name} restart",
+            Self::if_started(&name)
+        );
+        run_script(session, "`rc-service reload`", &script).await
+    }
+
+    async fn status(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<UnitStatus, Error> {
+        let name = sh_single_quote(name);
+        let script = format!(
+            "if rc-service {name} status >/dev/null 2>&1; then echo running; else echo stopped; fi; \
+             if rc-update show default 2>/dev/null | awk '{{print $1}}' | grep -qxF {name}; \
+             then echo enabled; else echo disabled; fi"
+        );
+        let output = session.command("sh").arg("-c").arg(&script).output().await?;
+        Ok(UnitStatus::parse_status_words(
+            &String::from_utf8_lossy(&output.stdout),
+            kind,
+        ))
+    }
+
+    async fn verify(&self, session: &openssh::Session, paths: &[String]) -> Result<(), Error> {
+        sh_syntax_check(session, paths).await
+    }
+}
+
+#[cfg(feature = "ssh")]
+impl OpenRc {
+    /// Ends the script early, and successfully, for a service that isn't
+    /// started; `--ifstarted` alone would fail there. `name` is quoted.
+    fn if_started(name: &str) -> String {
+        format!("rc-service {name} status >/dev/null 2>&1 || exit 0")
+    }
+
+    async fn rc_service(&self, session: &openssh::Session, name: &str, verb: &str) -> Result<(), Error> {
+        let script = format!("rc-service {} {verb}", sh_single_quote(name));
+        run_script(session, &format!("`rc-service {name} {verb}`"), &script).await
+    }
+}
+
+/// runit-backed service management (Void).
+///
+/// A service is a directory under `/etc/sv` whose `run` script is the one
+/// `script=` gives, or one generated from the unit by
+/// [`crate::service::script::runit_run`]. Enabling links it into the
+/// supervised directory (`/var/service`, or `/etc/service` elsewhere), and
+/// disabling removes the link, which also stops it. runit has no timers.
+#[cfg(feature = "ssh")]
+pub struct Runit;
+
+/// Sets `$d` to the directory runsvdir supervises.
+#[cfg(feature = "ssh")]
+const RUNIT_SERVICE_DIR: &str = "d=/var/service; [ -d \"$d\" ] || d=/etc/service";
+
+#[cfg(feature = "ssh")]
+#[async_trait::async_trait]
+impl ServiceManager for Runit {
+    fn unit_path(&self, name: &str, _kind: UnitKind) -> String {
+        format!("{}/run", self.service_dir(name))
+    }
+
+    fn unit_files(&self, name: &str, sources: UnitSources<'_>) -> Result<(Option<String>, Option<String>), Error> {
+        if sources.timer.is_some() {
+            return Err(format!("service {name}: runit has no timers; schedule the job with cron instead").into());
+        }
+        match (sources.script, sources.service) {
+            (Some(script), _) => Ok((Some(script.to_string()), None)),
+            (None, Some(service)) => Ok((Some(crate::service::script::runit_run(name, service)?), None)),
+            (None, None) => Ok((None, None)),
+        }
+    }
+
+    fn dropin_dir(&self, _name: &str) -> Option<String> {
+        None
+    }
+
+    fn unit_mode(&self) -> Option<u32> {
+        Some(0o755)
+    }
+
+    async fn remote_checksum(&self, session: &openssh::Session, path: &str) -> Result<Option<String>, Error> {
+        sha256sum(session, path).await
+    }
+
+    /// runsv runs the `run` script afresh each time the service starts.
+    async fn daemon_reload(&self, _session: &openssh::Session) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// runsv brings a newly linked service straight up, so a `down` file
+    /// holds it until runsv has taken it on, unless it is to start now.
+    async fn enable(&self, session: &openssh::Session, name: &str, _kind: UnitKind, start: bool) -> Result<(), Error> {
+        let dir = sh_single_quote(&self.service_dir(name));
+        let link = sh_single_quote(name);
+        let hold = if start { ":" } else { "touch \"$s/down\"" };
+        let script = format!(
+            "{RUNIT_SERVICE_DIR}; s={dir}; {hold}; ln -sfn \"$s\" \"$d\"/{link} || exit 1; \
+             i=0; while [ ! -p \"$s/supervise/ok\" ] && [ $i -lt 10 ]; do sleep 1; i=$((i+1)); done; \
+             rm -f \"$s/down\""
+        );
+        run_script(session, "linking the runit service", &script).await
+    }
+
+    async fn disable(&self, session: &openssh::Session, name: &str, _kind: UnitKind) -> Result<(), Error> {
+        let script = format!("{RUNIT_SERVICE_DIR}; rm -f \"$d\"/{}", sh_single_quote(name));
+        run_script(session, "unlinking the runit service", &script).await
+    }
+
+    async fn start(&self, session: &openssh::Session, name: &str, _kind: UnitKind) -> Result<(), Error> {
+        self.sv(session, name, "up").await
+    }
+
+    async fn stop(&self, session: &openssh::Session, name: &str, _kind: UnitKind) -> Result<(), Error> {
+        self.sv(session, name, "down").await
+    }
+
+    async fn restart(&self, session: &openssh::Session, name: &str, _kind: UnitKind) -> Result<(), Error> {
+        self.sv(session, name, "restart").await
+    }
+
+    async fn try_restart(&self, session: &openssh::Session, name: &str, _kind: UnitKind) -> Result<(), Error> {
+        self.sv_if_up(session, name, "restart").await
+    }
+
+    /// A SIGHUP, which is how runit services are conventionally reloaded.
+    async fn reload(&self, session: &openssh::Session, name: &str, _kind: UnitKind) -> Result<(), Error> {
+        self.sv_if_up(session, name, "hup").await
+    }
+
+    async fn status(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<UnitStatus, Error> {
+        let script = format!(
+            "if sv status {dir} 2>/dev/null | grep -q '^run:'; then echo running; else echo stopped; fi; \
+             {RUNIT_SERVICE_DIR}; if [ -L \"$d\"/{link} ]; then echo enabled; else echo disabled; fi",
+            dir = sh_single_quote(&self.service_dir(name)),
+            link = sh_single_quote(name),
+        );
+        let output = session.command("sh").arg("-c").arg(&script).output().await?;
+        Ok(UnitStatus::parse_status_words(
+            &String::from_utf8_lossy(&output.stdout),
+            kind,
+        ))
+    }
+
+    async fn verify(&self, session: &openssh::Session, paths: &[String]) -> Result<(), Error> {
+        sh_syntax_check(session, paths).await
+    }
+}
+
+#[cfg(feature = "ssh")]
+impl Runit {
+    /// The service's directory, which holds its `run` script.
+    fn service_dir(&self, name: &str) -> String {
+        format!("/etc/sv/{name}")
+    }
+
+    /// Run `sv <verb>` on the service by its directory, which works whatever
+    /// `$SVDIR` the host's `sv` defaults to.
+    async fn sv(&self, session: &openssh::Session, name: &str, verb: &str) -> Result<(), Error> {
+        let script = format!("sv {verb} {}", sh_single_quote(&self.service_dir(name)));
+        run_script(session, &format!("`sv {verb} {name}`"), &script).await
+    }
+
+    /// [`Runit::sv`], but only while `sv status` reports the service up.
+    async fn sv_if_up(&self, session: &openssh::Session, name: &str, verb: &str) -> Result<(), Error> {
+        let dir = sh_single_quote(&self.service_dir(name));
+        let script = format!("sv status {dir} 2>/dev/null | grep -q '^run:' || exit 0; sv {verb} {dir}");
+        run_script(session, &format!("`sv {verb} {name}`"), &script).await
+    }
+}
+
 /// launchd-backed service management (macOS).
 ///
-/// cook currently models services as systemd unit files (`.service`/`.timer`),
-/// which launchd cannot consume — it expects `.plist` files with a different
-/// schema. Until that translation exists, the launchd manager refuses installs
-/// with a clear error rather than writing systemd files into `LaunchDaemons`
-/// where they would silently never run. The trait seam means wiring up real
-/// launchd support later is confined to this `impl`.
+/// A service and its timer become one LaunchDaemon, translated from the
+/// systemd units by [`crate::service::plist::launch_daemon`] and labelled with
+/// the service's name. Loading it (`launchctl bootstrap`) is what starts it;
+/// a loaded daemon keeps the definition it was loaded with, so a changed plist
+/// takes effect when the service is next restarted.
 #[cfg(feature = "ssh")]
 pub struct Launchd;
 
@@ -175,25 +693,290 @@ impl ServiceManager for Launchd {
         format!("/Library/LaunchDaemons/{name}.plist")
     }
 
-    async fn remote_checksum(&self, _session: &openssh::Session, _path: &str) -> Result<Option<String>, Error> {
-        Err(launchd_unsupported())
+    fn unit_files(&self, name: &str, sources: UnitSources<'_>) -> Result<(Option<String>, Option<String>), Error> {
+        match (sources.service, sources.timer, sources.script) {
+            (Some(service), timer, _) => Ok((Some(crate::service::plist::launch_daemon(name, service, timer)?), None)),
+            (None, Some(_), _) => {
+                Err(format!("service {name}: launchd needs the service unit to go with a timer").into())
+            }
+            (None, None, Some(_)) => {
+                Err(format!("service {name}: script= serves OpenRC and runit; launchd needs a unit file").into())
+            }
+            (None, None, None) => Ok((None, None)),
+        }
+    }
+
+    fn dropin_dir(&self, _name: &str) -> Option<String> {
+        None
+    }
+
+    async fn remote_checksum(&self, session: &openssh::Session, path: &str) -> Result<Option<String>, Error> {
+        let output = session
+            .command("shasum")
+            .arg("-a")
+            .arg("256")
+            .arg(path)
+            .output()
+            .await?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        let sha = String::from_utf8(output.stdout)?
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .to_string();
+        Ok(Some(sha))
+    }
+
+    /// launchd reads a plist when the daemon is loaded, not on demand, so
+    /// there is nothing to reload.
+    async fn daemon_reload(&self, _session: &openssh::Session) -> Result<(), Error> {
+        Ok(())
     }
 
-    async fn reload(&self, _session: &openssh::Session) -> Result<(), Error> {
-        Err(launchd_unsupported())
+    async fn enable(&self, session: &openssh::Session, name: &str, kind: UnitKind, start: bool) -> Result<(), Error> {
+        self.launchctl(session, "enable", &format!("launchctl enable {}", self.target(name)))
+            .await?;
+        if start {
+            self.start(session, name, kind).await?;
+        }
+        Ok(())
+    }
+
+    async fn disable(&self, session: &openssh::Session, name: &str, _kind: UnitKind) -> Result<(), Error> {
+        self.launchctl(session, "disable", &format!("launchctl disable {}", self.target(name)))
+            .await
     }
 
-    async fn enable(&self, _session: &openssh::Session, _name: &str, _kind: UnitKind, _start: bool) -> Result<(), Error> {
-        Err(launchd_unsupported())
+    /// Loads the daemon if it isn't; a service is then kicked to run now,
+    /// while a timer's job waits for its calendar.
+    async fn start(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error> {
+        let mut script = self.bootstrap_script(name);
+        if kind == UnitKind::Service {
+            script.push_str(&format!(" && launchctl kickstart {}", self.target(name)));
+        }
+        self.launchctl(session, "start", &script).await
+    }
+
+    async fn stop(&self, session: &openssh::Session, name: &str, _kind: UnitKind) -> Result<(), Error> {
+        self.launchctl(session, "stop", &format!("launchctl bootout {}", self.target(name)))
+            .await
+    }
+
+    /// Unloads and loads the daemon again, which also picks up a changed
+    /// plist.
+    async fn restart(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error> {
+        let script = format!("launchctl bootout {} 2>/dev/null; true", self.target(name));
+        self.launchctl(session, "restart", &script).await?;
+        self.start(session, name, kind).await
+    }
+
+    /// Only a loaded daemon is restarted, the same way as by `restart`.
+    async fn try_restart(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error> {
+        let target = self.target(name);
+        let mut script = format!(
+            "launchctl print {target} >/dev/null 2>&1 || exit 0; launchctl bootout {target} 2>/dev/null; {}",
+            self.bootstrap_script(name)
+        );
+        if kind == UnitKind::Service {
+            script.push_str(&format!(" && launchctl kickstart {target}"));
+        }
+        self.launchctl(session, "restart", &script).await
+    }
+
+    /// launchd has no reload; a loaded daemon is restarted.
+    async fn reload(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<(), Error> {
+        self.try_restart(session, name, kind).await
+    }
+
+    async fn status(&self, session: &openssh::Session, name: &str, kind: UnitKind) -> Result<UnitStatus, Error> {
+        let target = self.target(name);
+        let script = format!(
+            "if launchctl print {target} >/dev/null 2>&1; then \
+               if launchctl print {target} | grep -q 'state = running'; then echo running; else echo loaded; fi; \
+             else echo unloaded; fi; \
+             if [ ! -f {path} ]; then echo missing; \
+             elif launchctl print-disabled system | grep -qF -e {off} -e {disabled}; then echo disabled; \
+             else echo enabled; fi",
+            path = sh_single_quote(&self.unit_path(name, kind)),
+            off = sh_single_quote(&format!("\"{name}\" => true")),
+            disabled = sh_single_quote(&format!("\"{name}\" => disabled")),
+        );
+        let output = session.command("sh").arg("-c").arg(&script).output().await?;
+        Ok(UnitStatus::parse_status_words(
+            &String::from_utf8_lossy(&output.stdout),
+            kind,
+        ))
+    }
+
+    async fn verify(&self, session: &openssh::Session, paths: &[String]) -> Result<(), Error> {
+        let output = session.command("plutil").arg("-lint").args(paths).output().await?;
+        if !output.status.success() {
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            return Err(anyhow::anyhow!("`plutil -lint` failed: {}", stdout.trim()).into());
+        }
+        Ok(())
     }
 }
 
 #[cfg(feature = "ssh")]
-fn launchd_unsupported() -> Error {
-    anyhow::anyhow!(
-        "managing services on macOS (launchd) is not yet supported: cook generates systemd \
-         unit files, which launchd cannot run. Target a Linux host, or add launchd/plist \
-         support to the Launchd service manager."
-    )
-    .into()
+impl Launchd {
+    /// The daemon as `launchctl` addresses it, e.g. `system/caddy`.
+    fn target(&self, name: &str) -> String {
+        sh_single_quote(&format!("system/{name}"))
+    }
+
+    /// Load the daemon's plist unless it is loaded already.
+    fn bootstrap_script(&self, name: &str) -> String {
+        format!(
+            "launchctl print {target} >/dev/null 2>&1 || launchctl bootstrap system {path}",
+            target = self.target(name),
+            path = sh_single_quote(&self.unit_path(name, UnitKind::Service)),
+        )
+    }
+
+    /// Run a `launchctl` script, with its complaint as the error when it
+    /// fails.
+    async fn launchctl(&self, session: &openssh::Session, what: &str, script: &str) -> Result<(), Error> {
+        let output = session.command("sh").arg("-c").arg(script).output().await?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow::anyhow!("launchctl {what} failed: {}", stderr.trim()).into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InitSystem, Platform, UnitKind, UnitStatus, verify_failures};
+
+    #[test]
+    fn a_running_enabled_unit() {
+        let status = UnitStatus::parse_systemctl("active\nenabled\n");
+        assert_eq!(
+            status,
+            UnitStatus {
+                active: true,
+                enabled: Some(true)
+            }
+        );
+    }
+
+    #[test]
+    fn a_crashed_unit_is_not_active() {
+        assert!(!UnitStatus::parse_systemctl("failed\nenabled\n").active);
+    }
+
+    #[test]
+    fn an_unknown_unit_is_stopped_and_disabled() {
+        let status = UnitStatus::parse_systemctl("inactive\nnot-found\n");
+        assert_eq!(
+            status,
+            UnitStatus {
+                active: false,
+                enabled: Some(false)
+            }
+        );
+    }
+
+    #[test]
+    fn a_static_unit_is_neither_enabled_nor_disabled() {
+        assert_eq!(UnitStatus::parse_systemctl("active\nstatic\n").enabled, None);
+    }
+
+    #[test]
+    fn verify_failures_keep_only_our_units_real_problems() {
+        let output = "\
+/lib/systemd/system/vendor.service:12: Unknown key name 'Foo' in section 'Service', ignoring.
+/etc/systemd/system/api.service:8: Unknown key name 'Restrat' in section 'Service', ignoring.
+api.service: Command /opt/api/bin/api is not executable: No such file or directory
+api.timer: Refusing to start, unit api.service to trigger not loaded.
+";
+        assert_eq!(
+            verify_failures(output, &["api.service", "api.timer"]),
+            vec![
+                "/etc/systemd/system/api.service:8: Unknown key name 'Restrat' in section 'Service', ignoring.",
+                "api.timer: Refusing to start, unit api.service to trigger not loaded.",
+            ]
+        );
+    }
+
+    #[test]
+    fn a_binary_the_deploy_has_yet_to_ship_is_not_a_failure() {
+        let output = "api.service: Command /opt/api/bin/api is not executable: No such file or directory\n";
+        assert!(verify_failures(output, &["api.service"]).is_empty());
+    }
+
+    #[test]
+    fn a_running_daemon_is_active_and_enabled() {
+        assert_eq!(
+            UnitStatus::parse_status_words("running\nenabled\n", UnitKind::Service),
+            UnitStatus {
+                active: true,
+                enabled: Some(true),
+            }
+        );
+    }
+
+    #[test]
+    fn a_loaded_daemon_is_active_only_as_a_timer() {
+        assert!(!UnitStatus::parse_status_words("loaded\nenabled\n", UnitKind::Service).active);
+        assert!(UnitStatus::parse_status_words("loaded\nenabled\n", UnitKind::Timer).active);
+    }
+
+    #[test]
+    fn a_daemon_without_a_plist_or_switched_off_is_disabled() {
+        for output in ["unloaded\nmissing\n", "unloaded\ndisabled\n", ""] {
+            assert_eq!(
+                UnitStatus::parse_status_words(output, UnitKind::Service),
+                UnitStatus {
+                    active: false,
+                    enabled: Some(false),
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn the_init_system_decides_the_linux_platform() {
+        assert_eq!(
+            Platform::parse_detect("Linux\nopenrc\n"),
+            Ok(Platform::Linux(InitSystem::OpenRc))
+        );
+        assert_eq!(
+            Platform::parse_detect("Linux\nrunit\n"),
+            Ok(Platform::Linux(InitSystem::Runit))
+        );
+        assert_eq!(
+            Platform::parse_detect("Linux\nsystemd\n"),
+            Ok(Platform::Linux(InitSystem::Systemd))
+        );
+        assert_eq!(Platform::parse_detect("Darwin\nunknown\n"), Ok(Platform::Macos));
+    }
+
+    #[test]
+    fn an_unknown_init_system_or_os_is_an_error() {
+        assert!(
+            Platform::parse_detect("Linux\nunknown\n")
+                .unwrap_err()
+                .contains("no supported init system")
+        );
+        assert_eq!(
+            Platform::parse_detect("FreeBSD\nunknown\n"),
+            Err("unsupported remote platform: FreeBSD".to_string())
+        );
+    }
+
+    #[test]
+    fn openrc_and_runit_report_running_or_stopped() {
+        assert_eq!(
+            UnitStatus::parse_status_words("stopped\nenabled\n", UnitKind::Service),
+            UnitStatus {
+                active: false,
+                enabled: Some(true),
+            }
+        );
+    }
 }
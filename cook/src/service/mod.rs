@@ -1,4 +1,8 @@
 pub mod api;
+pub mod dropin;
 pub mod manager;
+pub mod plist;
+pub(crate) mod program;
+pub mod script;
 pub mod spec;
 pub mod unit;
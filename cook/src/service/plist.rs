@@ -0,0 +1,475 @@
+//! Translating cook's systemd units into launchd property lists.
+//!
+//! A `service` is modelled as systemd units, whether shipped or generated from
+//! `exec=` and friends. On macOS the same service becomes one LaunchDaemon
+//! plist, from the unit as [`crate::service::program`] reads it:
+//!
+//! | systemd                    | launchd                        |
+//! |----------------------------|--------------------------------|
+//! | `ExecStart=`               | `ProgramArguments`             |
+//! | `User=`, `Group=`          | `UserName`, `GroupName`        |
+//! | `WorkingDirectory=`        | `WorkingDirectory`             |
+//! | `Environment=`             | `EnvironmentVariables`         |
+//! | `Restart=`                 | `KeepAlive`                    |
+//! | `OnCalendar=` (timer)      | `StartCalendarInterval`        |
+
+use crate::Error;
+use crate::service::program::{Restart, is_boot_target, program};
+use crate::service::unit;
+
+/// A plist value. Dictionaries keep their keys in insertion order, so the
+/// output reads in the order [`launch_daemon`] builds it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+    Array(Vec<Value>),
+    Dict(Vec<(String, Value)>),
+}
+
+/// The LaunchDaemon plist for service `name`, from its `.service` unit and
+/// optional `.timer` unit.
+///
+/// A service without a timer runs at load, which launchd does at boot for
+/// every daemon in `/Library/LaunchDaemons`; one with a timer runs on its
+/// calendar instead.
+pub fn launch_daemon(name: &str, service: &str, timer: Option<&str>) -> Result<String, Error> {
+    let program = program(name, service, "launchd")?;
+    let successful_exit = |restart: bool| Value::Dict(vec![("SuccessfulExit".to_string(), Value::Bool(restart))]);
+    let keep_alive = match program.restart {
+        None | Some(Restart::No) => None,
+        Some(Restart::Always) => Some(Value::Bool(true)),
+        Some(Restart::OnFailure) => Some(successful_exit(false)),
+        Some(Restart::OnSuccess) => Some(successful_exit(true)),
+    };
+    let environment: Vec<(String, Value)> = program
+        .environment
+        .into_iter()
+        .map(|(k, v)| (k, Value::String(v)))
+        .collect();
+
+    let mut plist = vec![
+        ("Label".to_string(), Value::String(name.to_string())),
+        (
+            "ProgramArguments".to_string(),
+            Value::Array(program.arguments.into_iter().map(Value::String).collect()),
+        ),
+    ];
+    let mut push = |key: &str, value: Option<Value>| {
+        if let Some(value) = value {
+            plist.push((key.to_string(), value));
+        }
+    };
+    push("UserName", program.user.map(Value::String));
+    push("GroupName", program.group.map(Value::String));
+    push("WorkingDirectory", program.working_directory.map(Value::String));
+    push(
+        "EnvironmentVariables",
+        (!environment.is_empty()).then_some(Value::Dict(environment)),
+    );
+    match timer {
+        None => push("RunAtLoad", Some(Value::Bool(true))),
+        Some(timer) => {
+            let mut intervals = timer_intervals(name, timer)?;
+            push("RunAtLoad", Some(Value::Bool(false)));
+            push(
+                "StartCalendarInterval",
+                Some(if intervals.len() == 1 {
+                    intervals.remove(0)
+                } else {
+                    Value::Array(intervals)
+                }),
+            );
+        }
+    }
+    push("KeepAlive", keep_alive);
+
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <!-- Managed by cook -->\n\
+         <plist version=\"1.0\">\n",
+    );
+    write_value(&mut xml, &Value::Dict(plist), 0);
+    xml.push_str("</plist>\n");
+    Ok(xml)
+}
+
+/// The `StartCalendarInterval` entries for a `.timer` unit.
+fn timer_intervals(name: &str, timer: &str) -> Result<Vec<Value>, Error> {
+    let mut intervals = Vec::new();
+    for (section, key, value) in unit::assignments(timer) {
+        if section.starts_with("X-") || key.starts_with("X-") {
+            continue;
+        }
+        let refuse = |why: &str| -> Error { format!("timer {name}: {key}= in [{section}] {why}").into() };
+        match (section.as_str(), key.as_str()) {
+            ("Unit", "Description" | "Documentation") => {}
+            ("Install", "WantedBy") if is_boot_target(&value) => {}
+            // launchd runs a calendar job it slept through once it wakes,
+            // which is what `Persistent=` asks of systemd.
+            ("Timer", "Persistent") => {}
+            ("Timer", "Unit") if value == format!("{name}.service") => {}
+            ("Timer", "OnCalendar") => intervals.extend(calendar(&value).map_err(|e| refuse(&e))?),
+            _ => return Err(refuse("has no launchd equivalent")),
+        }
+    }
+    if intervals.is_empty() {
+        return Err(format!("timer {name}: launchd needs an OnCalendar=").into());
+    }
+    Ok(intervals)
+}
+
+/// The `StartCalendarInterval` dictionaries for one `OnCalendar=` value.
+///
+/// launchd matches a calendar entry field by field with one value each, so a
+/// list or range in systemd's expression becomes one dictionary per
+/// combination. What launchd cannot express is refused: years, seconds,
+/// repetition steps, and a time zone other than the host's.
+fn calendar(value: &str) -> Result<Vec<Value>, String> {
+    let expanded = match value.trim() {
+        "minutely" => "*-*-* *:*:00",
+        "hourly" => "*-*-* *:00:00",
+        "daily" => "*-*-* 00:00:00",
+        "weekly" => "Mon *-*-* 00:00:00",
+        "monthly" => "*-*-01 00:00:00",
+        "quarterly" => "*-01,04,07,10-01 00:00:00",
+        "semiannually" => "*-01,07-01 00:00:00",
+        "yearly" | "annually" => "*-01-01 00:00:00",
+        other => other,
+    };
+    let mut tokens: Vec<&str> = expanded.split_whitespace().collect();
+    if tokens.is_empty() {
+        return Err("is empty".to_string());
+    }
+
+    let weekdays = if tokens[0].starts_with(|c: char| c.is_ascii_alphabetic()) {
+        Some(weekdays(tokens.remove(0))?)
+    } else {
+        None
+    };
+    let (mut date, mut time) = (None, None);
+    for token in tokens {
+        if token.contains(':') && time.is_none() {
+            time = Some(token);
+        } else if token.contains('-') && date.is_none() && time.is_none() {
+            date = Some(token);
+        } else {
+            return Err(format!(
+                "has {token:?}; launchd runs calendar jobs in the host's time zone and cannot take another"
+            ));
+        }
+    }
+
+    let (month, day) = match date.unwrap_or("*-*-*").split('-').collect::<Vec<_>>()[..] {
+        [year, month, day] => {
+            if year != "*" {
+                return Err("names a year, which launchd cannot match".to_string());
+            }
+            (field(month, 1, 12)?, field(day, 1, 31)?)
+        }
+        [month, day] => (field(month, 1, 12)?, field(day, 1, 31)?),
+        _ => {
+            return Err(format!(
+                "has a date launchd cannot read: {:?}",
+                date.unwrap_or_default()
+            ));
+        }
+    };
+    let (hour, minute) = match time.unwrap_or("00:00:00").split(':').collect::<Vec<_>>()[..] {
+        [hour, minute] => (field(hour, 0, 23)?, field(minute, 0, 59)?),
+        [hour, minute, second] => {
+            if !second.trim_start_matches('0').is_empty() {
+                return Err("fires on a second other than :00, which launchd cannot match".to_string());
+            }
+            (field(hour, 0, 23)?, field(minute, 0, 59)?)
+        }
+        _ => {
+            return Err(format!(
+                "has a time launchd cannot read: {:?}",
+                time.unwrap_or_default()
+            ));
+        }
+    };
+    if weekdays.is_some() && day.is_some() {
+        return Err("names both a weekday and a day of the month; launchd fires when either matches".to_string());
+    }
+
+    // One dictionary per combination of the fields that are set.
+    let mut entries: Vec<Vec<(String, Value)>> = vec![Vec::new()];
+    for (key, values) in [
+        ("Month", month),
+        ("Day", day),
+        ("Weekday", weekdays),
+        ("Hour", hour),
+        ("Minute", minute),
+    ] {
+        let Some(values) = values else { continue };
+        entries = entries
+            .into_iter()
+            .flat_map(|entry| {
+                values.iter().map(move |&v| {
+                    let mut entry = entry.clone();
+                    entry.push((key.to_string(), Value::Integer(v)));
+                    entry
+                })
+            })
+            .collect();
+    }
+    Ok(entries.into_iter().map(Value::Dict).collect())
+}
+
+/// The values of one calendar field, or `None` for `*`.
+fn field(spec: &str, min: i64, max: i64) -> Result<Option<Vec<i64>>, String> {
+    if spec == "*" {
+        return Ok(None);
+    }
+    if spec.contains('/') || spec.contains('~') {
+        return Err(format!("has {spec:?}; launchd has no repetition or end-of-month steps"));
+    }
+    let number = |s: &str| -> Result<i64, String> {
+        s.parse::<i64>()
+            .ok()
+            .filter(|n| (min..=max).contains(n))
+            .ok_or_else(|| format!("has {s:?} where a number from {min} to {max} belongs"))
+    };
+    let mut values = Vec::new();
+    for part in spec.split(',') {
+        match part.split_once("..") {
+            Some((from, to)) => values.extend(number(from)?..=number(to)?),
+            None => values.push(number(part)?),
+        }
+    }
+    Ok(Some(values))
+}
+
+/// The launchd weekdays (Sunday is 0) a systemd weekday list names.
+fn weekdays(spec: &str) -> Result<Vec<i64>, String> {
+    // systemd's order runs Monday to Sunday, so `Sat..Sun` is a valid range.
+    const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+    let index = |s: &str| -> Result<usize, String> {
+        let s = s.to_ascii_lowercase();
+        DAYS.iter()
+            .position(|day| s.len() >= 3 && day.starts_with(&s[..3]))
+            .ok_or_else(|| format!("has {s:?} where a weekday belongs"))
+    };
+    let mut values = Vec::new();
+    for part in spec.split(',') {
+        let (from, to) = match part.split_once("..") {
+            Some((from, to)) => (index(from)?, index(to)?),
+            None => (index(part)?, index(part)?),
+        };
+        values.extend((from..=to).map(|i| (i as i64 + 1) % 7));
+    }
+    Ok(values)
+}
+
+/// Append `value` as plist XML, indented with tabs as Apple's tools write it.
+fn write_value(xml: &mut String, value: &Value, depth: usize) {
+    let indent = "\t".repeat(depth);
+    match value {
+        Value::String(s) => xml.push_str(&format!("{indent}<string>{}</string>\n", escape(s))),
+        Value::Integer(n) => xml.push_str(&format!("{indent}<integer>{n}</integer>\n")),
+        Value::Bool(b) => xml.push_str(&format!("{indent}<{b}/>\n")),
+        Value::Array(items) => {
+            xml.push_str(&format!("{indent}<array>\n"));
+            for item in items {
+                write_value(xml, item, depth + 1);
+            }
+            xml.push_str(&format!("{indent}</array>\n"));
+        }
+        Value::Dict(entries) => {
+            xml.push_str(&format!("{indent}<dict>\n"));
+            for (key, item) in entries {
+                xml.push_str(&format!("{indent}\t<key>{}</key>\n", escape(key)));
+                write_value(xml, item, depth + 1);
+            }
+            xml.push_str(&format!("{indent}</dict>\n"));
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::launch_daemon;
+
+    const SERVICE: &str = "\
+[Unit]
+Description=API server
+After=network.target
+
+[Service]
+ExecStart=/opt/api/bin/api --port 8080 --name \"my api\"
+User=api
+Group=staff
+WorkingDirectory=/opt/api
+Environment=RUST_LOG=info \"GREETING=a & b\"
+Restart=on-failure
+
+[Install]
+WantedBy=multi-user.target
+";
+
+    #[test]
+    fn a_service_becomes_a_daemon_that_runs_at_load() {
+        let plist = launch_daemon("api", SERVICE, None).unwrap();
+        assert_eq!(
+            plist,
+            "\
+<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">
+<!-- Managed by cook -->
+<plist version=\"1.0\">
+<dict>
+\t<key>Label</key>
+\t<string>api</string>
+\t<key>ProgramArguments</key>
+\t<array>
+\t\t<string>/opt/api/bin/api</string>
+\t\t<string>--port</string>
+\t\t<string>8080</string>
+\t\t<string>--name</string>
+\t\t<string>my api</string>
+\t</array>
+\t<key>UserName</key>
+\t<string>api</string>
+\t<key>GroupName</key>
+\t<string>staff</string>
+\t<key>WorkingDirectory</key>
+\t<string>/opt/api</string>
+\t<key>EnvironmentVariables</key>
+\t<dict>
+\t\t<key>RUST_LOG</key>
+\t\t<string>info</string>
+\t\t<key>GREETING</key>
+\t\t<string>a &amp; b</string>
+\t</dict>
+\t<key>RunAtLoad</key>
+\t<true/>
+\t<key>KeepAlive</key>
+\t<dict>
+\t\t<key>SuccessfulExit</key>
+\t\t<false/>
+\t</dict>
+</dict>
+</plist>
+"
+        );
+    }
+
+    #[test]
+    fn a_timer_becomes_a_calendar_interval() {
+        let service = "[Service]\nType=oneshot\nExecStart=/usr/local/bin/sync\n";
+        let timer = "[Timer]\nOnCalendar=*-*-* 03:30:00\nPersistent=true\n\n[Install]\nWantedBy=timers.target\n";
+        let plist = launch_daemon("sync", service, Some(timer)).unwrap();
+        assert!(
+            plist.contains(
+                "\t<key>RunAtLoad</key>\n\t<false/>\n\
+                 \t<key>StartCalendarInterval</key>\n\t<dict>\n\
+                 \t\t<key>Hour</key>\n\t\t<integer>3</integer>\n\
+                 \t\t<key>Minute</key>\n\t\t<integer>30</integer>\n\t</dict>\n"
+            ),
+            "{plist}"
+        );
+        assert!(!plist.contains("KeepAlive"), "{plist}");
+    }
+
+    #[test]
+    fn weekday_ranges_become_one_interval_per_day() {
+        let service = "[Service]\nExecStart=/bin/report\n";
+        let plist = launch_daemon("report", service, Some("[Timer]\nOnCalendar=Fri..Sun 18:00\n")).unwrap();
+        let weekdays: Vec<&str> = plist
+            .lines()
+            .skip_while(|l| !l.contains("StartCalendarInterval"))
+            .filter(|l| l.contains("<integer>"))
+            .collect();
+        // Friday, Saturday and Sunday (0), each at 18:00.
+        assert_eq!(
+            weekdays.iter().map(|l| l.trim()).collect::<Vec<_>>(),
+            [
+                "<integer>5</integer>",
+                "<integer>18</integer>",
+                "<integer>0</integer>",
+                "<integer>6</integer>",
+                "<integer>18</integer>",
+                "<integer>0</integer>",
+                "<integer>0</integer>",
+                "<integer>18</integer>",
+                "<integer>0</integer>",
+            ]
+        );
+    }
+
+    #[test]
+    fn shorthand_calendars_expand_like_systemd() {
+        let service = "[Service]\nExecStart=/bin/rotate\n";
+        let plist = launch_daemon("rotate", service, Some("[Timer]\nOnCalendar=monthly\n")).unwrap();
+        assert!(
+            plist.contains(
+                "\t<dict>\n\t\t<key>Day</key>\n\t\t<integer>1</integer>\n\
+                 \t\t<key>Hour</key>\n\t\t<integer>0</integer>\n\
+                 \t\t<key>Minute</key>\n\t\t<integer>0</integer>\n\t</dict>\n"
+            ),
+            "{plist}"
+        );
+    }
+
+    #[test]
+    fn directives_launchd_cannot_honour_are_refused_by_name() {
+        let refused = |service: &str| launch_daemon("api", service, None).unwrap_err().to_string();
+        assert_eq!(
+            refused("[Service]\nExecStart=/bin/api\nProtectSystem=strict\n"),
+            "service api: ProtectSystem= in [Service] has no launchd equivalent"
+        );
+        assert_eq!(
+            refused("[Unit]\nRequires=postgresql.service\n[Service]\nExecStart=/bin/api\n"),
+            "service api: Requires= in [Unit] has no launchd equivalent"
+        );
+        assert_eq!(
+            refused("[Service]\nExecStart=/bin/api\nRestart=on-abort\n"),
+            "service api: Restart= in [Service] has no launchd equivalent; use no, always, on-failure or on-success"
+        );
+        assert_eq!(
+            refused("[Service]\nExecStart=-/bin/api\n"),
+            "service api: ExecStart= in [Service] uses a systemd prefix, which launchd has no equivalent for"
+        );
+        assert_eq!(
+            refused("[Service]\nExecStart=/bin/api $OPTS\n"),
+            "service api: ExecStart= in [Service] uses a variable, which launchd does not expand"
+        );
+        assert_eq!(
+            refused("[Service]\nWorkingDirectory=%h/api\nExecStart=/bin/api\n"),
+            "service api: WorkingDirectory= in [Service] uses a specifier, which launchd cannot expand"
+        );
+        assert_eq!(
+            refused("[Service]\nUser=api\n"),
+            "service api: launchd needs an ExecStart="
+        );
+    }
+
+    #[test]
+    fn calendars_launchd_cannot_match_are_refused() {
+        let service = "[Service]\nExecStart=/bin/sync\n";
+        let refused = |on_calendar: &str| {
+            let timer = format!("[Timer]\nOnCalendar={on_calendar}\n");
+            launch_daemon("sync", service, Some(&timer)).unwrap_err().to_string()
+        };
+        assert!(refused("Mon..Fri 18:00 America/New_York").contains("host's time zone"));
+        assert!(refused("2027-*-* 00:00").contains("names a year"));
+        assert!(refused("*:0/15").contains("no repetition"));
+        assert!(refused("*-*-* 00:00:30").contains("second other than :00"));
+        assert!(refused("Mon *-*-01 00:00").contains("both a weekday and a day of the month"));
+        assert!(refused("*-*-* 25:00").contains("a number from 0 to 23"));
+        assert_eq!(
+            launch_daemon("sync", service, Some("[Timer]\nOnBootSec=5min\n"))
+                .unwrap_err()
+                .to_string(),
+            "timer sync: OnBootSec= in [Timer] has no launchd equivalent"
+        );
+    }
+}
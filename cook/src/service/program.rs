@@ -0,0 +1,181 @@
+//! A systemd service unit read as the program it runs.
+//!
+//! launchd, OpenRC and runit don't read unit files; cook translates a
+//! `service`'s unit for them instead. [`program`] is the reading they share:
+//! the directives each of them has an equivalent for, with everything else
+//! refused by name, since a service that silently lost its `ProtectSystem=` or
+//! `Requires=` would run differently from the one in the Cookfile.
+//!
+//! Ordering directives (`After=`, `Before=`) and the usual `WantedBy=` targets
+//! are accepted and mostly dropped. `X-` extensions are ignored, as systemd
+//! itself ignores them.
+
+use crate::Error;
+use crate::service::unit;
+
+/// `Restart=`, as far as the other init systems can follow it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Restart {
+    No,
+    Always,
+    OnFailure,
+    OnSuccess,
+}
+
+/// What a service unit runs, and how.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Program {
+    pub description: Option<String>,
+    /// `ExecStart=`, split into the program and its arguments.
+    pub arguments: Vec<String>,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub working_directory: Option<String>,
+    pub environment: Vec<(String, String)>,
+    /// `None` when the unit doesn't say, which systemd takes as `no`.
+    pub restart: Option<Restart>,
+    /// `Type=oneshot`: the program runs to completion rather than staying up.
+    pub oneshot: bool,
+    /// Ordered after the network by `After=network.target` or
+    /// `network-online.target`.
+    pub after_network: bool,
+}
+
+/// Read the `.service` unit of service `name` for `init`, the init system
+/// named in errors.
+pub(crate) fn program(name: &str, service: &str, init: &str) -> Result<Program, Error> {
+    let mut description = None;
+    let mut arguments = None;
+    let mut user = None;
+    let mut group = None;
+    let mut working_directory = None;
+    let mut environment: Vec<(String, String)> = Vec::new();
+    let mut restart = None;
+    let mut oneshot = false;
+    let mut after_network = false;
+
+    for (section, key, value) in unit::assignments(service) {
+        if section.starts_with("X-") || key.starts_with("X-") {
+            continue;
+        }
+        let refuse = |why: &str| -> Error { format!("service {name}: {key}= in [{section}] {why}").into() };
+        if value.contains('%') {
+            return Err(refuse(&format!("uses a specifier, which {init} cannot expand")));
+        }
+        match (section.as_str(), key.as_str()) {
+            ("Unit", "Description") => description = Some(unit::unquote(&value).to_string()),
+            ("Unit", "After") => {
+                after_network |= value
+                    .split_whitespace()
+                    .any(|target| matches!(target, "network.target" | "network-online.target"))
+            }
+            ("Unit", "Documentation" | "Before") => {}
+            ("Install", "WantedBy") if is_boot_target(&value) => {}
+            ("Service", "Type") if matches!(value.as_str(), "simple" | "exec") => {}
+            ("Service", "Type") if value == "oneshot" => oneshot = true,
+            ("Service", "ExecStart") => {
+                if arguments.is_some() {
+                    return Err(refuse(&format!("is given twice; {init} runs a single program")));
+                }
+                arguments = Some(command(&value, init).map_err(|e| refuse(&e))?);
+            }
+            ("Service", "User") => user = Some(unit::unquote(&value).to_string()),
+            ("Service", "Group") => group = Some(unit::unquote(&value).to_string()),
+            ("Service", "WorkingDirectory") => {
+                let path = unit::unquote(&value);
+                if path.starts_with('-') || path == "~" {
+                    return Err(refuse(&format!("must be a plain absolute path for {init}")));
+                }
+                working_directory = Some(path.to_string());
+            }
+            ("Service", "Environment") => {
+                for word in words(&value).map_err(|e| refuse(&e))? {
+                    let (k, v) = word
+                        .split_once('=')
+                        .ok_or_else(|| refuse(&format!("has {word:?}, which is not NAME=value")))?;
+                    environment.retain(|(existing, _)| existing != k);
+                    environment.push((k.to_string(), v.to_string()));
+                }
+            }
+            ("Service", "Restart") => {
+                restart = Some(match value.as_str() {
+                    "no" => Restart::No,
+                    "always" => Restart::Always,
+                    "on-failure" => Restart::OnFailure,
+                    "on-success" => Restart::OnSuccess,
+                    _ => {
+                        return Err(refuse(&format!(
+                            "has no {init} equivalent; use no, always, on-failure or on-success"
+                        )));
+                    }
+                })
+            }
+            _ => return Err(refuse(&format!("has no {init} equivalent"))),
+        }
+    }
+
+    let arguments = arguments.ok_or_else(|| format!("service {name}: {init} needs an ExecStart="))?;
+    Ok(Program {
+        description,
+        arguments,
+        user,
+        group,
+        working_directory,
+        environment,
+        restart,
+        oneshot,
+        after_network,
+    })
+}
+
+/// The targets a unit is installed into to come up on boot, which the other
+/// init systems do for every service they have enabled.
+pub(crate) fn is_boot_target(value: &str) -> bool {
+    value
+        .split_whitespace()
+        .all(|target| matches!(target, "multi-user.target" | "default.target" | "timers.target"))
+}
+
+/// Split an `ExecStart=` command line into its arguments.
+fn command(value: &str, init: &str) -> Result<Vec<String>, String> {
+    if value.starts_with(['-', '@', '+', '!', ':']) {
+        return Err(format!("uses a systemd prefix, which {init} has no equivalent for"));
+    }
+    let words = words(value)?;
+    if words.is_empty() {
+        return Err("is empty".to_string());
+    }
+    if words.iter().any(|word| word.contains('$')) {
+        return Err(format!("uses a variable, which {init} does not expand"));
+    }
+    Ok(words)
+}
+
+/// Split a value into words as systemd does: on whitespace, except inside
+/// single or double quotes, with `\` escaping the next character.
+fn words(value: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, quote) {
+            ('\\', _) => {
+                let escaped = chars.next().ok_or("ends in a lone \\")?;
+                word.get_or_insert_default().push(escaped);
+            }
+            (q, None) if q == '"' || q == '\'' => {
+                quote = Some(q);
+                word.get_or_insert_default();
+            }
+            (q, Some(open)) if q == open => quote = None,
+            (c, None) if c.is_whitespace() => words.extend(word.take()),
+            (c, _) => word.get_or_insert_default().push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err("has an unterminated quote".to_string());
+    }
+    words.extend(word);
+    Ok(words)
+}
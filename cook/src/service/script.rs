@@ -0,0 +1,212 @@
+//! Translating cook's systemd units into OpenRC and runit service scripts.
+//!
+//! Like [`crate::service::plist`] for launchd, these read a `service`'s unit
+//! with [`crate::service::program`] and write what the init system runs
+//! instead: an `/etc/init.d` script for OpenRC, a `run` script under `/etc/sv`
+//! for runit. A host whose service is better described by hand gets that
+//! script from `script=` instead, and these are not used.
+//!
+//! Neither init system has timers; a `service` with one is refused for them.
+
+use crate::service::program::{Program, Restart, program};
+use crate::{Error, sh_single_quote};
+
+/// The OpenRC init script for service `name`, from its `.service` unit.
+///
+/// A unit that restarts runs under `supervise-daemon`, which respawns it
+/// whenever it exits; one that doesn't is backgrounded with a pidfile.
+pub fn openrc_script(name: &str, service: &str) -> Result<String, Error> {
+    let program = program(name, service, "OpenRC")?;
+    refuse_oneshot(name, &program, "OpenRC")?;
+    let supervised = match program.restart {
+        None | Some(Restart::No) => false,
+        // supervise-daemon respawns on any exit, so `on-failure` also
+        // restarts a service that exited cleanly.
+        Some(Restart::Always | Restart::OnFailure) => true,
+        Some(Restart::OnSuccess) => {
+            return Err(format!("service {name}: Restart=on-success has no OpenRC equivalent").into());
+        }
+    };
+
+    let mut script = String::from("#!/sbin/openrc-run\n# Managed by cook\n\n");
+    if let Some(description) = &program.description {
+        script.push_str(&format!("description={}\n", sh_single_quote(description)));
+    }
+    let (command, args) = program.arguments.split_first().expect("a program has its command");
+    script.push_str(&format!("command={}\n", sh_single_quote(command)));
+    if !args.is_empty() {
+        // openrc-run `eval`s the arguments, so each keeps its own quoting.
+        let args: Vec<String> = args.iter().map(|arg| sh_single_quote(arg)).collect();
+        script.push_str(&format!("command_args={}\n", sh_single_quote(&args.join(" "))));
+    }
+    if let Some(user) = run_as(name, &program, "OpenRC")? {
+        script.push_str(&format!("command_user={}\n", sh_single_quote(&user)));
+    }
+    if let Some(directory) = &program.working_directory {
+        script.push_str(&format!("directory={}\n", sh_single_quote(directory)));
+    }
+    if supervised {
+        script.push_str("supervisor=supervise-daemon\n");
+    } else {
+        script.push_str("command_background=true\npidfile=\"/run/${RC_SVCNAME}.pid\"\n");
+    }
+    for (key, value) in &program.environment {
+        script.push_str(&format!("export {key}={}\n", sh_single_quote(value)));
+    }
+    if program.after_network {
+        script.push_str("\ndepend() {\n\tafter net\n}\n");
+    }
+    Ok(script)
+}
+
+/// The runit `run` script for service `name`, from its `.service` unit.
+///
+/// runit restarts a service whenever it exits, so a unit that says it
+/// shouldn't be restarted is refused rather than quietly restarted. So is one
+/// that doesn't say, which systemd takes as `Restart=no`.
+pub fn runit_run(name: &str, service: &str) -> Result<String, Error> {
+    let program = program(name, service, "runit")?;
+    refuse_oneshot(name, &program, "runit")?;
+    let refused = match program.restart {
+        Some(Restart::Always | Restart::OnFailure) => None,
+        None => Some("the unit has no Restart=, which systemd reads as Restart=no"),
+        Some(Restart::No) => Some("Restart=no has no runit equivalent"),
+        Some(Restart::OnSuccess) => Some("Restart=on-success has no runit equivalent"),
+    };
+    if let Some(refused) = refused {
+        return Err(format!(
+            "service {name}: {refused}; runit restarts every service, so give the unit Restart=always"
+        )
+        .into());
+    }
+
+    let mut script = String::from("#!/bin/sh\n# Managed by cook\nexec 2>&1\n");
+    if let Some(directory) = &program.working_directory {
+        script.push_str(&format!("cd {} || exit 1\n", sh_single_quote(directory)));
+    }
+    for (key, value) in &program.environment {
+        script.push_str(&format!("export {key}={}\n", sh_single_quote(value)));
+    }
+    script.push_str("exec ");
+    if let Some(user) = run_as(name, &program, "runit")? {
+        script.push_str(&format!("chpst -u {} ", sh_single_quote(&user)));
+    }
+    let args: Vec<String> = program.arguments.iter().map(|arg| sh_single_quote(arg)).collect();
+    script.push_str(&args.join(" "));
+    script.push('\n');
+    Ok(script)
+}
+
+fn refuse_oneshot(name: &str, program: &Program, init: &str) -> Result<(), Error> {
+    if program.oneshot {
+        return Err(format!("service {name}: Type=oneshot has no {init} equivalent").into());
+    }
+    Ok(())
+}
+
+/// `user` or `user:group`, as both `command_user` and `chpst -u` take it.
+fn run_as(name: &str, program: &Program, init: &str) -> Result<Option<String>, Error> {
+    match (&program.user, &program.group) {
+        (Some(user), Some(group)) => Ok(Some(format!("{user}:{group}"))),
+        (Some(user), None) => Ok(Some(user.clone())),
+        (None, Some(_)) => Err(format!("service {name}: Group= without User= has no {init} equivalent").into()),
+        (None, None) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{openrc_script, runit_run};
+
+    const SERVICE: &str = "\
+[Unit]
+Description=API server
+After=network.target
+
+[Service]
+ExecStart=/opt/api/bin/api --port 8080 --name \"my api\"
+User=api
+Group=api
+WorkingDirectory=/opt/api
+Environment=RUST_LOG=info
+Restart=always
+
+[Install]
+WantedBy=multi-user.target
+";
+
+    #[test]
+    fn a_service_becomes_a_supervised_openrc_script() {
+        assert_eq!(
+            openrc_script("api", SERVICE).unwrap(),
+            "\
+#!/sbin/openrc-run
+# Managed by cook
+
+description='API server'
+command='/opt/api/bin/api'
+command_args=''\\''--port'\\'' '\\''8080'\\'' '\\''--name'\\'' '\\''my api'\\'''
+command_user='api:api'
+directory='/opt/api'
+supervisor=supervise-daemon
+export RUST_LOG='info'
+
+depend() {
+\tafter net
+}
+"
+        );
+    }
+
+    #[test]
+    fn a_service_that_does_not_restart_is_backgrounded_with_a_pidfile() {
+        let script = openrc_script("api", "[Service]\nExecStart=/opt/api/bin/api\n").unwrap();
+        assert_eq!(
+            script,
+            "#!/sbin/openrc-run\n# Managed by cook\n\ncommand='/opt/api/bin/api'\n\
+             command_background=true\npidfile=\"/run/${RC_SVCNAME}.pid\"\n"
+        );
+    }
+
+    #[test]
+    fn a_service_becomes_a_runit_run_script() {
+        assert_eq!(
+            runit_run("api", SERVICE).unwrap(),
+            "\
+#!/bin/sh
+# Managed by cook
+exec 2>&1
+cd '/opt/api' || exit 1
+export RUST_LOG='info'
+exec chpst -u 'api:api' '/opt/api/bin/api' '--port' '8080' '--name' 'my api'
+"
+        );
+    }
+
+    #[test]
+    fn what_the_init_system_cannot_do_is_refused() {
+        let refused = |script: Result<String, crate::Error>| script.unwrap_err().to_string();
+        assert_eq!(
+            refused(runit_run("api", "[Service]\nExecStart=/bin/api\nRestart=no\n")),
+            "service api: Restart=no has no runit equivalent; runit restarts every service, \
+             so give the unit Restart=always"
+        );
+        assert_eq!(
+            refused(runit_run("api", "[Service]\nExecStart=/bin/api\n")),
+            "service api: the unit has no Restart=, which systemd reads as Restart=no; \
+             runit restarts every service, so give the unit Restart=always"
+        );
+        assert_eq!(
+            refused(openrc_script("api", "[Service]\nType=oneshot\nExecStart=/bin/api\n")),
+            "service api: Type=oneshot has no OpenRC equivalent"
+        );
+        assert_eq!(
+            refused(openrc_script("api", "[Service]\nExecStart=/bin/api\nGroup=api\n")),
+            "service api: Group= without User= has no OpenRC equivalent"
+        );
+        assert_eq!(
+            refused(runit_run("api", "[Service]\nExecStart=/bin/api\nProtectSystem=full\n")),
+            "service api: ProtectSystem= in [Service] has no runit equivalent"
+        );
+    }
+}
@@ -1,7 +1,10 @@
 use std::fs;
+use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
+use crate::service::dropin::{Dropin, Dropins, WrongDropins};
+use crate::service::manager::UnitKind;
 use crate::service::unit::{RequiredWorkingDirectory, ServiceOwner};
 use crate::{Error, FromKdl, Modification, ModificationOverSsh, Rule, RuleOverSsh};
 
@@ -9,13 +12,35 @@ use crate::{Error, FromKdl, Modification, ModificationOverSsh, Rule, RuleOverSsh
 use crate::sh_single_quote;
 
 #[cfg(feature = "ssh")]
-use crate::service::manager::{Platform, UnitKind};
+use crate::file::atomic;
+#[cfg(feature = "ssh")]
+use crate::file::backup;
+#[cfg(feature = "ssh")]
+use crate::service::dropin::{self, DropinWrite};
+#[cfg(feature = "ssh")]
+use crate::service::manager::{Platform, UnitSources, UnitStatus};
+#[cfg(feature = "ssh")]
+use crate::service::unit::{self, Specifiers};
+#[cfg(feature = "ssh")]
+use std::{path::Path, time::SystemTime};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceSpec {
     pub name: String,
-    pub service_file_content: String,
-    pub start: bool,
+    /// The unit file to install. `None` for a unit the host already has —
+    /// one shipped by a package — which cook only overrides with `dropins`
+    /// and keeps in `state`.
+    pub service_file_content: Option<String>,
+    /// Drop-ins for `<name>.service.d`, `environment` included; see
+    /// [`crate::service::dropin`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dropins: Vec<Dropin>,
+    /// Whether the unit should be running, checked on every run — so a
+    /// service that crashed or was stopped by hand is brought back. `None`
+    /// leaves it as it is, which is what `start=false` asks for.
+    pub state: Option<ServiceState>,
+    /// Whether the unit should come up on boot.
+    pub enabled: bool,
     pub owner: Option<String>,
     /// Content of the `.timer` unit to install alongside the service, if any.
     ///
@@ -28,6 +53,29 @@ pub struct ServiceSpec {
     /// and the ownership the unit's `User=`/`Group=` need on it.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub working_directory: Option<RequiredWorkingDirectory>,
+    /// `StateDirectory=` entries of the unit file, ensured like the working
+    /// directory so files can be put there before the unit first starts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub state_directories: Vec<RequiredWorkingDirectory>,
+    /// The init script `script=` gives, installed in place of the one cook
+    /// would generate from the unit on an OpenRC or runit host: the
+    /// `/etc/init.d` script or the runit `run` script.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub init_script: Option<String>,
+    /// Copy unit files cook is about to overwrite under
+    /// [`crate::backup::BACKUP_DIR`] first.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub backup: bool,
+}
+
+/// `state=` on a service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServiceState {
+    Running,
+    Stopped,
+    /// Restarted on every run, whether or not anything else changed.
+    Restarted,
 }
 
 /// Build the content of a `.timer` unit that triggers `{name}.service` on the
@@ -51,35 +99,205 @@ fn generate_timer_file_content(name: &str, on_calendar: &str, persistent: bool)
     )
 }
 
+/// The `[Service]` settings a `service` node can give as properties instead
+/// of shipping a unit file: `service api exec="/opt/api/bin/api" user=api`.
+#[derive(Debug, Default)]
+struct UnitProperties {
+    description: Option<String>,
+    exec: Option<String>,
+    user: Option<String>,
+    group: Option<String>,
+    working_directory: Option<String>,
+    environment_file: Option<String>,
+    restart: Option<String>,
+    no_new_privileges: Option<bool>,
+    protect_system: Option<String>,
+    wanted_by: Option<String>,
+}
+
+impl UnitProperties {
+    /// Read `entry` if it is one of these properties, and say whether it was.
+    fn parse(&mut self, entry: &kdl::KdlEntry, service: &str) -> bool {
+        let text = || Some(entry.expect_str().to_string());
+        match entry.name().map(|i| i.value()) {
+            Some("description") => self.description = text(),
+            Some("exec") => self.exec = text(),
+            Some("user") => self.user = text(),
+            Some("group") => self.group = text(),
+            Some("working_directory") => self.working_directory = text(),
+            Some("environment_file") => self.environment_file = text(),
+            Some("wanted_by") => self.wanted_by = text(),
+            Some("restart") => {
+                let restart = entry.expect_str();
+                assert!(
+                    matches!(
+                        restart,
+                        "no" | "always" | "on-success" | "on-failure" | "on-abnormal" | "on-abort" | "on-watchdog"
+                    ),
+                    "service {service}: restart must be one of no, always, on-success, on-failure, on-abnormal, \
+                     on-abort or on-watchdog, not {restart}"
+                );
+                self.restart = text();
+            }
+            Some("no_new_privileges") => {
+                let value = entry.value().as_bool();
+                self.no_new_privileges =
+                    Some(value.unwrap_or_else(|| panic!("service {service}: no_new_privileges must be true or false")));
+            }
+            Some("protect_system") => {
+                // `ProtectSystem=` takes a boolean or `full`/`strict`.
+                let value = match (entry.value().as_bool(), entry.value().as_string()) {
+                    (Some(true), _) => "true",
+                    (Some(false), _) => "false",
+                    (_, Some(level @ ("full" | "strict"))) => level,
+                    _ => panic!("service {service}: protect_system must be true, false, full or strict"),
+                };
+                self.protect_system = Some(value.to_string());
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn is_empty(&self) -> bool {
+        let UnitProperties {
+            description,
+            exec,
+            user,
+            group,
+            working_directory,
+            environment_file,
+            restart,
+            no_new_privileges,
+            protect_system,
+            wanted_by,
+        } = self;
+        description.is_none()
+            && exec.is_none()
+            && user.is_none()
+            && group.is_none()
+            && working_directory.is_none()
+            && environment_file.is_none()
+            && restart.is_none()
+            && no_new_privileges.is_none()
+            && protect_system.is_none()
+            && wanted_by.is_none()
+    }
+}
+
+/// Build the content of a `.service` unit for `{name}` from its properties.
+/// `exec` is the one that's required; the rest are written only when given,
+/// so systemd's defaults apply otherwise.
+fn generate_service_file_content(name: &str, exec: &str, properties: &UnitProperties) -> String {
+    let description = properties.description.as_deref().unwrap_or(name);
+    let wanted_by = properties.wanted_by.as_deref().unwrap_or("multi-user.target");
+    let mut service = format!("ExecStart={exec}\n");
+    let optional = [
+        ("User", properties.user.as_deref()),
+        ("Group", properties.group.as_deref()),
+        ("WorkingDirectory", properties.working_directory.as_deref()),
+        ("EnvironmentFile", properties.environment_file.as_deref()),
+        ("Restart", properties.restart.as_deref()),
+        (
+            "NoNewPrivileges",
+            properties.no_new_privileges.map(|b| if b { "true" } else { "false" }),
+        ),
+        ("ProtectSystem", properties.protect_system.as_deref()),
+    ];
+    for (key, value) in optional {
+        if let Some(value) = value {
+            service.push_str(&format!("{key}={value}\n"));
+        }
+    }
+    format!(
+        "# Managed by cook\n\
+         [Unit]\n\
+         Description={description}\n\
+         After=network.target\n\
+         \n\
+         [Service]\n\
+         {service}\
+         \n\
+         [Install]\n\
+         WantedBy={wanted_by}\n"
+    )
+}
+
 impl FromKdl for ServiceSpec {
     fn kdl_keywords() -> &'static [&'static str] {
         &["service"]
     }
     fn add_rules_to_state(state: &mut crate::State, node: &kdl::KdlNode, context: &crate::Context) {
-        let mut entries = node.entries().iter();
+        let mut entries = node.entries().iter().peekable();
         let name = entries.next().unwrap().expect_str().to_string();
-        let service_file_path = entries.next().unwrap().expect_str();
-        let path = context.local_path(service_file_path);
-        let service_file_content = fs::read_to_string(path).expect("Failed to read service file");
-        let mut start = true;
+        // The unit file is optional: without one the service is a unit the
+        // host already has, e.g. `service postgresql { dropin ... }`.
+        let mut service_file_content = entries.next_if(|e| e.name().is_none()).map(|e| {
+            let path = context.local_path(e.expect_str());
+            fs::read_to_string(path).expect("Failed to read service file")
+        });
+        let mut properties = UnitProperties::default();
+        let mut start = None;
+        let mut service_state = None;
+        let mut enabled = true;
         let mut owner = None;
         let mut timer_file: Option<String> = None;
+        let mut init_script: Option<String> = None;
         let mut on_calendar: Option<String> = None;
         let mut persistent = true;
-        while let Some(e) = entries.next() {
+        let mut backup = context.backup();
+        for e in entries {
+            if properties.parse(e, &name) {
+                continue;
+            }
             match e.name().expect("Failed to get node name").value() {
-                "start" => start = e.value().as_bool().expect("Value for start is not a bool"),
+                "start" => start = Some(e.value().as_bool().expect("Value for start is not a bool")),
+                "state" => {
+                    service_state = Some(match e.expect_str() {
+                        "running" => ServiceState::Running,
+                        "stopped" => ServiceState::Stopped,
+                        "restarted" => ServiceState::Restarted,
+                        z => panic!("service {name}: state must be running, stopped or restarted, not {z}"),
+                    })
+                }
+                "enabled" => enabled = e.value().as_bool().expect("Value for enabled is not a bool"),
                 "owner" => owner = Some(e.expect_str().to_string()),
                 "timer" => {
                     let timer_path = context.local_path(e.expect_str());
                     let content = fs::read_to_string(timer_path).expect("Failed to read timer file");
                     timer_file = Some(content);
                 }
+                "script" => {
+                    let script_path = context.local_path(e.expect_str());
+                    init_script = Some(fs::read_to_string(script_path).expect("Failed to read init script"));
+                }
                 "on_calendar" => on_calendar = Some(e.expect_str().to_string()),
                 "persistent" => persistent = e.value().as_bool().expect("Value for persistent is not a bool"),
+                "backup" => backup = e.value().as_bool().expect("Value for backup is not a bool"),
                 z => panic!("Unexpected option for service: {}", z),
             }
         }
+        let mut dropins = Dropins::default();
+        for child in node.children().map(|c| c.nodes()).unwrap_or_default() {
+            if !dropins.parse_child(child, &name, context) {
+                panic!("Unexpected directive for service: {}", child.name().value());
+            }
+        }
+        let dropins = dropins.finish();
+
+        // A unit generated from properties is read back like a shipped one
+        // below, so its `WorkingDirectory=` and `User=` get the same care.
+        if !properties.is_empty() {
+            assert!(
+                service_file_content.is_none(),
+                "service {name}: give a unit file or unit properties like exec=, not both"
+            );
+            let exec = properties
+                .exec
+                .as_deref()
+                .unwrap_or_else(|| panic!("service {name}: generating a unit needs exec="));
+            service_file_content = Some(generate_service_file_content(&name, exec, &properties));
+        }
 
         let timer_file_content = match (timer_file, on_calendar) {
             (Some(_), Some(_)) => {
@@ -93,15 +311,58 @@ impl FromKdl for ServiceSpec {
             (None, None) => None,
         };
 
-        let working_directory = crate::service::unit::required_working_directory(&service_file_content);
+        // A broken unit caught here would otherwise surface on the host, as a
+        // unit systemd refuses after it is already installed. What may just
+        // be newer than cook's list of directives is left for the host's own
+        // check to judge.
+        for (content, kind) in [
+            (&service_file_content, UnitKind::Service),
+            (&timer_file_content, UnitKind::Timer),
+        ] {
+            let Some(content) = content else { continue };
+            let lint = crate::service::unit::lint(content, kind);
+            let unit = match kind {
+                UnitKind::Service => "service",
+                UnitKind::Timer => "timer",
+            };
+            assert!(
+                lint.errors.is_empty(),
+                "service {name}: the {unit} unit has problems: {}",
+                lint.errors.join("; ")
+            );
+            for warning in &lint.warnings {
+                tracing::warn!(service = %name, "the {unit} unit: {warning}, which may be a typo");
+            }
+        }
+
+        // `start=false` predates `state=`: install and enable the unit, but
+        // leave starting it to someone else.
+        let service_state = match (start, service_state) {
+            (Some(_), Some(_)) => panic!("service {name}: give state= or start=, not both"),
+            (Some(false), None) => None,
+            (_, state) => Some(state.unwrap_or(ServiceState::Running)),
+        };
+
+        let working_directory = service_file_content
+            .as_deref()
+            .and_then(crate::service::unit::required_working_directory);
+        let state_directories = service_file_content
+            .as_deref()
+            .map(crate::service::unit::state_directories)
+            .unwrap_or_default();
 
         state.add_rule(ServiceSpec {
             name,
             service_file_content,
-            start,
+            dropins,
+            state: service_state,
+            enabled,
             owner,
             timer_file_content,
             working_directory,
+            state_directories,
+            init_script,
+            backup,
         });
     }
 }
@@ -119,7 +380,9 @@ impl Rule for ServiceSpec {
     /// chowns the working directory to that user and starts the unit under it,
     /// so a `user` node for it has to run first.
     fn implied_after(&self) -> Vec<String> {
-        crate::service::unit::service_owner(&self.service_file_content)
+        self.service_file_content
+            .as_deref()
+            .and_then(crate::service::unit::service_owner)
             .map(|owner| vec![format!("user:{}", owner.user)])
             .unwrap_or_default()
     }
@@ -139,11 +402,14 @@ pub enum ServiceChange {
     MissingWorkingDirectory(MissingWorkingDirectory),
     WrongWorkingDirectoryOwner(WrongWorkingDirectoryOwner),
     NewService(NewService),
+    WrongDropins(WrongDropins),
+    Control(Control),
 }
 
 /// The unit's `WorkingDirectory=` does not exist on the target. systemd refuses
 /// to start a unit whose working directory is missing (unless it is prefixed
-/// with `-`), so cook creates it as part of applying the service rule.
+/// with `-`), so cook creates it as part of applying the service rule. The
+/// same goes for a `StateDirectory=`.
 #[derive(Debug, Serialize)]
 pub struct MissingWorkingDirectory {
     /// Name of the service the directory belongs to, for readable output.
@@ -165,15 +431,68 @@ pub struct WrongWorkingDirectoryOwner {
 #[derive(Debug, Serialize)]
 pub struct NewService {
     pub name: String,
-    pub service_file_content: String,
-    pub service_file_content_sha256: String,
-    pub start: bool,
+    pub service_file_content: Option<String>,
+    pub service_file_content_sha256: Option<String>,
     pub timer_file_content: Option<String>,
     pub timer_file_content_sha256: Option<String>,
+    /// Where the unit files being replaced are copied to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_file_backup: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timer_file_backup: Option<PathBuf>,
+}
+
+/// A unit is not in the runtime state, or the boot state, its rule asks for.
+#[derive(Debug, Serialize)]
+pub struct Control {
+    pub name: String,
+    pub kind: UnitKind,
+    pub action: ControlAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlAction {
+    Enable,
+    Disable,
+    Start,
+    Stop,
+    Restart,
+}
+
+impl ServiceSpec {
+    /// The unit whose state the rule is about. For a timer-backed service
+    /// that's the timer: it triggers the service on schedule, and the
+    /// service itself is expected to sit stopped between runs.
+    fn controlled_kind(&self) -> UnitKind {
+        if self.timer_file_content.is_some() {
+            UnitKind::Timer
+        } else {
+            UnitKind::Service
+        }
+    }
 }
 
+/// What it takes to get a unit from `status` to what `state` and `enabled`
+/// ask for, boot state first.
 #[cfg(feature = "ssh")]
-fn sha256_hex(content: &str) -> String {
+fn control_actions(state: Option<ServiceState>, enabled: bool, status: UnitStatus) -> Vec<ControlAction> {
+    let mut actions = Vec::new();
+    match status.enabled {
+        Some(false) if enabled => actions.push(ControlAction::Enable),
+        Some(true) if !enabled => actions.push(ControlAction::Disable),
+        _ => {}
+    }
+    match state {
+        Some(ServiceState::Running) if !status.active => actions.push(ControlAction::Start),
+        Some(ServiceState::Stopped) if status.active => actions.push(ControlAction::Stop),
+        Some(ServiceState::Restarted) => actions.push(ControlAction::Restart),
+        _ => {}
+    }
+    actions
+}
+
+pub(crate) fn sha256_hex(content: &str) -> String {
     use sha2::{Digest, Sha256};
     let mut hasher = Sha256::new();
     hasher.update(content.as_bytes());
@@ -185,22 +504,50 @@ fn sha256_hex(content: &str) -> String {
 impl RuleOverSsh for ServiceSpec {
     async fn check_ssh(&self, session: &openssh::Session) -> Result<Vec<Box<dyn Modification>>, Error> {
         let manager = Platform::detect(session).await?.service_manager();
+        // What goes on the host, which is not the systemd units themselves on
+        // a platform that needs them translated.
+        let (service_file_content, timer_file_content) = manager.unit_files(
+            &self.name,
+            UnitSources {
+                service: self.service_file_content.as_deref(),
+                timer: self.timer_file_content.as_deref(),
+                script: self.init_script.as_deref(),
+            },
+        )?;
 
         let service_file_path = manager.unit_path(&self.name, UnitKind::Service);
-        let local_service_sha256 = sha256_hex(&self.service_file_content);
-        let remote_service_sha256 = manager.remote_checksum(session, &service_file_path).await?;
+        let local_service_sha256 = service_file_content.as_deref().map(sha256_hex);
         // The service needs (re)writing if it's missing or its content differs.
-        let service_changed = remote_service_sha256.as_deref() != Some(local_service_sha256.as_str());
+        let (service_changed, service_exists) = if local_service_sha256.is_some() {
+            let remote_service_sha256 = manager.remote_checksum(session, &service_file_path).await?;
+            (
+                remote_service_sha256 != local_service_sha256,
+                remote_service_sha256.is_some(),
+            )
+        } else {
+            (false, false)
+        };
 
         // Mirror the same check for the optional timer unit.
-        let local_timer_sha256 = self.timer_file_content.as_deref().map(sha256_hex);
-        let timer_changed = if self.timer_file_content.is_some() {
-            let timer_file_path = manager.unit_path(&self.name, UnitKind::Timer);
+        let local_timer_sha256 = timer_file_content.as_deref().map(sha256_hex);
+        let timer_file_path = manager.unit_path(&self.name, UnitKind::Timer);
+        let (timer_changed, timer_exists) = if timer_file_content.is_some() {
             let remote_timer_sha256 = manager.remote_checksum(session, &timer_file_path).await?;
-            remote_timer_sha256.as_deref() != local_timer_sha256.as_deref()
+            (
+                remote_timer_sha256.as_deref() != local_timer_sha256.as_deref(),
+                remote_timer_sha256.is_some(),
+            )
         } else {
-            false
+            (false, false)
+        };
+
+        // Only a unit file that is there and about to change is backed up.
+        let stamp = backup::stamp(SystemTime::now());
+        let backup_of = |path: &str, changed: bool, exists: bool| {
+            (self.backup && changed && exists).then(|| backup::backup_path(Path::new(path), &stamp))
         };
+        let service_file_backup = backup_of(&service_file_path, service_changed, service_exists);
+        let timer_file_backup = backup_of(&timer_file_path, timer_changed, timer_exists);
 
         let mut changes: Vec<Box<dyn Modification>> = Vec::new();
 
@@ -208,12 +555,43 @@ impl RuleOverSsh for ServiceSpec {
         // can go missing (or be left root-owned by an earlier run) on a host
         // whose units are already up to date, and it has to be right *before*
         // the unit is enabled, so this change is ordered ahead of the install.
-        if let Some(directory) = &self.working_directory {
+        // Paths with `~` or specifiers are only known once the host says what
+        // they stand for; the directories share the unit's owner, so one
+        // reading covers them all.
+        let directories: Vec<&RequiredWorkingDirectory> =
+            self.working_directory.iter().chain(&self.state_directories).collect();
+        let specifiers = if directories.iter().any(|d| unit::needs_host(&d.path)) {
+            let user = directories
+                .iter()
+                .find_map(|d| d.owner.as_ref())
+                .map(|o| o.user.as_str());
+            Some(host_specifiers(session, user).await?)
+        } else {
+            None
+        };
+        for directory in directories {
+            let path = if unit::needs_host(&directory.path) {
+                let Some(path) = specifiers.as_ref().and_then(|s| s.expand(&directory.path)) else {
+                    tracing::warn!(
+                        service = %self.name,
+                        directory = %directory.path,
+                        "directory uses a specifier the host has no value for; cook will not create it"
+                    );
+                    continue;
+                };
+                path
+            } else {
+                directory.path.clone()
+            };
+            let directory = RequiredWorkingDirectory {
+                path,
+                owner: directory.owner.clone(),
+            };
             match remote_owner(session, &directory.path).await? {
                 None => changes.push(Box::new(ServiceChange::MissingWorkingDirectory(
                     MissingWorkingDirectory {
                         service: self.name.clone(),
-                        directory: directory.clone(),
+                        directory,
                     },
                 ))),
                 Some(current) => {
@@ -233,14 +611,64 @@ impl RuleOverSsh for ServiceSpec {
             }
         }
 
+        // Drop-ins are hashed in one listing of their directory, which is
+        // listed even for a service with none: one cook wrote on an earlier
+        // run may have been taken out of the config since.
+        let dropin_dir = manager.dropin_dir(&self.name);
+        if dropin_dir.is_none() && !self.dropins.is_empty() {
+            return Err(format!("service {}: drop-ins are not supported on this platform", self.name).into());
+        }
+        if let Some(dir) = dropin_dir {
+            let output = session
+                .command("sh")
+                .arg("-c")
+                .arg(dropin::list_script(&dir))
+                .output()
+                .await?;
+            let remote = dropin::parse_listing(&String::from_utf8_lossy(&output.stdout));
+            if let Some(dropin::Diff { write, remove }) = dropin::diff(&self.dropins, &remote) {
+                let write = write
+                    .into_iter()
+                    .map(|(dropin, exists)| {
+                        let path = format!("{dir}/{}", dropin.file);
+                        DropinWrite {
+                            backup: backup_of(&path, true, exists),
+                            file: dropin.file,
+                            content: dropin.content,
+                        }
+                    })
+                    .collect();
+                changes.push(Box::new(ServiceChange::WrongDropins(WrongDropins {
+                    service: self.name.clone(),
+                    dir,
+                    write,
+                    remove,
+                })));
+            }
+        }
+
         if service_changed || timer_changed {
             changes.push(Box::new(ServiceChange::NewService(NewService {
                 name: self.name.clone(),
-                service_file_content: self.service_file_content.clone(),
+                service_file_content,
                 service_file_content_sha256: local_service_sha256,
-                start: self.start,
-                timer_file_content: self.timer_file_content.clone(),
+                timer_file_content,
                 timer_file_content_sha256: local_timer_sha256,
+                service_file_backup,
+                timer_file_backup,
+            })));
+        }
+
+        // Asked every run, not just on install. A unit that isn't installed
+        // yet reads as stopped and disabled, so a new one gets enabled and
+        // started here too.
+        let kind = self.controlled_kind();
+        let status = manager.status(session, &self.name, kind).await?;
+        for action in control_actions(self.state, self.enabled, status) {
+            changes.push(Box::new(ServiceChange::Control(Control {
+                name: self.name.clone(),
+                kind,
+                action,
             })));
         }
 
@@ -248,6 +676,29 @@ impl RuleOverSsh for ServiceSpec {
     }
 }
 
+/// What systemd's specifiers stand for on the host, for a unit running as
+/// `user` — or as the manager's own user, root, for `None`.
+#[cfg(feature = "ssh")]
+async fn host_specifiers(session: &openssh::Session, user: Option<&str>) -> Result<Specifiers, Error> {
+    let user = sh_single_quote(user.unwrap_or("root"));
+    // `systemd-path` knows where this host keeps each directory; the
+    // fallbacks are systemd's own defaults, for hosts without it.
+    let script = format!(
+        "p() {{ systemd-path \"$1\" 2>/dev/null || echo \"$2\"; }}; \
+         echo \"t=$(p system-runtime /run)\"; \
+         echo \"S=$(p system-state-private /var/lib)\"; \
+         echo \"C=$(p system-state-cache /var/cache)\"; \
+         echo \"L=$(p system-state-logs /var/log)\"; \
+         echo \"E=$(p system-configuration /etc)\"; \
+         echo T=/tmp; echo V=/var/tmp; \
+         echo \"H=$(hostname)\"; \
+         echo u={user}; \
+         echo \"h=$(getent passwd {user} | cut -d: -f6)\""
+    );
+    let output = session.command("sh").arg("-c").arg(&script).output().await?;
+    Ok(Specifiers::parse(&String::from_utf8_lossy(&output.stdout)))
+}
+
 /// Ownership of a remote path, in both the name and numeric forms — a unit may
 /// write `User=app` or `User=1001`, and either has to compare equal.
 #[cfg(feature = "ssh")]
@@ -276,13 +727,22 @@ impl RemoteOwner {
     }
 }
 
+/// One round-trip for existence and ownership: user and group names, then
+/// ids. `test -d` first so a non-directory is reported as absent rather than
+/// as a wrong owner. `stat -c` is GNU's and BusyBox's; macOS takes `stat -f`.
+#[cfg(feature = "ssh")]
+fn owner_script(path: &str) -> String {
+    format!(
+        "test -d {p} && {{ stat -c '%U %G %u %g' {p} 2>/dev/null || stat -f '%Su %Sg %u %g' {p}; }}",
+        p = sh_single_quote(path)
+    )
+}
+
 /// Ownership of `path` on the remote host, or `None` if it is not a directory
 /// there (missing, or something else in its place — `mkdir -p` reports which).
 #[cfg(feature = "ssh")]
 async fn remote_owner(session: &openssh::Session, path: &str) -> Result<Option<RemoteOwner>, Error> {
-    // One round-trip for existence and ownership. `test -d` first so a
-    // non-directory is reported as absent rather than as a wrong owner.
-    let script = format!("test -d {p} && stat -c '%U %G %u %g' {p}", p = sh_single_quote(path));
+    let script = owner_script(path);
     let output = session.command("sh").arg("-c").arg(&script).output().await?;
     if !output.status.success() {
         return Ok(None);
@@ -302,6 +762,36 @@ async fn remote_owner(session: &openssh::Session, path: &str) -> Result<Option<R
     }))
 }
 
+/// Put a unit file back the way it was before a failed install: the previous
+/// content, or nothing at all for a unit that did not exist.
+#[cfg(feature = "ssh")]
+async fn roll_back(
+    session: &std::sync::Arc<openssh::Session>,
+    path: &str,
+    previous: Option<&str>,
+) -> Result<(), Error> {
+    match previous {
+        Some(previous) => {
+            let safeguards = atomic::Safeguards::default();
+            atomic::upload(
+                session,
+                Path::new(path),
+                previous.as_bytes(),
+                &atomic::Attributes::default(),
+                &safeguards,
+            )
+            .await
+        }
+        None => {
+            let status = session.command("rm").arg("-f").arg(path).status().await?;
+            if !status.success() {
+                return Err(format!("failed to remove {path} after a failed install").into());
+            }
+            Ok(())
+        }
+    }
+}
+
 /// Give `path` the ownership the unit's processes need.
 ///
 /// A unit that sets only `User=` gets `chown user:`, whose trailing colon tells
@@ -331,12 +821,12 @@ impl Modification for ServiceChange {
         match self {
             ServiceChange::MissingWorkingDirectory(missing) => write!(
                 f,
-                "create working directory {} for service {}",
+                "create directory {} for service {}",
                 missing.directory.path, missing.service
             ),
             ServiceChange::WrongWorkingDirectoryOwner(wrong) => write!(
                 f,
-                "chown working directory {} of service {} from {} to {}:{}",
+                "chown directory {} of service {} from {} to {}:{}",
                 wrong.path,
                 wrong.service,
                 wrong.current,
@@ -344,6 +834,21 @@ impl Modification for ServiceChange {
                 wrong.owner.group.as_deref().unwrap_or_default()
             ),
             ServiceChange::NewService(service) => write!(f, "new service {}", service.name),
+            ServiceChange::WrongDropins(wrong) => write!(f, "update drop-ins of service {}", wrong.service),
+            ServiceChange::Control(control) => {
+                let verb = match control.action {
+                    ControlAction::Enable => "enable",
+                    ControlAction::Disable => "disable",
+                    ControlAction::Start => "start",
+                    ControlAction::Stop => "stop",
+                    ControlAction::Restart => "restart",
+                };
+                let unit = match control.kind {
+                    UnitKind::Service => "service",
+                    UnitKind::Timer => "timer",
+                };
+                write!(f, "{verb} {unit} {}", control.name)
+            }
         }
     }
 
@@ -356,7 +861,6 @@ impl Modification for ServiceChange {
 #[async_trait::async_trait]
 impl ModificationOverSsh for ServiceChange {
     async fn apply_ssh(&self, session: std::sync::Arc<openssh::Session>) -> Result<(), Error> {
-        use openssh_sftp_client::{Sftp, SftpOptions};
         match self {
             ServiceChange::MissingWorkingDirectory(missing) => {
                 let path = &missing.directory.path;
@@ -382,45 +886,86 @@ impl ModificationOverSsh for ServiceChange {
             ServiceChange::NewService(service) => {
                 let manager = Platform::detect(&session).await?.service_manager();
 
-                let sftp = Sftp::from_clonable_session(session.clone(), SftpOptions::new()).await?;
-                let service_path = manager.unit_path(&service.name, UnitKind::Service);
-                let mut f = sftp.create(service_path).await?;
-                f.write_all(service.service_file_content.as_bytes()).await?;
-                f.close().await?;
-
-                if let Some(timer_file_content) = &service.timer_file_content {
-                    let timer_path = manager.unit_path(&service.name, UnitKind::Timer);
-                    let mut f = sftp.create(timer_path).await?;
-                    f.write_all(timer_file_content.as_bytes()).await?;
-                    f.close().await?;
+                let units = [
+                    (
+                        &service.service_file_content,
+                        &service.service_file_backup,
+                        UnitKind::Service,
+                    ),
+                    (&service.timer_file_content, &service.timer_file_backup, UnitKind::Timer),
+                ];
+                let mut written = Vec::new();
+                for (content, backup, kind) in units {
+                    let Some(content) = content else { continue };
+                    let path = manager.unit_path(&service.name, kind);
+                    // Kept in memory to put back should the new unit fail
+                    // verification; `None` for a unit that is new to the host.
+                    let previous = crate::file::edit::read_text(&session, Path::new(&path)).await?;
+                    // runit's run script lives in a directory of its own,
+                    // which a new service does not have yet.
+                    if let Some((dir, _)) = path.rsplit_once('/') {
+                        let status = session.command("mkdir").arg("-p").arg(dir).status().await?;
+                        if !status.success() {
+                            return Err(format!("failed to create {dir}").into());
+                        }
+                    }
+                    // Renamed into place like any other file, so systemd never
+                    // reads a half-written unit.
+                    atomic::upload(
+                        &session,
+                        Path::new(&path),
+                        content.as_bytes(),
+                        &atomic::Attributes {
+                            mode: manager.unit_mode(),
+                            ..atomic::Attributes::default()
+                        },
+                        &atomic::Safeguards {
+                            backup: backup.as_deref(),
+                            ..atomic::Safeguards::default()
+                        },
+                    )
+                    .await?;
+                    written.push((path, previous));
+                }
+
+                // Checked before the reload, so a unit systemd would refuse
+                // never replaces one it was running.
+                let paths: Vec<String> = written.iter().map(|(path, _)| path.clone()).collect();
+                if let Err(e) = manager.verify(&session, &paths).await {
+                    for (path, previous) in &written {
+                        roll_back(&session, path, previous.as_deref()).await?;
+                    }
+                    return Err(format!("service {}: {e}; the previous unit files were restored", service.name).into());
                 }
 
                 // Pick up the freshly written unit files (the "reload-daemon
-                // dance" that `ser` used to do for us).
-                manager.reload(&session).await?;
-
-                // For a timer-backed service it's the timer that gets enabled —
-                // it triggers the service on schedule. Otherwise enable the
-                // service itself.
-                let kind = if service.timer_file_content.is_some() {
-                    UnitKind::Timer
-                } else {
-                    UnitKind::Service
-                };
-                // Always enable, so the unit survives a reboot. `start=false`
-                // only defers starting it — typically because the binary lands
-                // in a later deploy — and `systemctl enable` doesn't need the
-                // binary to exist.
-                manager.enable(&session, &service.name, kind, service.start).await?;
+                // dance" that `ser` used to do for us). Enabling and starting
+                // are the `Control` changes that follow.
+                manager.daemon_reload(&session).await?;
                 Ok(())
             }
+            ServiceChange::WrongDropins(wrong) => {
+                let manager = Platform::detect(&session).await?.service_manager();
+                wrong.apply_ssh(&session, manager.as_ref()).await
+            }
+            ServiceChange::Control(control) => {
+                let manager = Platform::detect(&session).await?.service_manager();
+                let (name, kind) = (control.name.as_str(), control.kind);
+                match control.action {
+                    ControlAction::Enable => manager.enable(&session, name, kind, false).await,
+                    ControlAction::Disable => manager.disable(&session, name, kind).await,
+                    ControlAction::Start => manager.start(&session, name, kind).await,
+                    ControlAction::Stop => manager.stop(&session, name, kind).await,
+                    ControlAction::Restart => manager.restart(&session, name, kind).await,
+                }
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::generate_timer_file_content;
+    use super::{UnitProperties, generate_service_file_content, generate_timer_file_content};
 
     #[test]
     fn on_calendar_generates_a_complete_timer_unit() {
@@ -440,6 +985,84 @@ mod tests {
         );
     }
 
+    /// What `state=` and `enabled=` do to a unit in each runtime state.
+    #[cfg(feature = "ssh")]
+    mod control {
+        use super::super::{ControlAction, ServiceState, control_actions};
+        use crate::service::manager::UnitStatus;
+
+        fn status(active: bool, enabled: Option<bool>) -> UnitStatus {
+            UnitStatus { active, enabled }
+        }
+
+        #[test]
+        fn a_crashed_service_is_started_again() {
+            let actions = control_actions(Some(ServiceState::Running), true, status(false, Some(true)));
+            assert_eq!(actions, vec![ControlAction::Start]);
+        }
+
+        #[test]
+        fn a_new_unit_is_enabled_then_started() {
+            let actions = control_actions(Some(ServiceState::Running), true, status(false, Some(false)));
+            assert_eq!(actions, vec![ControlAction::Enable, ControlAction::Start]);
+        }
+
+        #[test]
+        fn a_stopped_service_is_stopped_and_disabled() {
+            let actions = control_actions(Some(ServiceState::Stopped), false, status(true, Some(true)));
+            assert_eq!(actions, vec![ControlAction::Disable, ControlAction::Stop]);
+        }
+
+        #[test]
+        fn restarted_restarts_every_time() {
+            let actions = control_actions(Some(ServiceState::Restarted), true, status(true, Some(true)));
+            assert_eq!(actions, vec![ControlAction::Restart]);
+        }
+
+        #[test]
+        fn no_state_leaves_the_unit_running_or_not() {
+            assert!(control_actions(None, true, status(false, Some(true))).is_empty());
+        }
+
+        #[test]
+        fn a_static_unit_is_not_enabled() {
+            assert!(control_actions(Some(ServiceState::Running), true, status(true, None)).is_empty());
+        }
+    }
+
+    #[test]
+    fn properties_generate_a_complete_service_unit() {
+        let properties = UnitProperties {
+            user: Some("api".to_string()),
+            working_directory: Some("/srv/api".to_string()),
+            environment_file: Some("/etc/api.env".to_string()),
+            restart: Some("always".to_string()),
+            no_new_privileges: Some(true),
+            protect_system: Some("strict".to_string()),
+            ..UnitProperties::default()
+        };
+        let content = generate_service_file_content("api", "/opt/api/bin/api --port 8080", &properties);
+        assert_eq!(
+            content,
+            "# Managed by cook\n\
+             [Unit]\n\
+             Description=api\n\
+             After=network.target\n\
+             \n\
+             [Service]\n\
+             ExecStart=/opt/api/bin/api --port 8080\n\
+             User=api\n\
+             WorkingDirectory=/srv/api\n\
+             EnvironmentFile=/etc/api.env\n\
+             Restart=always\n\
+             NoNewPrivileges=true\n\
+             ProtectSystem=strict\n\
+             \n\
+             [Install]\n\
+             WantedBy=multi-user.target\n"
+        );
+    }
+
     #[test]
     fn persistent_false_omits_the_persistent_line() {
         let content = generate_timer_file_content("flex-orders-sync", "Mon..Fri 18:00 America/New_York", false);
@@ -500,4 +1123,32 @@ mod tests {
             assert!(current().satisfies(&owner("app", None)));
         }
     }
+
+    /// Where `stat -c` is refused, as on macOS, the BSD form reads the owner.
+    #[cfg(feature = "ssh")]
+    #[test]
+    fn a_directory_owner_is_read_where_stat_takes_bsd_flags() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("cook-owner-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let bin = dir.join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        let shim = bin.join("stat");
+        std::fs::write(
+            &shim,
+            "#!/bin/sh\n[ \"$1\" = -f ] && [ \"$2\" = '%Su %Sg %u %g' ] || exit 1\necho 'ops staff 501 20'\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&shim, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let path_var = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(super::owner_script(dir.to_str().unwrap()))
+            .env("PATH", path_var)
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "ops staff 501 20\n");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
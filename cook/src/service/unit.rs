@@ -7,18 +7,39 @@
 //!
 //! The parsing follows systemd's file format where it matters and stops short
 //! where it doesn't: sections, comments, line continuations, quoting and the
-//! `-` "ignore failure" prefix are handled; specifier expansion (`%h`, `%S`,
-//! …) is not, because those resolve against the *remote* host's runtime
-//! context, which cook cannot know at plan time.
+//! `-` "ignore failure" prefix are handled at parse time. Specifiers (`%h`,
+//! `%S`, …) resolve against the *remote* host, so paths keep them until check
+//! time, when [`Specifiers`] expands them with facts read off the host.
+//!
+//! The same reading, plus [`set_value`], serves `setting` for INI files in
+//! general, which share the format's sections, comments and `key=value` lines.
+//!
+//! [`lint`] checks a unit at parse time: a line systemd can't read fails the
+//! Cookfile there instead of the unit on the host, and a section or key it
+//! doesn't know of — a typo, or a directive newer than its list — is a
+//! warning.
+
+use std::ops::Range;
+
+use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
+use crate::service::manager::UnitKind;
+
 /// Split a unit file into logical lines, joining any line continued with a
 /// trailing `\` onto the one that follows it.
 fn logical_lines(content: &str) -> Vec<String> {
+    logical_spans(content).into_iter().map(|(line, _)| line).collect()
+}
+
+/// [`logical_lines`], each with the range of physical lines it came from, so
+/// an edit can replace a continued assignment as a whole.
+fn logical_spans(content: &str) -> Vec<(String, Range<usize>)> {
     let mut lines = Vec::new();
     let mut pending = String::new();
-    for raw in content.lines() {
+    let mut start = 0;
+    for (i, raw) in content.lines().enumerate() {
         let line = raw.trim();
         if let Some(head) = line.strip_suffix('\\') {
             pending.push_str(head.trim_end());
@@ -26,11 +47,12 @@ fn logical_lines(content: &str) -> Vec<String> {
             continue;
         }
         pending.push_str(line);
-        lines.push(std::mem::take(&mut pending));
+        lines.push((std::mem::take(&mut pending), start..i + 1));
+        start = i + 1;
     }
     // A file whose last line ends in `\` leaves the continuation dangling.
     if !pending.is_empty() {
-        lines.push(pending);
+        lines.push((pending, start..content.lines().count()));
     }
     lines
 }
@@ -41,19 +63,86 @@ fn logical_lines(content: &str) -> Vec<String> {
 /// the last value. An explicitly empty assignment (`Key=`) resets the value to
 /// `None`, which is how systemd clears a directive inherited from a drop-in.
 fn directive(content: &str, section: &str, key: &str) -> Option<String> {
+    value(content, Some(section), key)
+}
+
+/// A section header's name, for a logical line that is one.
+fn section_name(line: &str) -> Option<&str> {
+    line.strip_prefix('[').and_then(|l| l.strip_suffix(']')).map(str::trim)
+}
+
+/// Whether a logical line is a blank or a comment.
+fn is_blank(line: &str) -> bool {
+    line.is_empty() || line.starts_with('#') || line.starts_with(';')
+}
+
+/// Every assignment in a unit file, in order, as `(section, key, value)`.
+/// Assignments before the first header have an empty section.
+pub(crate) fn assignments(content: &str) -> Vec<(String, String, String)> {
+    let mut current_section = String::new();
+    let mut assignments = Vec::new();
+    for line in logical_lines(content) {
+        let line = line.trim();
+        if is_blank(line) {
+            continue;
+        }
+        if let Some(name) = section_name(line) {
+            current_section = name.to_string();
+            continue;
+        }
+        if let Some((k, v)) = line.split_once('=') {
+            assignments.push((current_section.clone(), k.trim().to_string(), v.trim().to_string()));
+        }
+    }
+    assignments
+}
+
+/// Every value of a list directive such as `StateDirectory=`, whose
+/// assignments add up rather than replace each other. An empty assignment
+/// clears the ones before it.
+fn list_directive(content: &str, section: &str, key: &str) -> Vec<String> {
+    let mut current_section: Option<String> = None;
+    let mut values = Vec::new();
+    for line in logical_lines(content) {
+        let line = line.trim();
+        if is_blank(line) {
+            continue;
+        }
+        if let Some(name) = section_name(line) {
+            current_section = Some(name.to_string());
+            continue;
+        }
+        let Some((k, v)) = line.split_once('=') else {
+            continue;
+        };
+        if current_section.as_deref() != Some(section) || k.trim() != key {
+            continue;
+        }
+        let v = v.trim();
+        if v.is_empty() {
+            values.clear();
+        }
+        values.extend(v.split_whitespace().map(|v| unquote(v).to_string()));
+    }
+    values
+}
+
+/// [`directive`] for INI files generally, where `None` is the section before
+/// the first header.
+pub(crate) fn value(content: &str, section: Option<&str>, key: &str) -> Option<String> {
     let mut current_section: Option<String> = None;
     let mut value = None;
 
     for line in logical_lines(content) {
         let line = line.trim();
-        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+        if is_blank(line) {
             continue;
         }
-        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
-            current_section = Some(name.trim().to_string());
+        if let Some(name) = section_name(line) {
+            current_section = Some(name.to_string());
             continue;
         }
-        if current_section.as_deref() != Some(section) {
+        if current_section.as_deref() != section {
             continue;
         }
         let Some((k, v)) = line.split_once('=') else {
@@ -69,9 +158,79 @@ fn directive(content: &str, section: &str, key: &str) -> Option<String> {
     value
 }
 
+/// `content` with `key` in `section` set to `new`, or with every assignment
+/// of it removed for `None`. No other line changes.
+///
+/// The assignment [`value`] reads — the last one — is the one replaced. A key
+/// the section doesn't have goes after the section's last line, and a missing
+/// section is added at the end. New lines follow the file's own spacing
+/// around `=`.
+pub(crate) fn set_value(content: &str, section: Option<&str>, key: &str, new: Option<&str>) -> String {
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let mut current_section: Option<String> = None;
+    // The section before the first header always exists, if only empty.
+    let mut found = section.is_none();
+    let mut insert_at = 0;
+    let mut assignments: Vec<Range<usize>> = Vec::new();
+    let mut separator = None;
+    for (line, span) in logical_spans(content) {
+        let line = line.trim();
+        if let Some(name) = section_name(line) {
+            current_section = Some(name.to_string());
+            if current_section.as_deref() == section {
+                found = true;
+                insert_at = span.end;
+            }
+            continue;
+        }
+        if is_blank(line) {
+            continue;
+        }
+        let Some((k, _)) = line.split_once('=') else {
+            continue;
+        };
+        separator.get_or_insert(if k.ends_with(char::is_whitespace) { " = " } else { "=" });
+        if current_section.as_deref() != section {
+            continue;
+        }
+        insert_at = span.end;
+        if k.trim() == key {
+            assignments.push(span);
+        }
+    }
+
+    match new {
+        Some(new) => {
+            let assignment = format!("{key}{}{new}", separator.unwrap_or("="));
+            if let Some(last) = assignments.pop() {
+                let indent: String = lines[last.start].chars().take_while(|c| c.is_whitespace()).collect();
+                lines.splice(last, [format!("{indent}{assignment}")]);
+            } else if found {
+                lines.insert(insert_at, assignment);
+            } else {
+                if lines.last().is_some_and(|l| !l.trim().is_empty()) {
+                    lines.push(String::new());
+                }
+                lines.push(format!("[{}]", section.expect("the unnamed section is never missing")));
+                lines.push(assignment);
+            }
+        }
+        None => {
+            for span in assignments.into_iter().rev() {
+                lines.drain(span);
+            }
+        }
+    }
+    let mut out = lines.join("\n");
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
 /// Strip one layer of matching surrounding quotes, as systemd does when it
 /// reads a directive value.
-fn unquote(value: &str) -> &str {
+pub(crate) fn unquote(value: &str) -> &str {
     for quote in ['"', '\''] {
         if let Some(inner) = value.strip_prefix(quote).and_then(|v| v.strip_suffix(quote)) {
             return inner;
@@ -166,12 +325,11 @@ pub struct RequiredWorkingDirectory {
 /// The working directory cook must ensure exists, or `None` if there is nothing
 /// to enforce.
 ///
-/// Yields `None` when the unit sets no `WorkingDirectory=`, when the path is
+/// Yields `None` when the unit sets no `WorkingDirectory=`, or when the path is
 /// prefixed with `-` (the author has declared a missing directory acceptable,
-/// so cook does not create one), or when the path's meaning depends on the
-/// remote host's runtime context: `~` resolves to the service user's home and
-/// `%` specifiers resolve against systemd's runtime state, neither of which
-/// cook can evaluate at plan time.
+/// so cook does not create one). A path that depends on the host — `~` for
+/// the service user's home, or `%` specifiers — is kept as written, for
+/// [`Specifiers::expand`] to resolve at check time.
 pub fn required_working_directory(content: &str) -> Option<RequiredWorkingDirectory> {
     let WorkingDirectory { path, optional } = working_directory(content)?;
     if optional {
@@ -181,22 +339,261 @@ pub fn required_working_directory(content: &str) -> Option<RequiredWorkingDirect
         );
         return None;
     }
-    if path == "~" || path.contains('%') {
-        tracing::warn!(
-            working_directory = %path,
-            "WorkingDirectory resolves against the remote host's runtime context; cook will not create it"
-        );
-        return None;
-    }
     Some(RequiredWorkingDirectory {
         owner: service_owner(content),
         path,
     })
 }
 
+/// The directories a unit's `StateDirectory=` names, as `%S/…` paths for
+/// [`Specifiers::expand`], with the unit's owner.
+///
+/// systemd creates these itself when the unit starts, but files cook places
+/// there ahead of the first start need them earlier, so they are ensured
+/// like the working directory. Empty under `DynamicUser=yes`, where systemd
+/// keeps them under `private/` and links them into place itself.
+pub fn state_directories(content: &str) -> Vec<RequiredWorkingDirectory> {
+    if boolean(content, "Service", "DynamicUser") == Some(true) {
+        return Vec::new();
+    }
+    list_directive(content, "Service", "StateDirectory")
+        .into_iter()
+        // `StateDirectory=name:alias` also links `alias` to `name`; only
+        // `name` is a directory.
+        .map(|entry| {
+            entry
+                .split(':')
+                .next()
+                .unwrap_or_default()
+                .trim_matches('/')
+                .to_string()
+        })
+        .filter(|name| !name.is_empty())
+        .map(|name| RequiredWorkingDirectory {
+            path: format!("%S/{name}"),
+            owner: service_owner(content),
+        })
+        .collect()
+}
+
+/// Whether `path` means something only on the host, by way of `~` or a
+/// specifier.
+pub fn needs_host(path: &str) -> bool {
+    path == "~" || path.contains('%')
+}
+
+/// What systemd's specifiers stand for on one host, for the system manager
+/// and the unit's user.
+///
+/// Read with one script on the host, [`Specifiers::parse`] takes its
+/// `letter=value` lines: `t` (runtime directory), `S` (state), `C` (cache),
+/// `L` (logs), `E` (configuration), `T` and `V` (temporary directories),
+/// `H` (hostname), `u` (user) and `h` (the user's home).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Specifiers {
+    values: BTreeMap<char, String>,
+}
+
+impl Specifiers {
+    pub fn parse(output: &str) -> Specifiers {
+        let values = output
+            .lines()
+            .filter_map(|line| {
+                let (letter, value) = line.split_once('=')?;
+                let mut letters = letter.chars();
+                let (Some(letter), None) = (letters.next(), letters.next()) else {
+                    return None;
+                };
+                let value = value.trim();
+                (!value.is_empty()).then(|| (letter, value.to_string()))
+            })
+            .collect();
+        Specifiers { values }
+    }
+
+    /// `path` with `~` and every specifier replaced, or `None` if it uses one
+    /// these facts don't cover — an instance name (`%i`) has no value until a
+    /// unit is instantiated.
+    pub fn expand(&self, path: &str) -> Option<String> {
+        if path == "~" {
+            return self.values.get(&'h').cloned();
+        }
+        let mut expanded = String::with_capacity(path.len());
+        let mut chars = path.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                expanded.push(c);
+                continue;
+            }
+            match chars.next()? {
+                '%' => expanded.push('%'),
+                letter => expanded.push_str(self.values.get(&letter)?),
+            }
+        }
+        Some(expanded)
+    }
+}
+
+/// Keys of the `[Unit]` section, whitespace-separated, besides the
+/// `Condition…=` and `Assert…=` families.
+const UNIT_KEYS: &str = "\
+Description Documentation Wants Requires Requisite BindsTo PartOf Upholds Conflicts Before After
+OnFailure OnSuccess PropagatesReloadTo ReloadPropagatedFrom PropagatesStopTo StopPropagatedFrom
+JoinsNamespaceOf RequiresMountsFor WantsMountsFor OnFailureJobMode IgnoreOnIsolate
+StopWhenUnneeded RefuseManualStart RefuseManualStop AllowIsolate DefaultDependencies
+SurviveFinalKillSignal CollectMode FailureAction SuccessAction FailureActionExitStatus
+SuccessActionExitStatus JobTimeoutSec JobRunningTimeoutSec JobTimeoutAction
+JobTimeoutRebootArgument StartLimitIntervalSec StartLimitInterval StartLimitBurst
+StartLimitAction RebootArgument SourcePath
+";
+
+const INSTALL_KEYS: &str = "Alias WantedBy RequiredBy UpheldBy Also DefaultInstance";
+
+/// Keys of the `[Service]` section: its own, then the execution environment,
+/// kill and resource-control keys it shares with other process units.
+const SERVICE_KEYS: &str = "\
+Type ExitType RemainAfterExit GuessMainPID PIDFile BusName ExecStart ExecStartPre ExecStartPost
+ExecCondition ExecReload ExecStop ExecStopPost RestartSec RestartSteps RestartMaxDelaySec
+TimeoutStartSec TimeoutStopSec TimeoutAbortSec TimeoutSec TimeoutStartFailureMode
+TimeoutStopFailureMode RuntimeMaxSec RuntimeRandomizedExtraSec WatchdogSec Restart RestartMode
+SuccessExitStatus RestartPreventExitStatus RestartForceExitStatus RootDirectoryStartOnly
+PermissionsStartOnly NonBlocking NotifyAccess Sockets FileDescriptorStoreMax
+FileDescriptorStorePreserve USBFunctionDescriptors USBFunctionStrings OOMPolicy OpenFile
+ReloadSignal StartLimitIntervalSec StartLimitInterval StartLimitBurst StartLimitAction
+FailureAction SuccessAction RebootArgument ExecSearchPath WorkingDirectory RootDirectory
+RootImage RootImageOptions RootEphemeral RootHash RootHashSignature RootVerity RootImagePolicy
+MountImagePolicy ExtensionImagePolicy MountAPIVFS BindLogSockets ProtectProc ProcSubset
+BindPaths BindReadOnlyPaths MountImages ExtensionImages ExtensionDirectories User Group
+DynamicUser SupplementaryGroups SetLoginEnvironment PAMName CapabilityBoundingSet
+AmbientCapabilities NoNewPrivileges SecureBits SELinuxContext AppArmorProfile SmackProcessLabel
+LimitCPU LimitFSIZE LimitDATA LimitSTACK LimitCORE LimitRSS LimitNOFILE LimitAS LimitNPROC
+LimitMEMLOCK LimitLOCKS LimitSIGPENDING LimitMSGQUEUE LimitNICE LimitRTPRIO LimitRTTIME UMask
+CoredumpFilter KeyringMode OOMScoreAdjust TimerSlackNSec Personality IgnoreSIGPIPE Nice
+CPUSchedulingPolicy CPUSchedulingPriority CPUSchedulingResetOnFork CPUAffinity NUMAPolicy
+NUMAMask IOSchedulingClass IOSchedulingPriority ProtectSystem ProtectHome RuntimeDirectory
+StateDirectory CacheDirectory LogsDirectory ConfigurationDirectory RuntimeDirectoryMode
+StateDirectoryMode CacheDirectoryMode LogsDirectoryMode ConfigurationDirectoryMode
+RuntimeDirectoryPreserve TimeoutCleanSec ReadWritePaths ReadOnlyPaths InaccessiblePaths
+ExecPaths NoExecPaths ReadWriteDirectories ReadOnlyDirectories InaccessibleDirectories
+TemporaryFileSystem PrivateTmp PrivateDevices PrivateNetwork NetworkNamespacePath PrivateIPC
+IPCNamespacePath PrivatePIDs MemoryKSM PrivateUsers ProtectHostname ProtectClock
+ProtectKernelTunables ProtectKernelModules ProtectKernelLogs ProtectControlGroups
+RestrictAddressFamilies RestrictFileSystems RestrictNamespaces DelegateNamespaces
+LockPersonality MemoryDenyWriteExecute RestrictRealtime RestrictSUIDSGID RemoveIPC PrivateMounts
+MountFlags SystemCallFilter SystemCallErrorNumber SystemCallArchitectures SystemCallLog
+Environment EnvironmentFile PassEnvironment UnsetEnvironment StandardInput StandardOutput
+StandardError StandardInputText StandardInputData LogLevelMax LogExtraFields
+LogRateLimitIntervalSec LogRateLimitBurst LogFilterPatterns LogNamespace SyslogIdentifier
+SyslogFacility SyslogLevel SyslogLevelPrefix TTYPath TTYReset TTYVHangup TTYRows TTYColumns
+TTYVTDisallocate LoadCredential LoadCredentialEncrypted ImportCredential SetCredential
+SetCredentialEncrypted UtmpIdentifier UtmpMode KillMode KillSignal RestartKillSignal SendSIGHUP
+SendSIGKILL FinalKillSignal WatchdogSignal CPUAccounting CPUWeight StartupCPUWeight CPUQuota
+CPUQuotaPeriodSec AllowedCPUs StartupAllowedCPUs AllowedMemoryNodes StartupAllowedMemoryNodes
+MemoryAccounting MemoryMin MemoryLow StartupMemoryLow DefaultStartupMemoryLow MemoryHigh
+StartupMemoryHigh MemoryMax StartupMemoryMax MemorySwapMax StartupMemorySwapMax MemoryZSwapMax
+StartupMemoryZSwapMax MemoryZSwapWriteback TasksAccounting TasksMax IOAccounting IOWeight
+StartupIOWeight IODeviceWeight IOReadBandwidthMax IOWriteBandwidthMax IOReadIOPSMax
+IOWriteIOPSMax IODeviceLatencyTargetSec IPAccounting IPAddressAllow IPAddressDeny
+SocketBindAllow SocketBindDeny RestrictNetworkInterfaces NFTSet IPIngressFilterPath
+IPEgressFilterPath BPFProgram DeviceAllow DevicePolicy Slice Delegate DelegateSubgroup
+DisableControllers ManagedOOMSwap ManagedOOMMemoryPressure ManagedOOMMemoryPressureLimit
+ManagedOOMMemoryPressureDurationSec ManagedOOMPreference MemoryPressureWatch
+MemoryPressureThresholdSec CoredumpReceive CPUShares StartupCPUShares MemoryLimit
+BlockIOAccounting BlockIOWeight StartupBlockIOWeight BlockIODeviceWeight BlockIOReadBandwidth
+BlockIOWriteBandwidth
+";
+
+const TIMER_KEYS: &str = "\
+OnActiveSec OnBootSec OnStartupSec OnUnitActiveSec OnUnitInactiveSec OnCalendar AccuracySec
+RandomizedDelaySec RandomizedOffsetSec FixedRandomDelay DeferReactivation OnClockChange
+OnTimezoneChange Unit Persistent WakeSystem RemainAfterElapse
+";
+
+/// Whether `key` belongs in `section` of a unit of `kind`, or `None` for a
+/// section such a unit doesn't have.
+fn known_key(kind: UnitKind, section: &str, key: &str) -> Option<bool> {
+    let keys = match (kind, section) {
+        (_, "Unit") if key.starts_with("Condition") || key.starts_with("Assert") => return Some(true),
+        (_, "Unit") => UNIT_KEYS,
+        (_, "Install") => INSTALL_KEYS,
+        (UnitKind::Service, "Service") => SERVICE_KEYS,
+        (UnitKind::Timer, "Timer") => TIMER_KEYS,
+        _ => return None,
+    };
+    Some(keys.split_whitespace().any(|k| k == key))
+}
+
+/// What [`lint`] found in a unit file, one message per finding, each with
+/// the line it is on.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Lint {
+    /// Lines that aren't a section, an assignment or a comment, assignments
+    /// outside any section, and a service with nothing to run.
+    pub errors: Vec<String>,
+    /// Sections and keys missing from the ones listed here. Usually a typo,
+    /// but systemd gains directives with every release, so not an error.
+    pub warnings: Vec<String>,
+}
+
+/// The problems systemd would have with a unit file of `kind`.
+///
+/// Sections and keys are checked against the ones systemd documents; `X-`
+/// prefixed ones are the format's extension point and pass unchecked. A
+/// service also needs an `ExecStart=`, unless it is `Type=oneshot`.
+pub fn lint(content: &str, kind: UnitKind) -> Lint {
+    let mut lint = Lint::default();
+    // `None` before the first header; `Some(None)` inside an unknown section,
+    // whose keys have already been complained about once.
+    let mut current_section: Option<Option<String>> = None;
+
+    for (line, span) in logical_spans(content) {
+        let line = line.trim();
+        let number = span.start + 1;
+        if is_blank(line) {
+            continue;
+        }
+        if let Some(name) = section_name(line) {
+            let known = name.starts_with("X-") || known_key(kind, name, "").is_some();
+            if !known {
+                lint.warnings.push(format!("line {number}: unknown section [{name}]"));
+            }
+            current_section = Some(known.then(|| name.to_string()));
+            continue;
+        }
+        let Some((key, _)) = line.split_once('=') else {
+            lint.errors
+                .push(format!("line {number}: expected key=value, got {line:?}"));
+            continue;
+        };
+        let key = key.trim();
+        match &current_section {
+            None => lint
+                .errors
+                .push(format!("line {number}: {key}= is outside any section")),
+            Some(Some(section)) if !section.starts_with("X-") && !key.starts_with("X-") => {
+                if known_key(kind, section, key) == Some(false) {
+                    lint.warnings
+                        .push(format!("line {number}: unknown key {key}= in [{section}]"));
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    let oneshot = directive(content, "Service", "Type").as_deref() == Some("oneshot");
+    if kind == UnitKind::Service && !oneshot && directive(content, "Service", "ExecStart").is_none() {
+        lint.errors.push("[Service] has no ExecStart=".to_string());
+    }
+    lint
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{required_working_directory, service_owner, working_directory};
+    use super::{
+        Lint, Specifiers, lint, required_working_directory, service_owner, set_value, state_directories, value,
+        working_directory,
+    };
+    use crate::service::manager::UnitKind;
 
     const UNIT: &str = "\
 [Unit]
@@ -262,9 +659,54 @@ WantedBy=multi-user.target
     }
 
     #[test]
-    fn host_resolved_paths_are_not_required() {
-        assert_eq!(required_working_directory("[Service]\nWorkingDirectory=~\n"), None);
-        assert_eq!(required_working_directory("[Service]\nWorkingDirectory=%h/app\n"), None);
+    fn host_resolved_paths_are_kept_for_check_time() {
+        let unit = "[Service]\nWorkingDirectory=%S/app\nUser=app\n";
+        let directory = required_working_directory(unit).unwrap();
+        assert_eq!(directory.path, "%S/app");
+        assert_eq!(directory.owner.unwrap().user, "app");
+        assert_eq!(
+            required_working_directory("[Service]\nWorkingDirectory=~\n").map(|d| d.path),
+            Some("~".to_string())
+        );
+    }
+
+    fn specifiers() -> Specifiers {
+        Specifiers::parse("t=/run\nS=/var/lib\nC=/var/cache\nH=web-1\nu=app\nh=/home/app\nweird line\nL=\n")
+    }
+
+    #[test]
+    fn specifiers_expand_against_host_facts() {
+        let specifiers = specifiers();
+        assert_eq!(specifiers.expand("%S/app").as_deref(), Some("/var/lib/app"));
+        assert_eq!(specifiers.expand("%t/%u").as_deref(), Some("/run/app"));
+        assert_eq!(specifiers.expand("~").as_deref(), Some("/home/app"));
+        assert_eq!(
+            specifiers.expand("%h/data-100%%").as_deref(),
+            Some("/home/app/data-100%")
+        );
+        assert_eq!(specifiers.expand("/srv/plain").as_deref(), Some("/srv/plain"));
+    }
+
+    #[test]
+    fn a_specifier_without_a_fact_does_not_expand() {
+        let specifiers = specifiers();
+        // An instance name only exists once a unit is instantiated.
+        assert_eq!(specifiers.expand("/srv/%i"), None);
+        // A fact the host reported empty is no fact at all.
+        assert_eq!(specifiers.expand("%L/app"), None);
+        assert_eq!(specifiers.expand("/srv/trailing%"), None);
+        assert_eq!(Specifiers::default().expand("~"), None);
+    }
+
+    #[test]
+    fn state_directories_are_required_under_the_state_dir() {
+        let unit = "[Service]\nUser=app\nStateDirectory=app app/cache:alias\nStateDirectory=extra\n";
+        let paths: Vec<String> = state_directories(unit).into_iter().map(|d| d.path).collect();
+        assert_eq!(paths, ["%S/app", "%S/app/cache", "%S/extra"]);
+        assert_eq!(state_directories(unit)[0].owner.as_ref().unwrap().user, "app");
+        // An empty assignment resets the list, as it does in systemd.
+        assert!(state_directories("[Service]\nStateDirectory=app\nStateDirectory=\n").is_empty());
+        assert!(state_directories("[Service]\nDynamicUser=yes\nStateDirectory=app\n").is_empty());
     }
 
     #[test]
@@ -312,4 +754,110 @@ WantedBy=multi-user.target
         let owner = service_owner("[Service]\nUser=example\nDynamicUser=no\n").expect("owner");
         assert_eq!(owner.user, "example");
     }
+
+    #[test]
+    fn setting_a_value_replaces_the_assignment_that_counts() {
+        let unit = "[Service]\nExecStart=/usr/bin/a \\\n  --flag\nRestart=no\nRestart=on-failure\n";
+        let edited = set_value(unit, Some("Service"), "Restart", Some("always"));
+        assert_eq!(
+            edited,
+            "[Service]\nExecStart=/usr/bin/a \\\n  --flag\nRestart=no\nRestart=always\n"
+        );
+        let edited = set_value(unit, Some("Service"), "ExecStart", Some("/usr/bin/b"));
+        assert_eq!(
+            edited,
+            "[Service]\nExecStart=/usr/bin/b\nRestart=no\nRestart=on-failure\n"
+        );
+    }
+
+    #[test]
+    fn a_new_key_joins_its_section_and_a_new_section_goes_last() {
+        let ini = "; top\nname = app\n\n[server]\nport = 80\n\n[log]\nlevel = info\n";
+        assert_eq!(
+            set_value(ini, Some("server"), "host", Some("0.0.0.0")),
+            "; top\nname = app\n\n[server]\nport = 80\nhost = 0.0.0.0\n\n[log]\nlevel = info\n"
+        );
+        assert_eq!(
+            set_value(ini, None, "user", Some("app")),
+            "; top\nname = app\nuser = app\n\n[server]\nport = 80\n\n[log]\nlevel = info\n"
+        );
+        let added = set_value(ini, Some("cache"), "size", Some("10"));
+        assert!(added.ends_with("level = info\n\n[cache]\nsize = 10\n"), "{added}");
+        assert_eq!(value(&added, Some("cache"), "size").as_deref(), Some("10"));
+    }
+
+    #[test]
+    fn removing_a_key_drops_every_assignment_of_it() {
+        let ini = "[a]\nx=1\ny=2\nx=3\n[b]\nx=4\n";
+        assert_eq!(set_value(ini, Some("a"), "x", None), "[a]\ny=2\n[b]\nx=4\n");
+    }
+
+    #[test]
+    fn lint_reports_a_key_in_the_wrong_section_by_line() {
+        assert_eq!(
+            lint(UNIT, UnitKind::Service).warnings,
+            vec!["line 3: unknown key WorkingDirectory= in [Unit]".to_string()]
+        );
+    }
+
+    #[test]
+    fn lint_reports_typos_in_sections_and_keys() {
+        let unit =
+            "[Unit]\nDescripton=typo\n\n[Serivce]\nExecStart=/bin/a\n\n[Service]\nExecStart=/bin/a\nRestartSecs=5\n";
+        assert_eq!(
+            lint(unit, UnitKind::Service).warnings,
+            vec![
+                "line 2: unknown key Descripton= in [Unit]".to_string(),
+                "line 4: unknown section [Serivce]".to_string(),
+                "line 9: unknown key RestartSecs= in [Service]".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn lint_passes_extensions_conditions_and_continued_lines() {
+        let unit = "\
+[Unit]
+ConditionPathExists=/etc/app.conf
+X-Team=payments
+
+[Service]
+ExecStart=/usr/bin/app \\
+  --serve
+X-Owner=ops
+
+[X-Deploy]
+Anything=goes
+";
+        assert_eq!(lint(unit, UnitKind::Service), Lint::default());
+    }
+
+    #[test]
+    fn lint_needs_exec_start_unless_oneshot() {
+        assert_eq!(
+            lint("[Service]\nUser=app\n", UnitKind::Service).errors,
+            vec!["[Service] has no ExecStart=".to_string()]
+        );
+        assert_eq!(
+            lint("[Service]\nType=oneshot\nExecStop=/bin/cleanup\n", UnitKind::Service),
+            Lint::default()
+        );
+    }
+
+    #[test]
+    fn lint_reads_a_timer_against_timer_keys() {
+        let timer = "[Timer]\nOnCalendar=daily\nPersistent=true\n\n[Install]\nWantedBy=timers.target\n";
+        assert_eq!(lint(timer, UnitKind::Timer), Lint::default());
+        assert_eq!(
+            lint("[Service]\nExecStart=/bin/a\n", UnitKind::Timer).warnings,
+            vec!["line 1: unknown section [Service]".to_string()]
+        );
+        assert_eq!(
+            lint("stray\nKey=value\n", UnitKind::Timer).errors,
+            vec![
+                "line 1: expected key=value, got \"stray\"".to_string(),
+                "line 2: Key= is outside any section".to_string(),
+            ]
+        );
+    }
 }
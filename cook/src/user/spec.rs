@@ -18,7 +18,7 @@ impl FromKdl for UserSpec {
         let mut args = node.entries().iter();
         let name = args.next().unwrap().expect_str().to_string();
         let mut spec = UserSpec { name, is_login: false };
-        while let Some(e) = args.next() {
+        for e in args {
             match e.expect_str() {
                 "is_login" => spec.is_login = true,
                 z => panic!("Unrecognized keyword for user declaration: {}", z),
@@ -0,0 +1,498 @@
+//! `file`, `cp`, `directory` and `link` — what a node in the config turns into.
+//!
+//! The checks and applies run over SSH, so these tests stop at the rules the
+//! parser produces, read through the same serialization the agent consumes.
+
+use cook::{Context, State, add_kdl_deserializers_to_context, add_node};
+use kdl::KdlDocument;
+
+/// Parse a KDL config into a [`State`], exercising the same path the CLI uses.
+fn parse(src: &str) -> State {
+    let mut context = Context::new(".");
+    add_kdl_deserializers_to_context(&mut context);
+    let mut state = State::new();
+    let doc = KdlDocument::parse(src).expect("valid kdl");
+    for node in doc.nodes() {
+        add_node(node, &context, &mut state);
+    }
+    state
+}
+
+/// The rules of a state, serialized — the only public view of a rule's fields.
+fn serialized(state: &State) -> String {
+    let mut buf = Vec::new();
+    state.serialize(&mut buf);
+    String::from_utf8(buf).expect("serialized state is utf8")
+}
+
+#[test]
+fn directory_carries_its_mode_owner_and_recurse() {
+    let state = parse(r#"directory /srv/app mode="750" owner=app group=app-grp recurse=#true"#);
+    assert_eq!(
+        serialized(&state),
+        r#"{"path":"/srv/app","mode":488,"owner":"app","group":"app-grp","recurse":true}"#
+    );
+}
+
+#[test]
+fn a_trailing_slash_on_file_makes_a_directory() {
+    let state = parse(r#"file /srv/app/ mode="755""#);
+    assert_eq!(serialized(&state), r#"{"path":"/srv/app","mode":493}"#);
+}
+
+#[test]
+fn a_directory_unit_is_named_by_its_path_without_the_slash() {
+    let state = parse("file /srv/app/\npackage web after=/srv/app");
+    assert_eq!(state.units()[0].qualified(), "file:/srv/app");
+    let schedule = state.build_schedule().expect("valid schedule");
+    assert_eq!(schedule.deps[1].after, vec![0]);
+}
+
+#[test]
+#[should_panic(expected = "Unexpected option for directory: content")]
+fn directory_rejects_file_options() {
+    parse(r#"directory /srv/app content="nope""#);
+}
+
+#[test]
+fn link_carries_its_target() {
+    let state = parse("link /srv/app/current releases/2026-10-18 force=#true");
+    assert_eq!(
+        serialized(&state),
+        r#"{"path":"/srv/app/current","target":"releases/2026-10-18","force":true}"#
+    );
+}
+
+#[test]
+fn file_with_a_link_property_is_a_link() {
+    let state = parse("file /srv/app/current link=releases/2026-10-18");
+    assert_eq!(
+        serialized(&state),
+        r#"{"path":"/srv/app/current","target":"releases/2026-10-18"}"#
+    );
+    assert_eq!(state.units()[0].qualified(), "file:/srv/app/current");
+}
+
+#[test]
+#[should_panic(expected = "link requires a target")]
+fn link_requires_a_target() {
+    parse("link /srv/app/current");
+}
+
+#[test]
+fn inline_content_is_hashed_like_a_copied_file() {
+    // sha256 of "hello\n": `newline=#true` supplies the newline KDL strings lack.
+    let state = parse(r#"file /etc/motd content=hello newline=#true"#);
+    assert!(
+        serialized(&state).contains("5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"),
+        "got: {}",
+        serialized(&state)
+    );
+}
+
+#[test]
+fn a_content_child_takes_a_raw_multiline_string() {
+    let state = parse(
+        r##"
+file /etc/sudoers.d/env newline=#true {
+    content #"""
+        Defaults env_reset
+        # "quoted" \ backslash
+        """#
+}
+"##,
+    );
+    // sha256 of the dedented block plus its trailing newline.
+    assert!(
+        serialized(&state).contains("b43f33f97f254e5eb787b0c2e8f09e64ce069a847401828553e9b5b7626591f2"),
+        "got: {}",
+        serialized(&state)
+    );
+}
+
+#[test]
+#[should_panic(expected = "content is given more than once")]
+fn content_may_not_be_given_twice() {
+    parse("file /etc/motd content=a {\n    content b\n}");
+}
+
+#[test]
+#[should_panic(expected = "Unexpected option for cp: content")]
+fn cp_does_not_take_inline_content() {
+    parse("cp a b content=hello");
+}
+
+#[test]
+fn a_url_with_a_checksum_becomes_a_download() {
+    let sha = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+    let state = parse(&format!(
+        r#"file /usr/local/bin/x url="https://example.com/x" sha256="{sha}" fetch=controller mode="755""#
+    ));
+    assert_eq!(
+        serialized(&state),
+        format!(
+            r#"{{"path":"/usr/local/bin/x","mode":493,"content":{{"Url":{{"url":"https://example.com/x","sha256":"{sha}","fetch":"controller"}}}}}}"#
+        )
+    );
+}
+
+#[test]
+fn a_url_without_a_checksum_may_check_freshness() {
+    let state = parse(r#"file /opt/data.csv url="https://example.com/data.csv" cache=last-modified"#);
+    assert!(
+        serialized(&state).contains(r#""fetch":"host","cache":"last-modified""#),
+        "got: {}",
+        serialized(&state)
+    );
+}
+
+#[test]
+#[should_panic(expected = "give either content or url")]
+fn content_and_url_are_exclusive() {
+    parse(r#"file /opt/x content=a url="https://example.com/x""#);
+}
+
+#[test]
+#[should_panic(expected = "only apply to a url")]
+fn a_checksum_without_a_url_is_rejected() {
+    parse(&format!("file /opt/x content=a sha256=\"{}\"", "0".repeat(64)));
+}
+
+#[test]
+fn a_purged_copy_keeps_its_excludes() {
+    let state = parse("cp tests/fixtures /srv/fixtures {\n    purge #true\n    exclude \"*.sh\"\n}");
+    let json = serialized(&state);
+    assert!(json.contains(r#""purge":true,"keep":["**/*.sh"]"#), "got: {json}");
+    assert!(
+        !json.contains("install-example.sh"),
+        "excluded files are not shipped: {json}"
+    );
+}
+
+#[test]
+#[should_panic(expected = "purge only applies when copying a directory")]
+fn purge_needs_a_directory() {
+    parse("cp tests/fixtures/example.service /srv/x {\n    purge #true\n}");
+}
+
+#[test]
+fn validate_is_carried_by_a_copied_file() {
+    let state = parse(r#"cp tests/fixtures/example.service /etc/x.service validate="systemd-analyze verify %s""#);
+    assert!(
+        serialized(&state).ends_with(r#""validate":"systemd-analyze verify %s"}"#),
+        "got: {}",
+        serialized(&state)
+    );
+}
+
+#[test]
+fn every_file_of_a_copied_tree_is_validated() {
+    let state = parse(r#"cp tests/fixtures /srv/fixtures validate="sh -n %s""#);
+    let json = serialized(&state);
+    let files = json.matches(r#""path":"/srv/fixtures/"#).count();
+    assert!(files > 0, "got: {json}");
+    assert_eq!(json.matches(r#""validate":"sh -n %s""#).count(), files, "got: {json}");
+}
+
+#[test]
+#[should_panic(expected = "validate needs %s")]
+fn a_validator_must_be_given_the_new_file() {
+    parse(r#"file /etc/sudoers.d/env content=x validate="visudo -c""#);
+}
+
+#[test]
+fn labels_are_carried_by_a_file() {
+    let state = parse(
+        "file /srv/www/index.html content=hi selinux_context=httpd_sys_content_t {\n    acl { user deploy rx; }\n    xattr { user.origin cook; }\n}",
+    );
+    assert!(
+        serialized(&state).ends_with(
+            r#""labels":{"selinux_context":"httpd_sys_content_t","acl":[{"kind":"user","name":"deploy","perms":"r-x"}],"xattr":{"user.origin":"cook"}}}"#
+        ),
+        "got: {}",
+        serialized(&state)
+    );
+}
+
+#[test]
+fn every_file_of_a_copied_tree_is_labelled() {
+    let state = parse("cp tests/fixtures /srv/fixtures {\n    acl { group web r-x; }\n}");
+    let json = serialized(&state);
+    let files = json.matches(r#""path":"/srv/fixtures/"#).count();
+    assert!(files > 0, "got: {json}");
+    assert_eq!(
+        json.matches(r#""acl":[{"kind":"group","name":"web","perms":"r-x"}]"#)
+            .count(),
+        files,
+        "got: {json}"
+    );
+}
+
+#[test]
+#[should_panic(expected = "acl entries are `user <name> <perms>` or `group <name> <perms>`")]
+fn acl_entries_name_a_user_or_group() {
+    parse("file /srv/x content=hi {\n    acl { other r; }\n}");
+}
+
+#[test]
+fn backup_is_off_unless_asked_for() {
+    let state = parse("file /etc/motd content=hi");
+    assert!(!serialized(&state).contains("backup"));
+    let state = parse("file /etc/motd content=hi backup=#true");
+    assert!(
+        serialized(&state).ends_with(r#""backup":true}"#),
+        "got: {}",
+        serialized(&state)
+    );
+}
+
+/// [`parse`], with backups on for every node that does not say otherwise.
+fn parse_backing_up(src: &str) -> State {
+    let mut context = Context::new(".");
+    context.set_backup(true);
+    add_kdl_deserializers_to_context(&mut context);
+    let mut state = State::new();
+    for node in KdlDocument::parse(src).expect("valid kdl").nodes() {
+        add_node(node, &context, &mut state);
+    }
+    state
+}
+
+#[test]
+fn the_global_backup_setting_can_be_overridden_per_node() {
+    let tree = serialized(&parse_backing_up("cp tests/fixtures /srv/fixtures"));
+    assert!(tree.contains(r#""backup":true"#), "got: {tree}");
+    let motd = serialized(&parse_backing_up("file /etc/motd content=hi backup=#false"));
+    assert!(!motd.contains("backup"), "got: {motd}");
+}
+
+#[test]
+fn a_paranoid_copy_hashes_the_whole_tree() {
+    let tree = serialized(&parse("cp tests/fixtures /srv/fixtures"));
+    assert!(!tree.contains("paranoid"), "got: {tree}");
+    let mut context = Context::new(".");
+    context.set_paranoid(true);
+    add_kdl_deserializers_to_context(&mut context);
+    let mut state = State::new();
+    for node in KdlDocument::parse("cp tests/fixtures /srv/fixtures")
+        .expect("valid kdl")
+        .nodes()
+    {
+        add_node(node, &context, &mut state);
+    }
+    let tree = serialized(&state);
+    assert!(tree.contains(r#""paranoid":true"#), "got: {tree}");
+}
+
+#[test]
+fn a_tree_copy_can_set_file_and_directory_modes_apart() {
+    let json = serialized(&parse(
+        r#"cp tests/fixtures /srv/fixtures file_mode="640" dir_mode="750""#,
+    ));
+    assert!(json.contains(r#""mode":416"#), "got: {json}");
+    assert!(
+        json.contains(r#""directories":[{"path":"/srv/fixtures","mode":488}]"#),
+        "got: {json}"
+    );
+}
+
+#[test]
+fn preserve_mode_reads_modes_from_the_local_tree() {
+    use std::os::unix::fs::PermissionsExt;
+    let root = std::env::temp_dir().join(format!("cook-preserve-mode-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("tree/bin")).unwrap();
+    std::fs::write(root.join("tree/bin/run"), "#!/bin/sh\n").unwrap();
+    std::fs::set_permissions(root.join("tree/bin/run"), std::fs::Permissions::from_mode(0o755)).unwrap();
+    std::fs::set_permissions(root.join("tree/bin"), std::fs::Permissions::from_mode(0o711)).unwrap();
+    std::fs::set_permissions(root.join("tree"), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let mut context = Context::new(&root);
+    add_kdl_deserializers_to_context(&mut context);
+    let mut state = State::new();
+    let doc = KdlDocument::parse("cp tree /srv/app preserve_mode=#true").unwrap();
+    add_node(&doc.nodes()[0], &context, &mut state);
+    let json = serialized(&state);
+    std::fs::remove_dir_all(&root).unwrap();
+
+    assert!(json.contains(r#""path":"/srv/app/bin/run","mode":493"#), "got: {json}");
+    assert!(
+        json.contains(r#""directories":[{"path":"/srv/app","mode":493},{"path":"/srv/app/bin","mode":457}]"#),
+        "got: {json}"
+    );
+}
+
+#[test]
+#[should_panic(expected = "dir_mode only applies when copying a directory")]
+fn dir_mode_needs_a_directory() {
+    parse(r#"cp tests/fixtures/example.service /srv/x dir_mode="755""#);
+}
+
+#[test]
+#[should_panic(expected = "mode and file_mode mean the same thing")]
+fn mode_and_file_mode_are_exclusive() {
+    parse(r#"cp tests/fixtures /srv/x mode="644" file_mode="600""#);
+}
+
+/// A source tree under a fresh temporary root, built from `(path, content)`
+/// pairs, with a [`Context`] rooted there.
+fn tree(name: &str, files: &[(&str, &str)]) -> (std::path::PathBuf, Context) {
+    let root = std::env::temp_dir().join(format!("cook-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    for (path, content) in files {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+    let mut context = Context::new(&root);
+    add_kdl_deserializers_to_context(&mut context);
+    (root, context)
+}
+
+/// The destination paths of the files the `cp` in `node` would ship.
+fn shipped(context: &Context, node: &str) -> Vec<String> {
+    let mut state = State::new();
+    let doc = KdlDocument::parse(node).unwrap();
+    add_node(&doc.nodes()[0], context, &mut state);
+    let json: serde_json::Value = serde_json::from_str(&serialized(&state)).unwrap();
+    json["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| f["path"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn cp_honors_ignore_files_in_the_source_tree() {
+    let (root, context) = tree(
+        "ignore-files",
+        &[
+            ("site/.gitignore", "target/\n*.swp\n!keep.swp\n/build\n"),
+            ("site/.cookignore", "tests/\n"),
+            ("site/index.html", ""),
+            ("site/keep.swp", ""),
+            ("site/index.html.swp", ""),
+            ("site/target/debug/app", ""),
+            ("site/build/out.js", ""),
+            ("site/assets/build/logo.svg", ""),
+            ("site/tests/smoke.sh", ""),
+            ("site/docs/.gitignore", "draft.md\n!*.swp\n"),
+            ("site/docs/draft.md", ""),
+            ("site/docs/notes.swp", ""),
+            ("site/.git/HEAD", ""),
+        ],
+    );
+    let files = shipped(&context, "cp site /srv/site");
+    std::fs::remove_dir_all(&root).unwrap();
+    assert_eq!(
+        files,
+        vec![
+            "/srv/site/.gitignore",
+            // Anchored: only the top-level `build` is ignored.
+            "/srv/site/assets/build/logo.svg",
+            "/srv/site/docs/.gitignore",
+            // The nested file re-includes what its parent ignored.
+            "/srv/site/docs/notes.swp",
+            "/srv/site/index.html",
+            "/srv/site/keep.swp",
+        ]
+    );
+}
+
+#[test]
+fn ignore_files_can_be_turned_off() {
+    let (root, context) = tree(
+        "no-ignore-files",
+        &[
+            ("site/.gitignore", "*.log\n"),
+            ("site/app.log", ""),
+            ("site/index.html", ""),
+        ],
+    );
+    let files = shipped(&context, "cp site /srv/site ignore_files=#false");
+    std::fs::remove_dir_all(&root).unwrap();
+    assert_eq!(
+        files,
+        vec!["/srv/site/.gitignore", "/srv/site/app.log", "/srv/site/index.html"]
+    );
+}
+
+#[test]
+fn lines_for_one_file_are_one_rule() {
+    let state = parse(
+        r#"
+        line /etc/ssh/sshd_config "PasswordAuthentication no" match="^#?PasswordAuthentication\b"
+        line /etc/ssh/sshd_config "PermitRootLogin no" match="^#?PermitRootLogin\b" validate="sshd -t -f %s"
+        "#,
+    );
+    assert_eq!(state.rules().len(), 1);
+    assert_eq!(state.units().len(), 1);
+    let rule = serialized(&state);
+    assert!(rule.contains(r#""line":"PasswordAuthentication no""#), "got: {rule}");
+    assert!(rule.contains(r#""line":"PermitRootLogin no""#), "got: {rule}");
+    assert!(rule.contains(r#""validate":"sshd -t -f %s""#), "got: {rule}");
+}
+
+#[test]
+fn a_block_takes_its_content_as_a_child() {
+    let rule = serialized(&parse(
+        r##"
+block /etc/hosts cluster {
+    content #"""
+        10.0.0.5 db
+        10.0.0.6 cache
+        """#
+}
+"##,
+    ));
+    assert!(rule.contains(r#""name":"cluster""#), "got: {rule}");
+    assert!(
+        rule.contains(r#""content":"10.0.0.5 db\n10.0.0.6 cache""#),
+        "got: {rule}"
+    );
+}
+
+#[test]
+#[should_panic(expected = "give the line, or match= to remove lines by pattern")]
+fn a_present_line_needs_its_text() {
+    parse(r#"line /etc/environment match="^DEBUG=""#);
+}
+
+#[test]
+#[should_panic(expected = "not a valid regex")]
+fn a_line_pattern_must_be_a_regex() {
+    parse(r#"line /etc/environment "A=1" match="(""#);
+}
+
+#[test]
+fn settings_for_one_file_are_one_rule() {
+    let state = parse(
+        r#"
+        setting /etc/app/config.json path="server.port" value=8080
+        setting /etc/app/config.json path="debug" state=absent
+        "#,
+    );
+    assert_eq!(state.rules().len(), 1);
+    let rule = serialized(&state);
+    assert!(rule.contains(r#""format":"json""#), "got: {rule}");
+    assert!(rule.contains(r#"{"path":"server.port","value":8080}"#), "got: {rule}");
+    assert!(rule.contains(r#"{"path":"debug"}"#), "got: {rule}");
+}
+
+#[test]
+#[should_panic(expected = "give format=json, yaml, toml or ini")]
+fn a_settings_format_is_needed_when_the_extension_does_not_say() {
+    parse(r#"setting /etc/app/app.conf path="a" value=1"#);
+}
+
+#[test]
+#[should_panic(expected = "read as Json by an earlier setting")]
+fn one_file_is_read_in_one_format() {
+    parse(
+        r#"
+        setting /etc/app/config path="a" value=1 format=json
+        setting /etc/app/config path="b" value=1 format=yaml
+        "#,
+    );
+}
@@ -247,3 +247,118 @@ fn a_user_the_config_does_not_declare_is_not_a_dependency() {
 fn an_explicit_name_may_not_contain_a_colon() {
     parse("package a {\n  name we:b\n}");
 }
+
+#[test]
+fn notify_attaches_to_the_unit() {
+    let state = parse("package alpha notify=caddy\npackage beta {\n  notify service:nginx action=reload\n}");
+    let notified = |name| {
+        state.units()[unit_index(&state, name)]
+            .notify
+            .iter()
+            .map(|n| (n.service.as_str(), n.action))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(notified("alpha"), vec![("caddy", cook::NotifyAction::Restart)]);
+    assert_eq!(notified("beta"), vec![("nginx", cook::NotifyAction::Reload)]);
+}
+
+#[test]
+#[should_panic(expected = "only services can be notified")]
+fn only_services_can_be_notified() {
+    parse("package alpha {\n  notify package:beta\n}");
+}
+
+#[test]
+#[should_panic(expected = "action must be restart or reload")]
+fn a_notify_action_is_restart_or_reload() {
+    parse("package alpha {\n  notify caddy action=stop\n}");
+}
+
+#[test]
+fn flush_runs_between_the_units_around_it() {
+    let state = parse("package alpha\npackage beta\nflush\npackage gamma");
+    let flush = qualified_index(&state, "flush:1");
+    assert!(state.units()[flush].is_flush());
+    let schedule = state.build_schedule().expect("valid schedule");
+    let (alpha, beta, gamma) = (
+        unit_index(&state, "alpha"),
+        unit_index(&state, "beta"),
+        unit_index(&state, "gamma"),
+    );
+    assert_eq!(schedule.deps[flush].after, vec![alpha, beta]);
+    assert_eq!(schedule.deps[gamma].after, vec![flush]);
+}
+
+#[test]
+fn a_second_flush_runs_after_the_first() {
+    let state = parse("package alpha\nflush\npackage beta\nflush");
+    let first = qualified_index(&state, "flush:1");
+    let second = qualified_index(&state, "flush:2");
+    let beta = unit_index(&state, "beta");
+    let schedule = state.build_schedule().expect("valid schedule");
+    assert_eq!(schedule.deps[second].after, vec![first, beta]);
+}
+
+#[test]
+#[should_panic(expected = "flush takes no arguments")]
+fn flush_takes_no_arguments() {
+    parse("flush caddy");
+}
+
+#[test]
+fn a_line_merged_into_an_earlier_edit_keeps_its_ordering() {
+    let state = parse(
+        r#"
+        package openssh-server
+        line /etc/ssh/sshd_config "PermitRootLogin no" match="^#?PermitRootLogin\b"
+        line /etc/ssh/sshd_config "PasswordAuthentication no" match="^#?PasswordAuthentication\b" after=openssh-server notify=sshd
+        "#,
+    );
+    assert_eq!(state.units().len(), 2, "the second line joins the first one's unit");
+    let edit = unit_index(&state, "/etc/ssh/sshd_config");
+    let package = unit_index(&state, "openssh-server");
+    let schedule = state.build_schedule().unwrap();
+    assert_eq!(schedule.deps[edit].after, vec![package]);
+    assert!(ordered_before(&schedule.topo_order, package, edit));
+    assert_eq!(state.units()[edit].notify.len(), 1);
+}
+
+#[test]
+fn a_merged_setting_can_name_the_unit() {
+    let state = parse(
+        r#"
+        setting /etc/app/config.json path="server.port" value=8080
+        setting /etc/app/config.json path="debug" value=#false name=app-config
+        package app after=app-config
+        "#,
+    );
+    let schedule = state.build_schedule().unwrap();
+    let config = unit_index(&state, "app-config");
+    assert!(ordered_before(&schedule.topo_order, config, unit_index(&state, "app")));
+}
+
+#[test]
+#[should_panic(expected = "is named both first and second")]
+fn merged_nodes_may_not_name_the_unit_twice() {
+    parse(
+        r#"
+        line /etc/hosts "127.0.0.1 a" name=first
+        line /etc/hosts "127.0.0.1 b" name=second
+        "#,
+    );
+}
+
+#[test]
+fn notify_on_a_merged_setting_reaches_the_unit() {
+    let state = parse(
+        r#"
+        setting /etc/app/config.json path="server.port" value=8080
+        setting /etc/app/config.json path="debug" value=#false notify=app
+        "#,
+    );
+    let unit = &state.units()[unit_index(&state, "/etc/app/config.json")];
+    assert_eq!(
+        unit.notify.iter().map(|n| n.service.as_str()).collect::<Vec<_>>(),
+        vec!["app"]
+    );
+}
@@ -41,13 +41,31 @@ fn working_directory_and_its_owner_are_read_out_of_the_unit_file() {
 
 #[test]
 fn a_unit_without_a_working_directory_carries_none() {
-    let state = parse(r#"service install-example "tests/fixtures/install-example.sh""#);
+    let state = parse(r#"service example "tests/fixtures/minimal.service""#);
     assert!(
         !serialized(&state).contains("working_directory"),
         "a file with no WorkingDirectory should not produce one"
     );
 }
 
+#[test]
+fn specifier_paths_are_kept_for_the_host_to_resolve() {
+    // `%S` is the host's state directory, which cook reads off the host at
+    // check time; the rule keeps the path as the unit wrote it.
+    let state = parse(r#"service example "tests/fixtures/state-directory.service""#);
+    let json = serialized(&state);
+    assert!(
+        json.contains(r#""working_directory":{"path":"%S/example","owner":{"user":"example"}}"#),
+        "got: {json}"
+    );
+    assert!(
+        json.contains(
+            r#""state_directories":[{"path":"%S/example","owner":{"user":"example"}},{"path":"%S/example/cache","owner":{"user":"example"}}]"#
+        ),
+        "got: {json}"
+    );
+}
+
 #[test]
 fn an_optional_working_directory_is_not_created() {
     // `WorkingDirectory=-/srv/optional` starts the unit even when the directory
@@ -58,3 +76,136 @@ fn an_optional_working_directory_is_not_created() {
         "a `-` prefixed WorkingDirectory should not become a rule"
     );
 }
+
+#[test]
+fn a_service_is_kept_running_and_enabled_by_default() {
+    let state = parse(r#"service example "tests/fixtures/example.service""#);
+    let json = serialized(&state);
+    assert!(json.contains(r#""state":"running","enabled":true"#), "got: {json}");
+}
+
+#[test]
+fn start_false_leaves_the_runtime_state_alone() {
+    let state = parse(r#"service example "tests/fixtures/example.service" start=#false"#);
+    let json = serialized(&state);
+    assert!(json.contains(r#""state":null,"enabled":true"#), "got: {json}");
+}
+
+#[test]
+fn a_service_can_be_kept_stopped_and_disabled() {
+    let state = parse(r#"service example "tests/fixtures/example.service" state=stopped enabled=#false"#);
+    let json = serialized(&state);
+    assert!(json.contains(r#""state":"stopped","enabled":false"#), "got: {json}");
+}
+
+#[test]
+#[should_panic(expected = "give state= or start=, not both")]
+fn state_and_start_are_exclusive() {
+    parse(r#"service example "tests/fixtures/example.service" start=#true state=running"#);
+}
+
+#[test]
+#[should_panic(expected = "state must be running, stopped or restarted")]
+fn an_unknown_state_is_rejected() {
+    parse(r#"service example "tests/fixtures/example.service" state=up"#);
+}
+
+#[test]
+fn a_packaged_unit_takes_dropins_without_a_unit_file() {
+    let state = parse(
+        "service postgresql {\n    dropin limits content=\"[Service]\\nLimitNOFILE=65536\"\n    environment PGTZ=UTC\n}",
+    );
+    let json = serialized(&state);
+    assert!(json.contains(r#""service_file_content":null"#), "got: {json}");
+    assert!(
+        json.contains(
+            r##""dropins":[{"file":"limits.conf","content":"# Managed by cook\n[Service]\nLimitNOFILE=65536\n"},{"file":"cook-environment.conf","content":"# Managed by cook\n[Service]\nEnvironment=\"PGTZ=UTC\"\n"}]"##
+        ),
+        "got: {json}"
+    );
+}
+
+#[test]
+fn an_init_script_rides_along_with_the_unit() {
+    let state = parse(r#"service example "tests/fixtures/minimal.service" script="tests/fixtures/example.openrc""#);
+    let json = serialized(&state);
+    assert!(
+        json.contains(r##""init_script":"#!/sbin/openrc-run\ncommand=/usr/bin/example\n"##),
+        "got: {json}"
+    );
+}
+
+#[test]
+fn environment_values_that_are_not_strings_are_written_bare() {
+    let state = parse("service postgresql {\n    environment DEBUG=#true PORT=8080 RATIO=0.5\n}");
+    let json = serialized(&state);
+    assert!(
+        json.contains(r#"Environment=\"DEBUG=true\"\nEnvironment=\"PORT=8080\"\nEnvironment=\"RATIO=0.5\"\n"#),
+        "got: {json}"
+    );
+}
+
+#[test]
+#[should_panic(expected = "environment DEBUG is #null")]
+fn a_null_environment_value_is_rejected() {
+    parse("service postgresql {\n    environment DEBUG=#null\n}");
+}
+
+#[test]
+#[should_panic(expected = "dropin limits.conf is given more than once")]
+fn a_dropin_may_not_be_given_twice() {
+    parse("service postgresql {\n    dropin limits content=a\n    dropin limits.conf content=b\n}");
+}
+
+#[test]
+#[should_panic(expected = "Unexpected directive for service: override")]
+fn other_children_are_rejected() {
+    parse("service postgresql {\n    override x\n}");
+}
+
+#[test]
+fn a_unit_generated_from_properties_is_read_like_a_shipped_one() {
+    let state = parse(
+        r#"service api exec="/opt/api/bin/api" user=api group=api working_directory=/srv/api restart=always environment_file=/etc/api.env no_new_privileges=#true protect_system=strict"#,
+    );
+    let json = serialized(&state);
+    assert!(json.contains(r#"ExecStart=/opt/api/bin/api\n"#), "got: {json}");
+    assert!(json.contains(r#"ProtectSystem=strict\n"#), "got: {json}");
+    assert!(
+        json.contains(r#""working_directory":{"path":"/srv/api","owner":{"user":"api","group":"api"}}"#),
+        "got: {json}"
+    );
+    assert_eq!(state.rules()[0].implied_after(), vec!["user:api".to_string()]);
+}
+
+#[test]
+#[should_panic(expected = "give a unit file or unit properties like exec=, not both")]
+fn a_unit_file_and_properties_are_exclusive() {
+    parse(r#"service example "tests/fixtures/example.service" exec="/bin/true""#);
+}
+
+#[test]
+#[should_panic(expected = "generating a unit needs exec=")]
+fn generating_a_unit_needs_exec() {
+    parse("service api user=api");
+}
+
+#[test]
+#[should_panic(expected = "restart must be one of")]
+fn restart_takes_a_systemd_value() {
+    parse(r#"service api exec="/bin/true" restart=sometimes"#);
+}
+
+/// An unknown key may be a directive newer than cook's list, so it only
+/// warns; the host's `systemd-analyze verify` has the last word.
+#[test]
+fn an_unknown_key_does_not_fail_the_cookfile() {
+    let state = parse(r#"service example "tests/fixtures/typo.service""#);
+    assert!(serialized(&state).contains("Restrat=always"));
+}
+
+#[test]
+#[should_panic(expected = "[Service] has no ExecStart=")]
+fn a_unit_file_that_runs_nothing_fails_at_parse_time() {
+    parse(r#"service install-example "tests/fixtures/install-example.sh""#);
+}